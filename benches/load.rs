@@ -0,0 +1,96 @@
+//! Benchmarks for loading large Evergreen project configurations.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use shrub_rs::models::project::EvgProject;
+use std::fmt::Write;
+use yaml_merge_keys::merge_keys;
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+const N_TASKS: usize = 2_000;
+const N_VARIANTS: usize = 40;
+const N_FUNCTIONS: usize = 50;
+
+/// Build a project configuration about the size of large real-world projects.
+fn synthetic_project() -> String {
+    let mut config = String::new();
+    config.push_str("variables:\n");
+    config.push_str("  - &shell_defaults\n");
+    config.push_str("    working_dir: src\n");
+    config.push_str("    shell: bash\n");
+
+    config.push_str("functions:\n");
+    for i in 0..N_FUNCTIONS {
+        writeln!(config, "  func_{}:", i).unwrap();
+        config.push_str("    - command: shell.exec\n");
+        config.push_str("      params:\n");
+        config.push_str("        <<: *shell_defaults\n");
+        writeln!(config, "        script: echo ${{expansion_{}}}", i).unwrap();
+        config.push_str("    - command: expansions.update\n");
+        config.push_str("      params:\n");
+        config.push_str("        file: expansions.yml\n");
+    }
+
+    config.push_str("tasks:\n");
+    for i in 0..N_TASKS {
+        writeln!(config, "  - name: task_{}", i).unwrap();
+        config.push_str("    tags: [\"tag_a\", \"tag_b\"]\n");
+        if i > 0 {
+            config.push_str("    depends_on:\n");
+            writeln!(config, "      - name: task_{}", i - 1).unwrap();
+        }
+        config.push_str("    commands:\n");
+        writeln!(config, "      - func: func_{}", i % N_FUNCTIONS).unwrap();
+        config.push_str("        vars:\n");
+        writeln!(config, "          suite: suite_{}", i).unwrap();
+        config.push_str("          resmoke_jobs_max: 4\n");
+        config.push_str("      - command: shell.exec\n");
+        config.push_str("        params:\n");
+        config.push_str("          <<: *shell_defaults\n");
+        config.push_str("          script: make test\n");
+    }
+
+    config.push_str("buildvariants:\n");
+    for i in 0..N_VARIANTS {
+        writeln!(config, "  - name: variant_{}", i).unwrap();
+        writeln!(config, "    display_name: Variant {}", i).unwrap();
+        config.push_str("    run_on:\n");
+        config.push_str("      - ubuntu2004-small\n");
+        config.push_str("    expansions:\n");
+        writeln!(config, "      variant_index: \"{}\"", i).unwrap();
+        config.push_str("    tasks:\n");
+        for t in (0..N_TASKS).step_by(4) {
+            writeln!(config, "      - name: task_{}", t).unwrap();
+        }
+    }
+
+    config
+}
+
+/// Load a project by emitting the merged document and parsing it again with serde_yaml.
+///
+/// This is how `EvgProject::from_yaml_str` used to work and is kept as a baseline.
+fn emit_and_reparse(yaml_contents: &str) -> EvgProject {
+    let mut raw = YamlLoader::load_from_str(yaml_contents).unwrap();
+    let merged = merge_keys(raw.remove(0)).unwrap();
+    let mut out_str = String::new();
+    {
+        let mut emitter = YamlEmitter::new(&mut out_str);
+        emitter.dump(&merged).unwrap();
+    }
+    serde_yaml::from_str(&out_str).unwrap()
+}
+
+fn bench_load(c: &mut Criterion) {
+    let config = synthetic_project();
+    let mut group = c.benchmark_group("load_large_project");
+    group.sample_size(20);
+    group.bench_function("from_yaml_str", |b| {
+        b.iter(|| EvgProject::from_yaml_str(black_box(&config)).unwrap())
+    });
+    group.bench_function("emit_and_reparse", |b| {
+        b.iter(|| emit_and_reparse(black_box(&config)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_load);
+criterion_main!(benches);
@@ -0,0 +1,433 @@
+//! Create and extract archives the way the `archive.*` commands do.
+//!
+//! [`pack_targz`] creates a gzipped tarball of the files under a directory that match a set of
+//! include globs, like `archive.targz_pack`. Entries are named by their path relative to the
+//! directory, so the archive can be extracted by Evergreen's agent or `tar` as well as by
+//! [`extract_targz`] and [`extract`].
+//!
+//! Globs are matched against paths relative to the source directory or the root of the archive,
+//! with a leading `./` ignored. `*` matches any characters including `/`, so `*.txt` matches text
+//! files in any directory, and `**` matches any number of directories.
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors that can occur while creating or extracting an archive.
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    /// A glob could not be parsed.
+    #[error("Invalid glob '{pattern}': {reason}")]
+    InvalidPattern {
+        /// The glob that could not be parsed.
+        pattern: String,
+        /// Why the glob is invalid.
+        reason: String,
+    },
+
+    /// A file could not be read or written.
+    #[error("Could not access '{}': {source}", .path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Error accessing the file.
+        source: io::Error,
+    },
+
+    /// The archive is not in a format that can be extracted.
+    #[error("Could not detect the archive format of '{}'", .0.display())]
+    UnknownFormat(PathBuf),
+
+    /// The archive could not be read.
+    #[error("Could not read archive '{}': {reason}", .path.display())]
+    Corrupt {
+        /// Path of the archive.
+        path: PathBuf,
+        /// Why the archive could not be read.
+        reason: String,
+    },
+}
+
+/// A kind of archive that can be extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A gzipped tarball.
+    TarGz,
+    /// An uncompressed tarball.
+    Tar,
+    /// A zip file.
+    Zip,
+}
+
+impl Display for ArchiveFormat {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::Zip => "zip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ArchiveFormat {
+    /// Detect the format of an archive from its contents, falling back to its extension.
+    ///
+    /// * `path`: Path to the archive.
+    pub fn detect(path: &Path) -> Result<Self, ArchiveError> {
+        let mut header = vec![];
+        File::open(path)
+            .and_then(|file| file.take(512).read_to_end(&mut header))
+            .map_err(|source| io_error(path, source))?;
+
+        if header.starts_with(&[0x1f, 0x8b]) {
+            return Ok(ArchiveFormat::TarGz);
+        }
+        if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            return Ok(ArchiveFormat::Zip);
+        }
+        if header.get(257..262) == Some(&b"ustar"[..]) {
+            return Ok(ArchiveFormat::Tar);
+        }
+
+        let name = path.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(ArchiveError::UnknownFormat(path.to_path_buf()))
+        }
+    }
+}
+
+/// Create a gzipped tarball of the files under a directory that match any of the include globs
+/// and none of the exclude globs.
+///
+/// Returns the names of the files added to the archive, in the order they were added.
+///
+/// * `source_dir`: Directory to archive files from.
+/// * `target`: Path of the archive to create.
+/// * `include`: Globs of files to include.
+/// * `exclude`: Globs of files to leave out.
+pub fn pack_targz(
+    source_dir: &Path,
+    target: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<String>, ArchiveError> {
+    let files: Vec<(PathBuf, String)> = find_files(source_dir, include, exclude)?
+        .into_iter()
+        .filter(|(path, _)| path != target)
+        .collect();
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).map_err(|source| io_error(parent, source))?;
+    }
+    let archive = File::create(target).map_err(|source| io_error(target, source))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(archive, Compression::default()));
+    builder.follow_symlinks(false);
+    for (path, name) in &files {
+        builder
+            .append_path_with_name(path, name)
+            .map_err(|source| io_error(path, source))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(|source| io_error(target, source))?;
+
+    Ok(files.into_iter().map(|(_, name)| name).collect())
+}
+
+/// Extract a gzipped tarball, skipping entries that match any of the exclude globs.
+///
+/// * `archive`: Path to the archive.
+/// * `destination`: Directory to extract files to, created if needed.
+/// * `exclude`: Globs of entries to skip.
+pub fn extract_targz(
+    archive: &Path,
+    destination: &Path,
+    exclude: &[String],
+) -> Result<(), ArchiveError> {
+    extract_as(ArchiveFormat::TarGz, archive, destination, exclude)
+}
+
+/// Extract an archive of any supported format, like `archive.auto_extract`.
+///
+/// Returns the format the archive was detected as.
+///
+/// * `archive`: Path to the archive.
+/// * `destination`: Directory to extract files to, created if needed.
+/// * `exclude`: Globs of entries to skip.
+pub fn extract(
+    archive: &Path,
+    destination: &Path,
+    exclude: &[String],
+) -> Result<ArchiveFormat, ArchiveError> {
+    let format = ArchiveFormat::detect(archive)?;
+    extract_as(format, archive, destination, exclude)?;
+    Ok(format)
+}
+
+fn extract_as(
+    format: ArchiveFormat,
+    archive: &Path,
+    destination: &Path,
+    exclude: &[String],
+) -> Result<(), ArchiveError> {
+    let exclude = glob_set(exclude)?;
+    fs::create_dir_all(destination).map_err(|source| io_error(destination, source))?;
+    let file = File::open(archive).map_err(|source| io_error(archive, source))?;
+    let result = match format {
+        ArchiveFormat::TarGz => unpack_tar(GzDecoder::new(file), destination, &exclude),
+        ArchiveFormat::Tar => unpack_tar(file, destination, &exclude),
+        ArchiveFormat::Zip => unpack_zip(file, destination, &exclude),
+    };
+    result.map_err(|reason| ArchiveError::Corrupt {
+        path: archive.to_path_buf(),
+        reason,
+    })
+}
+
+fn unpack_tar<R: Read>(reader: R, destination: &Path, exclude: &GlobSet) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|err| err.to_string())? {
+        let mut entry = entry.map_err(|err| err.to_string())?;
+        let name = entry
+            .path()
+            .map_err(|err| err.to_string())?
+            .to_string_lossy()
+            .to_string();
+        if exclude.is_match(normalize(&name)) {
+            continue;
+        }
+        // `unpack_in` refuses to write outside of the destination.
+        entry
+            .unpack_in(destination)
+            .map_err(|err| format!("could not extract '{}': {}", name, err))?;
+    }
+    Ok(())
+}
+
+fn unpack_zip(file: File, destination: &Path, exclude: &GlobSet) -> Result<(), String> {
+    let mut archive = zip::ZipArchive::new(file).map_err(|err| err.to_string())?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|err| err.to_string())?;
+        let name = match entry.enclosed_name() {
+            Some(name) => name.to_path_buf(),
+            None => return Err(format!("'{}' is outside of the archive", entry.name())),
+        };
+        if exclude.is_match(normalize(&name.to_string_lossy())) {
+            continue;
+        }
+
+        let path = destination.join(&name);
+        let written = if entry.is_dir() {
+            fs::create_dir_all(&path)
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| File::create(&path))
+                .and_then(|mut out| io::copy(&mut entry, &mut out))
+                .and_then(|_| set_mode(&path, entry.unix_mode()))
+        };
+        written.map_err(|err| format!("could not extract '{}': {}", name.display(), err))?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+/// Find the files under a directory that match any of the include globs and none of the exclude
+/// globs.
+///
+/// Globs are matched against the paths of files relative to `dir` and `*` can match across
+/// directories. Files are returned with their relative paths, sorted by name.
+pub(crate) fn find_files(
+    dir: &Path,
+    include: &[String],
+    exclude: &[String],
+) -> Result<Vec<(PathBuf, String)>, ArchiveError> {
+    let include = glob_set(include)?;
+    let exclude = glob_set(exclude)?;
+    let mut files = vec![];
+    collect_files(dir, "", &mut files)?;
+    Ok(files
+        .into_iter()
+        .filter(|(_, name)| include.is_match(name) && !exclude.is_match(name))
+        .collect())
+}
+
+/// Collect the files under a directory with their paths relative to it, sorted by name.
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<(), ArchiveError> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|source| io_error(dir, source))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let file_type = entry
+            .file_type()
+            .map_err(|source| io_error(&path, source))?;
+        if file_type.is_dir() {
+            collect_files(&path, &format!("{}/", name), files)?;
+        } else {
+            files.push((path, name));
+        }
+    }
+    Ok(())
+}
+
+/// Build a matcher for the given globs.
+fn glob_set(patterns: &[String]) -> Result<GlobSet, ArchiveError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(normalize(pattern)).map_err(|err| ArchiveError::InvalidPattern {
+            pattern: pattern.to_string(),
+            reason: err.kind().to_string(),
+        })?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|err| ArchiveError::InvalidPattern {
+        pattern: patterns.join(", "),
+        reason: err.to_string(),
+    })
+}
+
+/// Remove any leading `./` from a path or glob.
+fn normalize(path: &str) -> &str {
+    let mut path = path;
+    while let Some(rest) = path.strip_prefix("./") {
+        path = rest;
+    }
+    path
+}
+
+fn io_error(path: &Path, source: io::Error) -> ArchiveError {
+    ArchiveError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pack_and_extract_targz() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("src");
+        write(&source.join("main.o"), "main");
+        write(&source.join("lib/util.o"), "util");
+        write(&source.join("lib/notes.txt"), "notes");
+        write(&source.join("bin/tool"), "tool");
+        write(&source.join("other.c"), "other");
+        let target = dir.path().join("out/packed.tgz");
+
+        let packed = pack_targz(
+            &source,
+            &target,
+            &strings(&["./**.o", "./bin/**", "lib/*"]),
+            &strings(&["*.txt"]),
+        )
+        .unwrap();
+
+        assert_eq!(packed, vec!["bin/tool", "lib/util.o", "main.o"]);
+        let extracted = dir.path().join("extracted");
+        extract_targz(&target, &extracted, &strings(&["bin/*"])).unwrap();
+        assert_eq!(
+            fs::read_to_string(extracted.join("lib/util.o")).unwrap(),
+            "util"
+        );
+        assert!(extracted.join("main.o").is_file());
+        assert!(!extracted.join("bin/tool").exists());
+        assert!(!extracted.join("lib/notes.txt").exists());
+    }
+
+    #[test]
+    fn test_detect_and_extract_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir.path().join("src/a.txt"), "a");
+
+        let tarball = dir.path().join("archive.bin");
+        let mut builder = tar::Builder::new(File::create(&tarball).unwrap());
+        builder
+            .append_path_with_name(dir.path().join("src/a.txt"), "nested/a.txt")
+            .unwrap();
+        builder.finish().unwrap();
+
+        let zip_file = dir.path().join("archive.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&zip_file).unwrap());
+        writer
+            .start_file("nested/b.txt", zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(b"b").unwrap();
+        writer.finish().unwrap();
+
+        let unknown = dir.path().join("archive.rar");
+        write(&unknown, "not an archive");
+
+        let out = dir.path().join("out");
+        assert_eq!(extract(&tarball, &out, &[]).unwrap(), ArchiveFormat::Tar);
+        assert_eq!(extract(&zip_file, &out, &[]).unwrap(), ArchiveFormat::Zip);
+        assert_eq!(fs::read_to_string(out.join("nested/a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(out.join("nested/b.txt")).unwrap(), "b");
+        assert!(matches!(
+            extract(&unknown, &out, &[]),
+            Err(ArchiveError::UnknownFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let result = pack_targz(
+            dir.path(),
+            &dir.path().join("a.tgz"),
+            &strings(&["[a"]),
+            &[],
+        );
+
+        assert!(matches!(
+            result,
+            Err(ArchiveError::InvalidPattern { pattern, .. }) if pattern == "[a"
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+//! Store artifacts that tasks upload to and download from S3.
+//!
+//! Tasks often pass files to each other through S3 with `s3.put`, `s3.get` and `s3Copy.copy`.
+//! An [`ArtifactStore`] stands in for S3 when running tasks locally, with
+//! [`LocalArtifactStore`] keeping artifacts in a local directory so they can be shared between
+//! tasks without any AWS access.
+use crate::models::params::S3Location;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Somewhere to keep artifacts, addressed by bucket and path like S3.
+pub trait ArtifactStore: Send + Sync {
+    /// Store the given local file at the given location.
+    ///
+    /// * `location`: Location to store the file at.
+    /// * `file`: Local file to store.
+    fn put(&self, location: &S3Location, file: &Path) -> io::Result<()>;
+
+    /// Save the artifact at the given location to a local file.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if there is no artifact at the location.
+    ///
+    /// * `location`: Location of the artifact.
+    /// * `file`: Local file to save the artifact to.
+    fn get(&self, location: &S3Location, file: &Path) -> io::Result<()>;
+
+    /// Copy an artifact from one location to another.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if there is no artifact at the source.
+    ///
+    /// * `source`: Location of the artifact to copy.
+    /// * `destination`: Location to copy the artifact to.
+    fn copy(&self, source: &S3Location, destination: &S3Location) -> io::Result<()>;
+}
+
+/// Keep artifacts in a local directory, at `<root>/<bucket>/<path>`.
+#[derive(Debug, Clone)]
+pub struct LocalArtifactStore {
+    root: PathBuf,
+}
+
+impl LocalArtifactStore {
+    /// Create a store that keeps artifacts under the given directory.
+    pub fn new(root: &Path) -> Self {
+        LocalArtifactStore {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// Get the local path an artifact is kept at.
+    ///
+    /// Fails if the bucket or path would point outside of the store.
+    pub fn path_of(&self, location: &S3Location) -> io::Result<PathBuf> {
+        let mut path = self.root.clone();
+        for part in [&location.bucket, &location.path] {
+            for component in Path::new(part.trim_start_matches('/')).components() {
+                match component {
+                    Component::Normal(name) => path.push(name),
+                    Component::CurDir => (),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "invalid artifact location '{}/{}'",
+                                location.bucket, location.path
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl ArtifactStore for LocalArtifactStore {
+    fn put(&self, location: &S3Location, file: &Path) -> io::Result<()> {
+        copy_file(file, &self.path_of(location)?)
+    }
+
+    fn get(&self, location: &S3Location, file: &Path) -> io::Result<()> {
+        copy_file(&self.path_of(location)?, file)
+    }
+
+    fn copy(&self, source: &S3Location, destination: &S3Location) -> io::Result<()> {
+        copy_file(&self.path_of(source)?, &self.path_of(destination)?)
+    }
+}
+
+/// Copy a file, creating the directory of the destination if needed.
+fn copy_file(source: &Path, destination: &Path) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(source, destination).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn location(bucket: &str, path: &str) -> S3Location {
+        S3Location {
+            bucket: bucket.to_string(),
+            path: path.to_string(),
+            extra: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_local_artifact_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalArtifactStore::new(&dir.path().join("store"));
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "contents").unwrap();
+
+        store.put(&location("bucket", "a/file.txt"), &file).unwrap();
+        store
+            .copy(
+                &location("bucket", "a/file.txt"),
+                &location("other", "/b/copy.txt"),
+            )
+            .unwrap();
+        store
+            .get(
+                &location("other", "b/copy.txt"),
+                &dir.path().join("out/copy.txt"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("store/bucket/a/file.txt")).unwrap(),
+            "contents"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out/copy.txt")).unwrap(),
+            "contents"
+        );
+        let missing = store.get(&location("bucket", "missing"), &dir.path().join("missing"));
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_locations_outside_of_store_are_rejected() {
+        let store = LocalArtifactStore::new(Path::new("/store"));
+
+        let result = store.path_of(&location("bucket", "../../etc/passwd"));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,77 @@
+//! Errors that can occur while interpreting Evergreen project configuration.
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+/// Position of a node in the original YAML input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// Line in the input (starting at 1).
+    pub line: usize,
+    /// Column in the input (starting at 1).
+    pub column: usize,
+}
+
+impl Display for SourceLocation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {} column {}", self.line, self.column)
+    }
+}
+
+/// Errors that can occur when parsing an Evergreen project configuration.
+#[derive(Debug, Error)]
+pub enum ShrubError {
+    /// The input is not valid YAML.
+    #[error("Invalid YAML: {message} at {location}")]
+    YamlSyntax {
+        /// Description of the syntax error.
+        message: String,
+        /// Location of the syntax error.
+        location: SourceLocation,
+    },
+
+    /// Merge keys (`<<`) in the input could not be resolved.
+    #[error("Could not resolve merge keys: {0}")]
+    MergeKey(#[from] yaml_merge_keys::MergeKeyError),
+
+    /// The input did not contain exactly one YAML document.
+    #[error("Expected 1 and only 1 yaml document, found {found}")]
+    MultiDocument {
+        /// Number of documents found in the input.
+        found: usize,
+    },
+
+    /// The input is valid YAML but does not describe a valid Evergreen project.
+    #[error("{path}: {message}{}", display_location(.location))]
+    Schema {
+        /// Logical path to the offending node, e.g. `buildvariants[12].tasks[3].distros`.
+        path: String,
+        /// Location of the offending node in the input, if it could be determined.
+        location: Option<SourceLocation>,
+        /// Description of what is wrong with the node.
+        message: String,
+    },
+}
+
+impl From<yaml_rust::ScanError> for ShrubError {
+    fn from(err: yaml_rust::ScanError) -> Self {
+        let marker = err.marker();
+        let location = SourceLocation {
+            line: marker.line(),
+            column: marker.col() + 1,
+        };
+        // The scanner error includes the location in its message, strip it so it isn't repeated.
+        let message = err.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(idx) => message[..idx].to_string(),
+            None => message,
+        };
+        ShrubError::YamlSyntax { message, location }
+    }
+}
+
+fn display_location(location: &Option<SourceLocation>) -> String {
+    match location {
+        Some(location) => format!(" at {}", location),
+        None => String::new(),
+    }
+}
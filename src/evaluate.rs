@@ -0,0 +1,314 @@
+//! Perform the preprocessing `evergreen evaluate` does on a project configuration.
+//!
+//! Evaluating a project:
+//!
+//! * Expands matrices into the build variants they define (see [`crate::matrix`]).
+//! * Replaces selectors (`.tag !.other`) in build variant tasks with a reference to each task
+//!   or task group the selector matches.
+//! * Checks that every other build variant task is a task or task group.
+//! * Applies build variant defaults (`run_on` and `activate`) to each task reference that does
+//!   not set them.
+//! * Replaces selectors in task dependencies with a dependency on each task the selector
+//!   matches.
+use crate::matrix::{expand_matrices, MatrixError};
+use crate::models::project::EvgProject;
+use crate::models::task::{TaskDependency, TaskRef};
+use crate::models::variant::VariantEntry;
+use crate::selector::{Selector, SelectorError};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Errors that can occur while evaluating a project.
+#[derive(Debug, Error, PartialEq)]
+pub enum EvaluateError {
+    /// A build variant references a task or task group that does not exist.
+    #[error("Build variant '{variant}' references undefined task '{name}'")]
+    UndefinedTask {
+        /// Name of the build variant.
+        variant: String,
+        /// Name of the task being referenced.
+        name: String,
+    },
+
+    /// A tag selector did not match any tasks.
+    #[error("Selector '{selector}' in '{context}' does not match any tasks")]
+    UnmatchedSelector {
+        /// Build variant or task the selector was used in.
+        context: String,
+        /// The selector that matched nothing.
+        selector: String,
+    },
+
+    /// A selector could not be parsed.
+    #[error("Invalid selector in '{context}': {source}")]
+    InvalidSelector {
+        /// Build variant or task the selector was used in.
+        context: String,
+        /// Why the selector is invalid.
+        source: SelectorError,
+    },
+
+    /// A matrix could not be expanded.
+    #[error(transparent)]
+    Matrix(#[from] MatrixError),
+}
+
+/// Perform the same preprocessing on the given project that `evergreen evaluate` does.
+///
+/// * `project`: Project to evaluate.
+pub fn evaluate(mut project: EvgProject) -> Result<EvgProject, EvaluateError> {
+    let mut build_variants = expand_matrices(&project)?;
+    project.buildvariants.clear();
+    project.axes = None;
+
+    for build_variant in &mut build_variants {
+        let task_refs = std::mem::take(&mut build_variant.tasks);
+        let mut seen = HashSet::new();
+        for task_ref in task_refs {
+            let names = resolve_task_ref(&project, &build_variant.name, &task_ref)?;
+            for name in names {
+                if !seen.insert(name.clone()) {
+                    continue;
+                }
+                let mut resolved = TaskRef {
+                    name,
+                    ..task_ref.clone()
+                };
+                if resolved.distros.is_none() {
+                    resolved.distros = build_variant.run_on.clone();
+                }
+                if resolved.activate.is_none() {
+                    resolved.activate = build_variant.activate;
+                }
+                build_variant.tasks.push(resolved);
+            }
+        }
+    }
+    project.buildvariants = build_variants.into_iter().map(VariantEntry::from).collect();
+
+    let depends_on = project
+        .tasks
+        .iter()
+        .map(|task| {
+            task.depends_on
+                .as_ref()
+                .map(|d| resolve_dependencies(&project, &task.name, d))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, EvaluateError>>()?;
+    for (task, depends_on) in project.tasks.iter_mut().zip(depends_on) {
+        task.depends_on = depends_on;
+    }
+
+    Ok(project)
+}
+
+/// Replace selectors in the given dependencies with a dependency on each task they match.
+fn resolve_dependencies(
+    project: &EvgProject,
+    task: &str,
+    depends_on: &[TaskDependency],
+) -> Result<Vec<TaskDependency>, EvaluateError> {
+    let mut resolved = vec![];
+    for dependency in depends_on {
+        let selector =
+            Selector::parse(&dependency.name).map_err(|source| EvaluateError::InvalidSelector {
+                context: task.to_string(),
+                source,
+            })?;
+        if dependency.name == "*" || selector.name().is_some() {
+            resolved.push(dependency.clone());
+            continue;
+        }
+
+        let matches =
+            selector
+                .select_tasks(project)
+                .map_err(|_| EvaluateError::UnmatchedSelector {
+                    context: task.to_string(),
+                    selector: dependency.name.clone(),
+                })?;
+        resolved.extend(matches.into_iter().map(|t| TaskDependency {
+            name: t.name.clone(),
+            variant: dependency.variant.clone(),
+            extra: dependency.extra.clone(),
+        }));
+    }
+    Ok(resolved)
+}
+
+/// Get the names of the tasks or task groups a build variant task reference refers to.
+fn resolve_task_ref(
+    project: &EvgProject,
+    variant: &str,
+    task_ref: &TaskRef,
+) -> Result<Vec<String>, EvaluateError> {
+    let name = task_ref.name.as_str();
+    let selector = Selector::parse(name).map_err(|source| EvaluateError::InvalidSelector {
+        context: variant.to_string(),
+        source,
+    })?;
+    match selector.select_task_units(project) {
+        Ok(names) => Ok(names.into_iter().map(|n| n.to_string()).collect()),
+        Err(_) if selector.name().is_some() => Err(EvaluateError::UndefinedTask {
+            variant: variant.to_string(),
+            name: name.to_string(),
+        }),
+        Err(_) => Err(EvaluateError::UnmatchedSelector {
+            context: variant.to_string(),
+            selector: name.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_resolves_build_variant_tasks() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions: {}
+tasks:
+  - name: compile
+    tags: ["build"]
+  - name: package
+    tags: ["build"]
+  - name: test
+    depends_on:
+      - name: .build
+        variant: linux
+task_groups:
+  - name: test_group
+    tasks: ["test"]
+buildvariants:
+  - name: linux
+    run_on: ["ubuntu2004"]
+    activate: false
+    tasks:
+      - name: .build
+        distros: ["ubuntu2004-large"]
+      - name: compile
+      - name: test_group
+        activate: true
+"#,
+        )
+        .unwrap();
+
+        let evaluated = evaluate(project).unwrap();
+
+        let tasks: Vec<(&str, Vec<String>, Option<bool>)> = evaluated.buildvariants[0]
+            .build_variant()
+            .unwrap()
+            .tasks
+            .iter()
+            .map(|t| (t.name.as_str(), t.distros.clone().unwrap(), t.activate))
+            .collect();
+        assert_eq!(
+            tasks,
+            vec![
+                ("compile", vec!["ubuntu2004-large".to_string()], Some(false)),
+                ("package", vec!["ubuntu2004-large".to_string()], Some(false)),
+                ("test_group", vec!["ubuntu2004".to_string()], Some(true)),
+            ]
+        );
+        let dependencies: Vec<&str> = evaluated.tasks[2]
+            .depends_on
+            .iter()
+            .flatten()
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(dependencies, vec!["compile", "package"]);
+    }
+
+    #[test]
+    fn test_evaluate_fails_on_undefined_tasks() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions: {}
+tasks: []
+buildvariants:
+  - name: linux
+    tasks:
+      - name: compile
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            evaluate(project).unwrap_err(),
+            EvaluateError::UndefinedTask {
+                variant: "linux".to_string(),
+                name: "compile".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_evaluate_fails_on_unmatched_selectors() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions: {}
+tasks:
+  - name: compile
+buildvariants:
+  - name: linux
+    tasks:
+      - name: .missing
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            evaluate(project).unwrap_err(),
+            EvaluateError::UnmatchedSelector {
+                context: "linux".to_string(),
+                selector: ".missing".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_evaluate_expands_matrices() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+axes:
+  - id: os
+    values:
+      - id: linux
+        run_on: ["ubuntu2004"]
+      - id: windows
+        run_on: ["windows-vsCurrent"]
+tasks:
+  - name: compile
+buildvariants:
+  - matrix_name: build
+    matrix_spec:
+      os: "*"
+    tasks:
+      - name: compile
+"#,
+        )
+        .unwrap();
+
+        let evaluated = evaluate(project).unwrap();
+
+        let variants: Vec<(&str, Option<Vec<String>>)> = evaluated
+            .build_variants()
+            .map(|bv| (bv.name.as_str(), bv.tasks[0].distros.clone()))
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("build__os~linux", Some(vec!["ubuntu2004".to_string()])),
+                (
+                    "build__os~windows",
+                    Some(vec!["windows-vsCurrent".to_string()])
+                ),
+            ]
+        );
+        assert!(evaluated.matrices().next().is_none());
+        assert!(evaluated.axes.is_none());
+    }
+}
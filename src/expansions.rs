@@ -0,0 +1,400 @@
+//! Work out the expansions available to a task and where each of their values comes from.
+//!
+//! Expansions are collected from several places, each one overriding the ones before it:
+//!
+//! 1. Built-in expansions Evergreen sets for every task, like `task_name` and `revision`.
+//! 2. The `expansions` of the build variant the task runs on.
+//! 3. The default values of project `parameters`.
+//! 4. The `vars` of a function call, which only apply to the commands of that function.
+use crate::interpolate::Interpolator;
+use crate::models::commands::FunctionCall;
+use crate::models::project::EvgProject;
+use crate::models::variant::BuildVariant;
+use crate::selector::Selector;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+/// Errors that can occur while calculating expansions.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum ExpansionsError {
+    /// The build variant is not defined in the project.
+    #[error("Build variant '{0}' is not defined")]
+    UndefinedVariant(String),
+
+    /// The task does not run on the build variant.
+    #[error("Task '{task}' does not run on build variant '{variant}'")]
+    TaskNotOnVariant {
+        /// Name of the build variant.
+        variant: String,
+        /// Name of the task.
+        task: String,
+    },
+}
+
+/// Details about a task run that Evergreen provides as built-in expansions.
+///
+/// These can't be determined from the project configuration, so any that are not provided are
+/// left undefined.
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    /// Identifier of the project (`project`, `project_identifier`).
+    pub project: Option<String>,
+    /// Commit hash being tested (`revision`, `github_commit`).
+    pub revision: Option<String>,
+    /// Branch the project tracks (`branch_name`).
+    pub branch_name: Option<String>,
+    /// ID of the version (`version_id`).
+    pub version_id: Option<String>,
+    /// ID of the build (`build_id`).
+    pub build_id: Option<String>,
+    /// ID of the task (`task_id`).
+    pub task_id: Option<String>,
+    /// Execution number of the task (`execution`).
+    pub execution: Option<u64>,
+    /// Working directory of the task (`workdir`).
+    pub workdir: Option<String>,
+    /// What requested the version, e.g. `patch_request` or `gitter_request` (`requester`).
+    pub requester: Option<String>,
+    /// Whether the task is part of a patch (`is_patch`).
+    pub is_patch: bool,
+}
+
+/// Where the value of an expansion came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpansionSource {
+    /// A built-in expansion set by Evergreen.
+    Builtin,
+    /// The `expansions` of a build variant.
+    Variant(String),
+    /// The default value of a project parameter.
+    Parameter,
+    /// The `vars` of a call to a function.
+    FunctionVars(String),
+}
+
+impl Display for ExpansionSource {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ExpansionSource::Builtin => write!(f, "built-in"),
+            ExpansionSource::Variant(name) => write!(f, "build variant '{}'", name),
+            ExpansionSource::Parameter => write!(f, "parameter"),
+            ExpansionSource::FunctionVars(name) => write!(f, "vars of function '{}'", name),
+        }
+    }
+}
+
+/// The effective value of an expansion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    /// Value of the expansion.
+    pub value: String,
+    /// Where the value came from.
+    pub source: ExpansionSource,
+    /// Earlier values that were overridden by this one, oldest first.
+    pub shadowed: Vec<(ExpansionSource, String)>,
+}
+
+/// The expansions available to a task running on a build variant.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectiveExpansions {
+    expansions: BTreeMap<String, Expansion>,
+}
+
+impl EffectiveExpansions {
+    /// Calculate the expansions available to the given task running on the given build variant.
+    ///
+    /// * `project`: Project the task is defined in.
+    /// * `variant`: Name of build variant the task runs on.
+    /// * `task`: Name of task.
+    /// * `context`: Details of the task run for built-in expansions.
+    pub fn for_task(
+        project: &EvgProject,
+        variant: &str,
+        task: &str,
+        context: &RunContext,
+    ) -> Result<Self, ExpansionsError> {
+        let build_variant = project
+            .build_variants()
+            .find(|bv| bv.name == variant)
+            .ok_or_else(|| ExpansionsError::UndefinedVariant(variant.to_string()))?;
+        if !project
+            .tasks_on_variant(build_variant)
+            .iter()
+            .any(|t| t.name == task)
+        {
+            return Err(ExpansionsError::TaskNotOnVariant {
+                variant: variant.to_string(),
+                task: task.to_string(),
+            });
+        }
+
+        let mut expansions = EffectiveExpansions::default();
+        for (name, value) in builtins(project, build_variant, task, context) {
+            expansions.set(name, &value, ExpansionSource::Builtin);
+        }
+        for (name, value) in build_variant.expansions.iter().flatten() {
+            expansions.set(name, value, ExpansionSource::Variant(variant.to_string()));
+        }
+        for parameter in project.parameters.iter().flatten() {
+            if let Some(value) = &parameter.value {
+                expansions.set(&parameter.key, value, ExpansionSource::Parameter);
+            }
+        }
+        Ok(expansions)
+    }
+
+    /// Get the expansions available to the commands of the given function call.
+    ///
+    /// Expansions referenced in the `vars` of the call are substituted before they are added,
+    /// with undefined references replaced by an empty string like Evergreen does.
+    ///
+    /// * `call`: Function call to add `vars` from.
+    pub fn with_function_call(&self, call: &FunctionCall) -> Self {
+        let values = self.values();
+        let interpolator = Interpolator::new(&values);
+        let mut expansions = self.clone();
+        let vars: BTreeMap<&String, String> = call
+            .vars
+            .iter()
+            .flatten()
+            .map(|(k, v)| (k, interpolator.interpolate_str(&v.to_string()).value))
+            .collect();
+        for (name, value) in vars {
+            expansions.set(
+                name,
+                &value,
+                ExpansionSource::FunctionVars(call.func.clone()),
+            );
+        }
+        expansions
+    }
+
+    /// Get the effective value of the given expansion.
+    pub fn get(&self, name: &str) -> Option<&Expansion> {
+        self.expansions.get(name)
+    }
+
+    /// Iterate over all the expansions in order of their names.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Expansion)> {
+        self.expansions.iter()
+    }
+
+    /// Get the values of all the expansions, e.g. to use with an
+    /// [`Interpolator`](crate::interpolate::Interpolator).
+    pub fn values(&self) -> BTreeMap<String, String> {
+        self.expansions
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.value.to_string()))
+            .collect()
+    }
+
+    fn set(&mut self, name: &str, value: &str, source: ExpansionSource) {
+        match self.expansions.get_mut(name) {
+            Some(existing) => {
+                let previous_source = std::mem::replace(&mut existing.source, source);
+                let previous_value = std::mem::replace(&mut existing.value, value.to_string());
+                existing.shadowed.push((previous_source, previous_value));
+            }
+            None => {
+                self.expansions.insert(
+                    name.to_string(),
+                    Expansion {
+                        value: value.to_string(),
+                        source,
+                        shadowed: vec![],
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Get the built-in expansions Evergreen sets for a task.
+fn builtins(
+    project: &EvgProject,
+    build_variant: &BuildVariant,
+    task: &str,
+    context: &RunContext,
+) -> Vec<(&'static str, String)> {
+    let mut builtins = vec![
+        ("build_variant", build_variant.name.to_string()),
+        ("task_name", task.to_string()),
+    ];
+    if let Some(distro) = distro(project, build_variant, task) {
+        builtins.push(("distro_id", distro));
+    }
+
+    let optional = [
+        ("project", &context.project),
+        ("project_identifier", &context.project),
+        ("revision", &context.revision),
+        ("github_commit", &context.revision),
+        ("branch_name", &context.branch_name),
+        ("version_id", &context.version_id),
+        ("build_id", &context.build_id),
+        ("task_id", &context.task_id),
+        ("workdir", &context.workdir),
+        ("requester", &context.requester),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            builtins.push((name, value.to_string()));
+        }
+    }
+    if let Some(execution) = context.execution {
+        builtins.push(("execution", execution.to_string()));
+    }
+    if context.is_patch {
+        builtins.push(("is_patch", "true".to_string()));
+    }
+    builtins
+}
+
+/// Get the distro a task runs on, which is the first distro of the task reference or the
+/// build variant.
+fn distro(project: &EvgProject, build_variant: &BuildVariant, task: &str) -> Option<String> {
+    let task_group_map = project.task_group_map();
+    let task_ref = build_variant.tasks.iter().find(|task_ref| {
+        let units = Selector::parse(&task_ref.name).and_then(|s| s.select_task_units(project));
+        units.unwrap_or_default().iter().any(|name| {
+            *name == task
+                || task_group_map
+                    .get(*name)
+                    .is_some_and(|tg| tg.tasks.iter().any(|t| t == task))
+        })
+    });
+    task_ref
+        .and_then(|t| t.distros.as_ref())
+        .or(build_variant.run_on.as_ref())
+        .and_then(|distros| distros.first())
+        .cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::commands::EvgCommand;
+
+    const PROJECT: &str = r#"
+parameters:
+  - key: mongodb_version
+    value: "6.0"
+    description: Version to test against.
+functions:
+  run_tests:
+    command: shell.exec
+    params:
+      script: echo ${suite}
+tasks:
+  - name: compile
+  - name: test
+    commands:
+      - func: run_tests
+        vars:
+          suite: core
+          mongodb_version: ${mongodb_version}-latest
+          distro_id: ${missing}
+task_groups:
+  - name: test_group
+    tasks: ["test"]
+buildvariants:
+  - name: linux
+    run_on: ["ubuntu2004"]
+    expansions:
+      task_name: overridden
+      mongodb_version: "5.0"
+      compiler: gcc
+    tasks:
+      - name: compile
+      - name: test_group
+        distros: ["ubuntu2004-large"]
+"#;
+
+    #[test]
+    fn test_expansions_for_task() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let context = RunContext {
+            revision: Some("abc123".to_string()),
+            ..Default::default()
+        };
+
+        let expansions =
+            EffectiveExpansions::for_task(&project, "linux", "test", &context).unwrap();
+
+        let values: Vec<(&str, &str, String)> = expansions
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.value.as_str(), v.source.to_string()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("build_variant", "linux", "built-in".to_string()),
+                ("compiler", "gcc", "build variant 'linux'".to_string()),
+                ("distro_id", "ubuntu2004-large", "built-in".to_string()),
+                ("github_commit", "abc123", "built-in".to_string()),
+                ("mongodb_version", "6.0", "parameter".to_string()),
+                ("revision", "abc123", "built-in".to_string()),
+                (
+                    "task_name",
+                    "overridden",
+                    "build variant 'linux'".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            expansions.get("task_name").unwrap().shadowed,
+            vec![(ExpansionSource::Builtin, "test".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_expansions_for_function_call() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let expansions =
+            EffectiveExpansions::for_task(&project, "linux", "test", &RunContext::default())
+                .unwrap();
+        let call = match &project.tasks[1].commands.as_ref().unwrap()[0] {
+            EvgCommand::Function(call) => call,
+            _ => panic!("Expected function call"),
+        };
+
+        let scoped = expansions.with_function_call(call);
+
+        let mongodb_version = scoped.get("mongodb_version").unwrap();
+        assert_eq!(mongodb_version.value, "6.0-latest");
+        assert_eq!(
+            mongodb_version.source,
+            ExpansionSource::FunctionVars("run_tests".to_string())
+        );
+        assert_eq!(
+            mongodb_version.shadowed,
+            vec![
+                (
+                    ExpansionSource::Variant("linux".to_string()),
+                    "5.0".to_string()
+                ),
+                (ExpansionSource::Parameter, "6.0".to_string()),
+            ]
+        );
+        assert_eq!(scoped.get("suite").unwrap().value, "core");
+        assert_eq!(scoped.get("distro_id").unwrap().value, "");
+        assert_eq!(expansions.get("suite"), None);
+    }
+
+    #[test]
+    fn test_expansions_for_task_not_on_variant() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let result =
+            EffectiveExpansions::for_task(&project, "linux", "lint", &RunContext::default());
+
+        assert_eq!(
+            result.unwrap_err(),
+            ExpansionsError::TaskNotOnVariant {
+                variant: "linux".to_string(),
+                task: "lint".to_string()
+            }
+        );
+    }
+}
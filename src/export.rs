@@ -0,0 +1,377 @@
+//! Render the structure of an Evergreen project as a graph.
+//!
+//! The build variants, tasks, task groups, display tasks and dependencies of a project can be
+//! exported as [Graphviz DOT](https://graphviz.org/doc/info/lang.html) or
+//! [Mermaid](https://mermaid.js.org/syntax/flowchart.html) text. Dependency edges point from a
+//! task to the tasks that depend on it, so the graph reads in the order tasks run.
+use crate::graph::{DependencyGraph, TaskNode};
+use crate::models::project::EvgProject;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Describe which parts of a project should be exported.
+#[derive(Debug, Clone, Default)]
+pub struct ExportFilter {
+    /// Only include tasks on this build variant.
+    pub variant: Option<String>,
+    /// Only include this task and the tasks it depends on or that depend on it.
+    pub neighbourhood_of: Option<TaskNode>,
+}
+
+/// Tasks of a build variant to include in the export.
+struct VariantView {
+    name: String,
+    label: String,
+    /// Task groups on the variant and the tasks they contain.
+    task_groups: Vec<(String, Vec<String>)>,
+    /// Tasks on the variant that are not in a task group.
+    tasks: Vec<String>,
+    /// Display tasks on the variant and the tasks they contain.
+    display_tasks: Vec<(String, Vec<String>)>,
+}
+
+/// The parts of a project to include in an export.
+struct ExportView {
+    variants: Vec<VariantView>,
+    edges: Vec<(TaskNode, TaskNode)>,
+}
+
+impl ExportView {
+    fn new(project: &EvgProject, filter: &ExportFilter) -> Self {
+        let graph = DependencyGraph::from_project(project);
+        let mut included: BTreeSet<&TaskNode> = graph
+            .nodes()
+            .filter(|n| filter.variant.iter().all(|v| &n.variant == v))
+            .collect();
+        if let Some(center) = &filter.neighbourhood_of {
+            let mut neighbourhood = graph.upstream(center);
+            neighbourhood.extend(graph.downstream(center));
+            if graph.contains(center) {
+                neighbourhood.insert(center);
+            }
+            included = included.intersection(&neighbourhood).copied().collect();
+        }
+
+        let task_group_map = project.task_group_map();
+        let mut variants = vec![];
+        for build_variant in project.build_variants() {
+            let is_included =
+                |task: &str| included.contains(&TaskNode::new(&build_variant.name, task));
+            let mut task_groups = vec![];
+            let mut grouped = BTreeSet::new();
+            for task_ref in &build_variant.tasks {
+                if let Some(task_group) = task_group_map.get(&task_ref.name) {
+                    let tasks: Vec<String> = task_group
+                        .tasks
+                        .iter()
+                        .filter(|t| is_included(t))
+                        .cloned()
+                        .collect();
+                    if !tasks.is_empty() {
+                        grouped.extend(tasks.iter().cloned());
+                        task_groups.push((task_group.name.to_string(), tasks));
+                    }
+                }
+            }
+
+            let tasks: Vec<String> = project
+                .tasks_on_variant(build_variant)
+                .iter()
+                .map(|t| t.name.to_string())
+                .filter(|t| is_included(t) && !grouped.contains(t))
+                .collect();
+            if tasks.is_empty() && task_groups.is_empty() {
+                continue;
+            }
+
+            let display_tasks = build_variant
+                .display_tasks
+                .iter()
+                .flatten()
+                .map(|dt| {
+                    let tasks: Vec<String> = dt
+                        .execution_tasks
+                        .iter()
+                        .filter(|t| is_included(t))
+                        .cloned()
+                        .collect();
+                    (dt.name.to_string(), tasks)
+                })
+                .filter(|(_, tasks)| !tasks.is_empty())
+                .collect();
+
+            variants.push(VariantView {
+                name: build_variant.name.to_string(),
+                label: build_variant
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| build_variant.name.to_string()),
+                task_groups,
+                tasks,
+                display_tasks,
+            });
+        }
+
+        let mut edges = vec![];
+        for node in &included {
+            for dependency in graph.dependencies(node) {
+                if included.contains(dependency) {
+                    edges.push((dependency.clone(), (*node).clone()));
+                }
+            }
+        }
+
+        ExportView { variants, edges }
+    }
+}
+
+/// Render the given project as a Graphviz DOT graph.
+///
+/// * `project`: Project to render.
+/// * `filter`: Parts of the project to include.
+pub fn to_dot(project: &EvgProject, filter: &ExportFilter) -> String {
+    let view = ExportView::new(project, filter);
+    let mut out = String::new();
+    writeln!(out, "digraph evergreen {{").unwrap();
+    writeln!(out, "  compound=true;").unwrap();
+    writeln!(out, "  node [shape=box];").unwrap();
+
+    for variant in &view.variants {
+        let task_id = |task: &str| dot_quote(&TaskNode::new(&variant.name, task).to_string());
+        writeln!(
+            out,
+            "  subgraph {} {{",
+            dot_quote(&format!("cluster_{}", variant.name))
+        )
+        .unwrap();
+        writeln!(out, "    label={};", dot_quote(&variant.label)).unwrap();
+        for (task_group, tasks) in &variant.task_groups {
+            let cluster = format!("cluster_{}:{}", variant.name, task_group);
+            writeln!(out, "    subgraph {} {{", dot_quote(&cluster)).unwrap();
+            writeln!(out, "      label={};", dot_quote(task_group)).unwrap();
+            writeln!(out, "      style=dashed;").unwrap();
+            for task in tasks {
+                writeln!(out, "      {} [label={}];", task_id(task), dot_quote(task)).unwrap();
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+        for task in &variant.tasks {
+            writeln!(out, "    {} [label={}];", task_id(task), dot_quote(task)).unwrap();
+        }
+        for (display_task, tasks) in &variant.display_tasks {
+            let display_id = dot_quote(&format!("{}:display:{}", variant.name, display_task));
+            writeln!(
+                out,
+                "    {} [label={}, shape=folder];",
+                display_id,
+                dot_quote(display_task)
+            )
+            .unwrap();
+            for task in tasks {
+                writeln!(
+                    out,
+                    "    {} -> {} [style=dotted, arrowhead=none];",
+                    display_id,
+                    task_id(task)
+                )
+                .unwrap();
+            }
+        }
+        writeln!(out, "  }}").unwrap();
+    }
+
+    for (from, to) in &view.edges {
+        writeln!(
+            out,
+            "  {} -> {};",
+            dot_quote(&from.to_string()),
+            dot_quote(&to.to_string())
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// Render the given project as a Mermaid flowchart.
+///
+/// * `project`: Project to render.
+/// * `filter`: Parts of the project to include.
+pub fn to_mermaid(project: &EvgProject, filter: &ExportFilter) -> String {
+    let view = ExportView::new(project, filter);
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let mut id_for = |key: String| -> String {
+        let next = format!("n{}", ids.len());
+        ids.entry(key).or_insert(next).clone()
+    };
+
+    let mut out = String::new();
+    writeln!(out, "flowchart LR").unwrap();
+    for variant in &view.variants {
+        let variant_id = id_for(format!("variant:{}", variant.name));
+        writeln!(
+            out,
+            "  subgraph {}[{}]",
+            variant_id,
+            mermaid_quote(&variant.label)
+        )
+        .unwrap();
+        for (task_group, tasks) in &variant.task_groups {
+            let group_id = id_for(format!("group:{}:{}", variant.name, task_group));
+            writeln!(
+                out,
+                "    subgraph {}[{}]",
+                group_id,
+                mermaid_quote(task_group)
+            )
+            .unwrap();
+            for task in tasks {
+                let id = id_for(TaskNode::new(&variant.name, task).to_string());
+                writeln!(out, "      {}[{}]", id, mermaid_quote(task)).unwrap();
+            }
+            writeln!(out, "    end").unwrap();
+        }
+        for task in &variant.tasks {
+            let id = id_for(TaskNode::new(&variant.name, task).to_string());
+            writeln!(out, "    {}[{}]", id, mermaid_quote(task)).unwrap();
+        }
+        for (display_task, tasks) in &variant.display_tasks {
+            let display_id = id_for(format!("display:{}:{}", variant.name, display_task));
+            writeln!(out, "    {}[/{}/]", display_id, mermaid_quote(display_task)).unwrap();
+            for task in tasks {
+                let id = id_for(TaskNode::new(&variant.name, task).to_string());
+                writeln!(out, "    {} -.- {}", display_id, id).unwrap();
+            }
+        }
+        writeln!(out, "  end").unwrap();
+    }
+
+    for (from, to) in &view.edges {
+        let from_id = id_for(from.to_string());
+        let to_id = id_for(to.to_string());
+        writeln!(out, "  {} --> {}", from_id, to_id).unwrap();
+    }
+    out
+}
+
+fn dot_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn mermaid_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "#quot;"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECT: &str = r#"
+functions: {}
+tasks:
+  - name: compile
+  - name: unit_tests
+    depends_on:
+      - name: compile
+  - name: lint
+  - name: package
+    depends_on:
+      - name: compile
+        variant: linux
+task_groups:
+  - name: test_group
+    tasks: ["unit_tests"]
+buildvariants:
+  - name: linux
+    display_name: "Linux \"x86\""
+    tasks:
+      - name: compile
+      - name: test_group
+      - name: lint
+    display_tasks:
+      - name: checks
+        execution_tasks: ["unit_tests", "lint"]
+  - name: windows
+    tasks:
+      - name: package
+"#;
+
+    #[test]
+    fn test_dot_export() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let dot = to_dot(&project, &ExportFilter::default());
+
+        assert_eq!(
+            dot,
+            r#"digraph evergreen {
+  compound=true;
+  node [shape=box];
+  subgraph "cluster_linux" {
+    label="Linux \"x86\"";
+    subgraph "cluster_linux:test_group" {
+      label="test_group";
+      style=dashed;
+      "linux:unit_tests" [label="unit_tests"];
+    }
+    "linux:compile" [label="compile"];
+    "linux:lint" [label="lint"];
+    "linux:display:checks" [label="checks", shape=folder];
+    "linux:display:checks" -> "linux:unit_tests" [style=dotted, arrowhead=none];
+    "linux:display:checks" -> "linux:lint" [style=dotted, arrowhead=none];
+  }
+  subgraph "cluster_windows" {
+    label="windows";
+    "windows:package" [label="package"];
+  }
+  "linux:compile" -> "linux:unit_tests";
+  "linux:compile" -> "windows:package";
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_mermaid_export_filtered_to_variant() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let filter = ExportFilter {
+            variant: Some("windows".to_string()),
+            ..Default::default()
+        };
+
+        let mermaid = to_mermaid(&project, &filter);
+
+        assert_eq!(
+            mermaid,
+            r#"flowchart LR
+  subgraph n0["windows"]
+    n1["package"]
+  end
+"#
+        );
+    }
+
+    #[test]
+    fn test_export_filtered_to_neighbourhood() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let filter = ExportFilter {
+            neighbourhood_of: Some(TaskNode::new("windows", "package")),
+            ..Default::default()
+        };
+
+        let mermaid = to_mermaid(&project, &filter);
+
+        assert_eq!(
+            mermaid,
+            r#"flowchart LR
+  subgraph n0["Linux #quot;x86#quot;"]
+    n1["compile"]
+  end
+  subgraph n2["windows"]
+    n3["package"]
+  end
+  n1 --> n3
+"#
+        );
+    }
+}
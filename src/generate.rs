@@ -0,0 +1,418 @@
+//! Build the configuration files read by `generate.tasks`.
+//!
+//! `generate.tasks` only accepts a subset of a project configuration: tasks, functions, task
+//! groups and build variants. Evergreen rejects a file with any other top level fields, like
+//! `pre` or `modules`, so [`GeneratedConfig`] only has those fields. Build variants, tasks and
+//! task groups are checked too: fields shrub does not model are only allowed if `generate.tasks`
+//! accepts them. It can be built from an [`EvgProject`] with [`GeneratedConfig::from_project`],
+//! which fails if the project contains anything that can't be generated.
+//!
+//! Configs are written as JSON with [`GeneratedConfig::write_json`], or several at once with
+//! [`write_configs`] which also describes the `generate.tasks` command that loads them. To see
+//! the project Evergreen would end up with, apply them to it with [`apply::apply_generated`].
+//! Configs too large for a single file can be split with [`shard::shard`].
+//!
+//! Test suites can be split into sub-tasks that take about the same time with
+//! [`split::split_tests`], and many similar tasks can be created from a
+//! [`template::TaskTemplate`].
+use crate::models::builtin::GenerateTasksParams;
+use crate::models::project::{EvgProject, FunctionDefinition};
+use crate::models::task::EvgTask;
+use crate::models::task_group::EvgTaskGroup;
+use crate::models::variant::BuildVariant;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub mod apply;
+pub mod shard;
+pub mod split;
+pub mod template;
+
+/// Errors that can occur while building or writing generated configuration.
+#[derive(Debug, Error)]
+pub enum GenerateError {
+    /// The project contains fields that `generate.tasks` does not accept.
+    #[error("generate.tasks does not support: {}", .0.join(", "))]
+    Unsupported(Vec<String>),
+
+    /// The configuration could not be converted to or from JSON.
+    #[error("Invalid generated configuration: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// A file could not be written.
+    #[error("Could not write '{}': {source}", .path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Error writing the file.
+        source: io::Error,
+    },
+}
+
+/// Fields of build variants that `generate.tasks` accepts but shrub does not model.
+const VARIANT_FIELDS: &[&str] = &[
+    "allow_for_git_tag",
+    "allowed_requesters",
+    "cron",
+    "deactivate_previous",
+    "depends_on",
+    "disable",
+    "exec_timeout_secs",
+    "git_tag_only",
+    "patch_only",
+    "patchable",
+    "paths",
+    "priority",
+];
+
+/// Fields of build variant tasks that `generate.tasks` accepts but shrub does not model.
+const TASK_REF_FIELDS: &[&str] = &[
+    "allow_for_git_tag",
+    "allowed_requesters",
+    "batchtime",
+    "create_check_run",
+    "cron",
+    "depends_on",
+    "disable",
+    "exec_timeout_secs",
+    "git_tag_only",
+    "patch_only",
+    "patchable",
+    "priority",
+    "run_on",
+    "stepback",
+];
+
+/// Fields of tasks that `generate.tasks` accepts but shrub does not model.
+const TASK_FIELDS: &[&str] = &["allow_for_git_tag", "create_check_run", "git_tag_only"];
+
+/// Fields of task dependencies that `generate.tasks` accepts but shrub does not model.
+const DEPENDENCY_FIELDS: &[&str] = &["omit_generated_tasks", "patch_optional", "status"];
+
+/// Fields of task groups that `generate.tasks` accepts but shrub does not model.
+const TASK_GROUP_FIELDS: &[&str] = &[
+    "callback_timeout_secs",
+    "setup_task_can_fail_task",
+    "setup_task_timeout_secs",
+    "teardown_group_timeout_secs",
+    "teardown_task_can_fail_task",
+];
+
+/// Get the paths of the fields in `extra` that are not in `allowed`.
+fn unsupported_fields<'a>(
+    path: String,
+    extra: &'a BTreeMap<String, serde_yaml::Value>,
+    allowed: &'a [&str],
+) -> impl Iterator<Item = String> + 'a {
+    extra
+        .keys()
+        .filter(move |field| !allowed.contains(&field.as_str()))
+        .map(move |field| format!("{}.{}", path, field))
+}
+
+/// Configuration that can be added to a project with `generate.tasks`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GeneratedConfig {
+    /// Build variants to create, or to add tasks and display tasks to if they already exist.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buildvariants: Vec<BuildVariant>,
+    /// Tasks to create.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tasks: Vec<EvgTask>,
+    /// Functions to create.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub functions: BTreeMap<String, FunctionDefinition>,
+    /// Task groups to create.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub task_groups: Vec<EvgTaskGroup>,
+}
+
+impl GeneratedConfig {
+    /// Get the parts of the given project that can be generated.
+    ///
+    /// Fails if the project sets any other fields, listing all of them.
+    pub fn from_project(project: &EvgProject) -> Result<Self, GenerateError> {
+        let EvgProject {
+            buildvariants: _,
+            axes,
+            tasks,
+            task_groups,
+            functions,
+            pre,
+            post,
+            timeout,
+            modules,
+            stepback,
+            pre_error_fails_task,
+            oom_tracker,
+            command_type,
+            ignore,
+            parameters,
+            include,
+            extra,
+        } = project;
+
+        let mut unsupported: Vec<String> = [
+            ("matrix_name", project.matrices().next().is_some()),
+            ("axes", axes.is_some()),
+            ("pre", pre.is_some()),
+            ("post", post.is_some()),
+            ("timeout", timeout.is_some()),
+            ("modules", modules.is_some()),
+            ("stepback", stepback.is_some()),
+            ("pre_error_fails_task", pre_error_fails_task.is_some()),
+            ("oom_tracker", oom_tracker.is_some()),
+            ("command_type", command_type.is_some()),
+            ("ignore", ignore.is_some()),
+            ("parameters", parameters.is_some()),
+            ("include", include.is_some()),
+        ]
+        .iter()
+        .filter(|(_, is_set)| *is_set)
+        .map(|(field, _)| field.to_string())
+        .collect();
+        unsupported.extend(extra.keys().cloned());
+
+        let config = GeneratedConfig {
+            buildvariants: project.build_variants().cloned().collect(),
+            tasks: tasks.clone(),
+            functions: functions
+                .iter()
+                .map(|(name, function)| (name.to_string(), function.clone()))
+                .collect(),
+            task_groups: task_groups.clone().unwrap_or_default(),
+        };
+        unsupported.extend(config.unsupported_fields());
+        if !unsupported.is_empty() {
+            return Err(GenerateError::Unsupported(unsupported));
+        }
+        Ok(config)
+    }
+
+    /// Parse generated configuration from JSON.
+    ///
+    /// Fails if the JSON has any fields that `generate.tasks` does not accept.
+    pub fn from_json_str(json: &str) -> Result<Self, GenerateError> {
+        let config: GeneratedConfig = serde_json::from_str(json)?;
+        let unsupported = config.unsupported_fields();
+        if !unsupported.is_empty() {
+            return Err(GenerateError::Unsupported(unsupported));
+        }
+        Ok(config)
+    }
+
+    /// Get the paths of fields of build variants, tasks and task groups that `generate.tasks`
+    /// does not accept.
+    fn unsupported_fields(&self) -> Vec<String> {
+        let mut unsupported = vec![];
+        for (bv_index, bv) in self.buildvariants.iter().enumerate() {
+            let path = format!("buildvariants[{}]", bv_index);
+            unsupported.extend(unsupported_fields(path.clone(), &bv.extra, VARIANT_FIELDS));
+            for (index, task_ref) in bv.tasks.iter().enumerate() {
+                let path = format!("{}.tasks[{}]", path, index);
+                unsupported.extend(unsupported_fields(path, &task_ref.extra, TASK_REF_FIELDS));
+            }
+            for (index, display_task) in bv.display_tasks.iter().flatten().enumerate() {
+                let path = format!("{}.display_tasks[{}]", path, index);
+                unsupported.extend(unsupported_fields(path, &display_task.extra, &[]));
+            }
+        }
+        for (task_index, task) in self.tasks.iter().enumerate() {
+            let path = format!("tasks[{}]", task_index);
+            unsupported.extend(unsupported_fields(path.clone(), &task.extra, TASK_FIELDS));
+            for (index, dependency) in task.depends_on.iter().flatten().enumerate() {
+                let path = format!("{}.depends_on[{}]", path, index);
+                unsupported.extend(unsupported_fields(
+                    path,
+                    &dependency.extra,
+                    DEPENDENCY_FIELDS,
+                ));
+            }
+        }
+        for (index, task_group) in self.task_groups.iter().enumerate() {
+            let path = format!("task_groups[{}]", index);
+            unsupported.extend(unsupported_fields(
+                path,
+                &task_group.extra,
+                TASK_GROUP_FIELDS,
+            ));
+        }
+        unsupported
+    }
+
+    /// Serialize the configuration to JSON.
+    pub fn to_json_string(&self) -> Result<String, GenerateError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Write the configuration to the given file as JSON, creating its directory if needed.
+    pub fn write_json(&self, path: &Path) -> Result<(), GenerateError> {
+        let json = self.to_json_string()?;
+        let io_error = |source| GenerateError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::write(path, json).map_err(io_error)
+    }
+}
+
+/// Write each of the given configs to its own JSON file.
+///
+/// Files are named `<prefix>.json` if there is one config, or `<prefix>_<index>.json` if there
+/// are more. Returns the parameters of the `generate.tasks` command that loads the files, with
+/// paths joined to `dir` the way it was given.
+///
+/// * `configs`: Configs to write.
+/// * `dir`: Directory to write files to.
+/// * `prefix`: Start of the name of each file.
+pub fn write_configs(
+    configs: &[GeneratedConfig],
+    dir: &Path,
+    prefix: &str,
+) -> Result<GenerateTasksParams, GenerateError> {
+    let mut files = vec![];
+    for (index, config) in configs.iter().enumerate() {
+        let name = if configs.len() == 1 {
+            format!("{}.json", prefix)
+        } else {
+            format!("{}_{}.json", prefix, index)
+        };
+        let path = dir.join(name);
+        config.write_json(&path)?;
+        files.push(path.to_string_lossy().to_string());
+    }
+    Ok(GenerateTasksParams {
+        files,
+        extra: BTreeMap::new(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECT: &str = r#"
+functions:
+  run:
+    command: shell.exec
+    params:
+      script: ./run.sh
+tasks:
+  - name: test_0
+    commands:
+      - func: run
+task_groups:
+  - name: test_group
+    tasks: ["test_0"]
+buildvariants:
+  - name: linux
+    tasks:
+      - name: test_group
+    display_tasks:
+      - name: test
+        execution_tasks: ["test_0"]
+"#;
+
+    #[test]
+    fn test_from_project() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let config = GeneratedConfig::from_project(&project).unwrap();
+        let json = config.to_json_string().unwrap();
+        let read_back = GeneratedConfig::from_json_str(&json).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let keys: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            vec!["buildvariants", "functions", "task_groups", "tasks"]
+        );
+        assert_eq!(
+            value["buildvariants"][0]["display_tasks"][0]["execution_tasks"],
+            serde_json::json!(["test_0"])
+        );
+        assert_eq!(read_back.tasks[0].name, "test_0");
+        assert!(read_back.functions.contains_key("run"));
+    }
+
+    #[test]
+    fn test_unsupported_fields_are_rejected() {
+        let yaml = format!(
+            "pre: []\nstepback: true\nexec_timeout_secs: 10\n{}",
+            PROJECT
+        );
+        let project = EvgProject::from_yaml_str(&yaml).unwrap();
+
+        let result = GeneratedConfig::from_project(&project);
+
+        match result {
+            Err(GenerateError::Unsupported(fields)) => {
+                assert_eq!(fields, vec!["pre", "stepback", "exec_timeout_secs"])
+            }
+            other => panic!("Expected unsupported fields, got {:?}", other),
+        }
+        assert!(GeneratedConfig::from_json_str(r#"{"pre": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_nested_fields_are_rejected() {
+        let json = r#"{
+            "tasks": [
+                {
+                    "name": "test_0",
+                    "not_a_field": true,
+                    "depends_on": [{"name": "compile", "status": "*", "typo": 1}]
+                }
+            ],
+            "buildvariants": [
+                {
+                    "name": "linux",
+                    "cron": "0 * * * *",
+                    "tasks": [{"name": "test_0", "priority": 10, "distro": "ubuntu"}]
+                }
+            ]
+        }"#;
+
+        let result = GeneratedConfig::from_json_str(json);
+
+        match result {
+            Err(GenerateError::Unsupported(fields)) => assert_eq!(
+                fields,
+                vec![
+                    "buildvariants[0].tasks[0].distro",
+                    "tasks[0].not_a_field",
+                    "tasks[0].depends_on[0].typo",
+                ]
+            ),
+            other => panic!("Expected unsupported fields, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_write_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let config = GeneratedConfig::from_project(&project).unwrap();
+
+        let params = write_configs(
+            &[config.clone(), GeneratedConfig::default()],
+            &dir.path().join("generated"),
+            "tasks",
+        )
+        .unwrap();
+
+        assert_eq!(params.files.len(), 2);
+        assert!(params.files[1].ends_with("generated/tasks_1.json"));
+        let written = fs::read_to_string(&params.files[0]).unwrap();
+        assert_eq!(written, config.to_json_string().unwrap());
+        assert_eq!(fs::read_to_string(&params.files[1]).unwrap(), "{}");
+    }
+}
@@ -0,0 +1,409 @@
+//! Apply the output of `generate.tasks` to a project, the way Evergreen does.
+//!
+//! * Tasks, functions and task groups are added to the project. They can't redefine any that
+//!   are already in the project or in an earlier document.
+//! * Build variants that don't exist are created.
+//! * Build variants that exist get the generated tasks and display tasks added to them. Display
+//!   tasks with the same name have their execution tasks combined, and tasks already on the
+//!   build variant are not added again. Any other field of an existing build variant can't be
+//!   changed.
+//!
+//! Documents are applied in order, so a later document can add to a build variant created by an
+//! earlier one.
+use super::{GenerateError, GeneratedConfig};
+use crate::models::project::EvgProject;
+use crate::models::variant::{BuildVariant, DisplayTask};
+use std::collections::hash_map::Entry;
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+/// Something in a generated document that conflicts with what is already defined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateConflict {
+    /// Index of the document the conflict is in.
+    pub document: usize,
+    /// Field that was redefined, e.g. `tasks` or `buildvariants.run_on`.
+    pub field: String,
+    /// Name of the item that was redefined. For fields of a build variant, the build variant.
+    pub name: String,
+}
+
+impl Display for GenerateConflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "generated document {}: {} '{}' is already defined",
+            self.document, self.field, self.name
+        )
+    }
+}
+
+/// Errors that can occur while applying generated configuration to a project.
+#[derive(Debug, Error)]
+pub enum ApplyError {
+    /// A generated document could not be parsed.
+    #[error("Could not parse generated document {document}: {source}")]
+    Parse {
+        /// Index of the document.
+        document: usize,
+        /// Error parsing the document.
+        source: GenerateError,
+    },
+
+    /// Generated documents redefine things that are already defined.
+    #[error("Could not apply generated configuration: {}", display_conflicts(.0))]
+    Conflicts(Vec<GenerateConflict>),
+}
+
+fn display_conflicts(conflicts: &[GenerateConflict]) -> String {
+    conflicts
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// What changed in a project when generated configuration was applied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeReport {
+    /// Names of tasks that were added.
+    pub added_tasks: Vec<String>,
+    /// Names of functions that were added.
+    pub added_functions: Vec<String>,
+    /// Names of task groups that were added.
+    pub added_task_groups: Vec<String>,
+    /// Names of build variants that were created.
+    pub created_variants: Vec<String>,
+    /// Tasks added to build variants that already existed, as `(variant, task)`.
+    pub added_task_refs: Vec<(String, String)>,
+    /// Tasks that were already on a build variant and not added again, as `(variant, task)`.
+    pub skipped_task_refs: Vec<(String, String)>,
+    /// Display tasks added to build variants that already existed, as `(variant, display task)`.
+    pub added_display_tasks: Vec<(String, String)>,
+    /// Display tasks that existed and had execution tasks added, as `(variant, display task)`.
+    pub merged_display_tasks: Vec<(String, String)>,
+}
+
+/// Apply generated JSON documents to a project.
+///
+/// * `project`: Project to apply the documents to.
+/// * `documents`: Contents of the files given to `generate.tasks`.
+pub fn apply_generated_json(
+    project: EvgProject,
+    documents: &[&str],
+) -> Result<(EvgProject, MergeReport), ApplyError> {
+    let configs = documents
+        .iter()
+        .enumerate()
+        .map(|(document, json)| {
+            GeneratedConfig::from_json_str(json)
+                .map_err(|source| ApplyError::Parse { document, source })
+        })
+        .collect::<Result<Vec<GeneratedConfig>, ApplyError>>()?;
+    apply_generated(project, configs)
+}
+
+/// Apply generated configuration to a project.
+///
+/// Returns the combined project along with a report of what changed. If anything conflicts, all
+/// of the conflicts are returned instead.
+///
+/// * `project`: Project to apply the configuration to.
+/// * `configs`: Generated configuration, in the order `generate.tasks` would load it.
+pub fn apply_generated(
+    mut project: EvgProject,
+    configs: Vec<GeneratedConfig>,
+) -> Result<(EvgProject, MergeReport), ApplyError> {
+    let mut applier = Applier {
+        document: 0,
+        conflicts: vec![],
+        report: MergeReport::default(),
+    };
+    for (document, config) in configs.into_iter().enumerate() {
+        applier.document = document;
+        applier.apply(&mut project, config);
+    }
+
+    if applier.conflicts.is_empty() {
+        Ok((project, applier.report))
+    } else {
+        Err(ApplyError::Conflicts(applier.conflicts))
+    }
+}
+
+/// Track conflicts and changes while applying generated configuration.
+struct Applier {
+    document: usize,
+    conflicts: Vec<GenerateConflict>,
+    report: MergeReport,
+}
+
+impl Applier {
+    fn conflict(&mut self, field: &str, name: &str) {
+        self.conflicts.push(GenerateConflict {
+            document: self.document,
+            field: field.to_string(),
+            name: name.to_string(),
+        });
+    }
+
+    fn apply(&mut self, project: &mut EvgProject, config: GeneratedConfig) {
+        let GeneratedConfig {
+            buildvariants,
+            tasks,
+            functions,
+            task_groups,
+        } = config;
+
+        for task in tasks {
+            if project.tasks.iter().any(|t| t.name == task.name) {
+                self.conflict("tasks", &task.name);
+            } else {
+                self.report.added_tasks.push(task.name.to_string());
+                project.tasks.push(task);
+            }
+        }
+        for (name, function) in functions {
+            match project.functions.entry(name) {
+                Entry::Occupied(entry) => self.conflict("functions", entry.key()),
+                Entry::Vacant(entry) => {
+                    self.report.added_functions.push(entry.key().to_string());
+                    entry.insert(function);
+                }
+            }
+        }
+        let existing_task_groups = project.task_groups.get_or_insert_with(Vec::new);
+        for task_group in task_groups {
+            if existing_task_groups
+                .iter()
+                .any(|tg| tg.name == task_group.name)
+            {
+                self.conflict("task_groups", &task_group.name);
+            } else {
+                self.report
+                    .added_task_groups
+                    .push(task_group.name.to_string());
+                existing_task_groups.push(task_group);
+            }
+        }
+        if existing_task_groups.is_empty() {
+            project.task_groups = None;
+        }
+
+        for build_variant in buildvariants {
+            let existing = project
+                .build_variants_mut()
+                .find(|bv| bv.name == build_variant.name);
+            match existing {
+                Some(existing) => self.extend_variant(existing, build_variant),
+                None => {
+                    self.report
+                        .created_variants
+                        .push(build_variant.name.to_string());
+                    project.buildvariants.push(build_variant.into());
+                }
+            }
+        }
+    }
+
+    /// Add the tasks and display tasks of a generated build variant to an existing one.
+    fn extend_variant(&mut self, target: &mut BuildVariant, other: BuildVariant) {
+        let BuildVariant {
+            name,
+            tasks,
+            display_name,
+            run_on,
+            display_tasks,
+            batchtime,
+            expansions,
+            stepback,
+            modules,
+            activate,
+            tags,
+            extra,
+        } = other;
+
+        self.check_unchanged("display_name", &name, &target.display_name, display_name);
+        self.check_unchanged("run_on", &name, &target.run_on, run_on);
+        self.check_unchanged("batchtime", &name, &target.batchtime, batchtime);
+        self.check_unchanged("expansions", &name, &target.expansions, expansions);
+        self.check_unchanged("stepback", &name, &target.stepback, stepback);
+        self.check_unchanged("modules", &name, &target.modules, modules);
+        self.check_unchanged("activate", &name, &target.activate, activate);
+        self.check_unchanged("tags", &name, &target.tags, tags);
+        for (field, value) in extra {
+            let existing = target.extra.get(&field).cloned();
+            self.check_unchanged(&field, &name, &existing, Some(value));
+        }
+
+        for task_ref in tasks {
+            let entry = (name.to_string(), task_ref.name.to_string());
+            if target.tasks.iter().any(|t| t.name == task_ref.name) {
+                self.report.skipped_task_refs.push(entry);
+            } else {
+                self.report.added_task_refs.push(entry);
+                target.tasks.push(task_ref);
+            }
+        }
+
+        for display_task in display_tasks.into_iter().flatten() {
+            let existing_display_tasks = target.display_tasks.get_or_insert_with(Vec::new);
+            let entry = (name.to_string(), display_task.name.to_string());
+            match existing_display_tasks
+                .iter_mut()
+                .find(|dt| dt.name == display_task.name)
+            {
+                Some(existing) => {
+                    merge_display_task(existing, display_task);
+                    self.report.merged_display_tasks.push(entry);
+                }
+                None => {
+                    existing_display_tasks.push(display_task);
+                    self.report.added_display_tasks.push(entry);
+                }
+            }
+        }
+    }
+
+    /// Check that a generated build variant does not change a field of an existing one.
+    fn check_unchanged<T: PartialEq>(
+        &mut self,
+        field: &str,
+        variant: &str,
+        target: &Option<T>,
+        value: Option<T>,
+    ) {
+        if value.is_some() && &value != target {
+            self.conflict(&format!("buildvariants.{}", field), variant);
+        }
+    }
+}
+
+/// Add the execution tasks of a display task that are not already in an existing one.
+fn merge_display_task(target: &mut DisplayTask, other: DisplayTask) {
+    for task in other.execution_tasks {
+        if !target.execution_tasks.contains(&task) {
+            target.execution_tasks.push(task);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BASE: &str = r#"
+functions:
+  run:
+    command: shell.exec
+    params:
+      script: ./run.sh
+tasks:
+  - name: compile
+  - name: lint
+buildvariants:
+  - name: linux
+    run_on: ["ubuntu2004"]
+    tasks:
+      - name: compile
+      - name: lint
+    display_tasks:
+      - name: tests
+        execution_tasks: ["lint"]
+"#;
+
+    #[test]
+    fn test_apply_generated() {
+        let project = EvgProject::from_yaml_str(BASE).unwrap();
+        let first = r#"{
+            "tasks": [{"name": "test_0"}, {"name": "test_1"}],
+            "task_groups": [{"name": "tg", "tasks": ["test_1"]}],
+            "buildvariants": [
+                {"name": "linux", "run_on": ["ubuntu2004"],
+                 "tasks": [{"name": "compile"}, {"name": "test_0"}],
+                 "display_tasks": [{"name": "tests", "execution_tasks": ["test_0", "lint"]}]},
+                {"name": "windows", "tasks": [{"name": "test_1"}]}
+            ]
+        }"#;
+        let second = r#"{
+            "functions": {"setup": {"command": "shell.exec", "params": {"script": "setup"}}},
+            "buildvariants": [
+                {"name": "windows", "tasks": [{"name": "tg"}],
+                 "display_tasks": [{"name": "more", "execution_tasks": ["test_1"]}]}
+            ]
+        }"#;
+
+        let (project, report) = apply_generated_json(project, &[first, second]).unwrap();
+
+        let linux = &project.build_variant_map()["linux"];
+        let linux_tasks: Vec<&str> = linux.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(linux_tasks, vec!["compile", "lint", "test_0"]);
+        assert_eq!(
+            linux.display_tasks.as_ref().unwrap()[0].execution_tasks,
+            vec!["lint", "test_0"]
+        );
+        assert_eq!(project.build_variant_map()["windows"].tasks.len(), 2);
+        assert!(project.functions.contains_key("setup"));
+        assert_eq!(project.validate(), vec![]);
+
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(
+            report,
+            MergeReport {
+                added_tasks: vec!["test_0".to_string(), "test_1".to_string()],
+                added_functions: vec!["setup".to_string()],
+                added_task_groups: vec!["tg".to_string()],
+                created_variants: vec!["windows".to_string()],
+                added_task_refs: vec![pair("linux", "test_0"), pair("windows", "tg")],
+                skipped_task_refs: vec![pair("linux", "compile")],
+                added_display_tasks: vec![pair("windows", "more")],
+                merged_display_tasks: vec![pair("linux", "tests")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_redefinitions_are_rejected() {
+        let project = EvgProject::from_yaml_str(BASE).unwrap();
+        let first = r#"{
+            "tasks": [{"name": "compile"}, {"name": "new"}],
+            "functions": {
+                "run": {"command": "shell.exec", "params": {"script": "other"}},
+                "report": {"command": "shell.exec", "params": {"script": "report"}}
+            },
+            "buildvariants": [{"name": "linux", "run_on": ["rhel80"], "tasks": []}]
+        }"#;
+        let second = r#"{
+            "tasks": [{"name": "new"}],
+            "functions": {"report": {"command": "shell.exec", "params": {"script": "report"}}}
+        }"#;
+
+        let result = apply_generated_json(project, &[first, second]);
+
+        let conflicts: Vec<String> = match result {
+            Err(ApplyError::Conflicts(conflicts)) => {
+                conflicts.iter().map(|c| c.to_string()).collect()
+            }
+            other => panic!("Expected conflicts, got {:?}", other),
+        };
+        assert_eq!(
+            conflicts,
+            vec![
+                "generated document 0: tasks 'compile' is already defined",
+                "generated document 0: functions 'run' is already defined",
+                "generated document 0: buildvariants.run_on 'linux' is already defined",
+                "generated document 1: tasks 'new' is already defined",
+                "generated document 1: functions 'report' is already defined",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_documents_are_rejected() {
+        let project = EvgProject::from_yaml_str(BASE).unwrap();
+
+        let result = apply_generated_json(project, &["{}", r#"{"pre": []}"#]);
+
+        assert!(matches!(result, Err(ApplyError::Parse { document: 1, .. })));
+    }
+}
@@ -0,0 +1,637 @@
+//! Split generated configuration across several `generate.tasks` files.
+//!
+//! Evergreen limits how large a `generate.tasks` file can be, so large configurations need to be
+//! split into several files. Evergreen applies the files together, but each is kept as
+//! consistent on its own as it can be:
+//!
+//! * The tasks of a task group are in the same file as the task group.
+//! * The execution tasks of a display task are in the same file as the display task.
+//! * Each function is defined once, in the first file with a task or task group that calls it,
+//!   since a function can't be defined by more than one file. Functions that aren't called are
+//!   put in the first file.
+//! * A build variant is in every file that has tasks or display tasks for it, with only those
+//!   tasks and display tasks. Its other fields are repeated in each file.
+//!
+//! Tasks that have to be kept together are never split across files, so limits that are smaller
+//! than any of those sets of tasks can't be met.
+use super::{GenerateError, GeneratedConfig};
+use crate::models::commands::EvgCommand;
+use crate::models::project::EvgProject;
+use crate::models::task_group::EvgTaskGroup;
+use crate::models::variant::BuildVariant;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use thiserror::Error;
+
+/// Upper bound on the size of the JSON around the top level fields of a config.
+const CONFIG_OVERHEAD_BYTES: usize = 128;
+/// Upper bound on the size of the JSON around the tasks and display tasks of a build variant.
+const VARIANT_OVERHEAD_BYTES: usize = 64;
+
+/// Errors that can occur while splitting generated configuration.
+#[derive(Debug, Error)]
+pub enum ShardError {
+    /// Tasks that have to be in the same file are larger than the limits.
+    #[error("{} must be in the same file but do not fit in the limits", .0.join(", "))]
+    TooLarge(Vec<String>),
+
+    /// The configuration could not be built or measured.
+    #[error(transparent)]
+    Generate(#[from] GenerateError),
+}
+
+/// Limits on each generated file.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ShardLimits {
+    /// Largest a file can be, in bytes of JSON as written by [`GeneratedConfig::to_json_string`].
+    pub max_bytes: Option<usize>,
+    /// Most tasks that can be defined in a file.
+    pub max_tasks: Option<usize>,
+}
+
+impl ShardLimits {
+    fn allows(&self, bytes: usize, tasks: usize) -> bool {
+        self.max_bytes.is_none_or(|max| bytes <= max)
+            && self.max_tasks.is_none_or(|max| tasks <= max)
+    }
+}
+
+/// Split a generated project into configs that are each within the given limits.
+///
+/// Fails if the project contains anything that can't be generated.
+///
+/// * `project`: Project to split.
+/// * `limits`: Limits on each config.
+pub fn shard_project(
+    project: &EvgProject,
+    limits: &ShardLimits,
+) -> Result<Vec<GeneratedConfig>, ShardError> {
+    shard(&GeneratedConfig::from_project(project)?, limits)
+}
+
+/// Split generated configuration into configs that are each within the given limits.
+///
+/// Tasks are kept in the order they are defined in, and each config is filled before starting
+/// the next. At least one config is always returned.
+///
+/// * `config`: Configuration to split.
+/// * `limits`: Limits on each config.
+pub fn shard(
+    config: &GeneratedConfig,
+    limits: &ShardLimits,
+) -> Result<Vec<GeneratedConfig>, ShardError> {
+    let units = Units::new(config);
+    let costs = units.costs(config)?;
+    let function_sizes = config
+        .functions
+        .iter()
+        .map(|(name, function)| Ok(nested_size(function, 2)? + name.len() + 4))
+        .collect::<Result<Vec<usize>, GenerateError>>()?;
+    let headers = config
+        .buildvariants
+        .iter()
+        .map(|bv| {
+            let header = BuildVariant {
+                tasks: vec![],
+                display_tasks: None,
+                ..bv.clone()
+            };
+            Ok(nested_size(&header, 2)? + VARIANT_OVERHEAD_BYTES)
+        })
+        .collect::<Result<Vec<usize>, GenerateError>>()?;
+
+    let mut shards: Vec<Shard> = vec![];
+    let mut current = Shard::default();
+    let mut bytes = CONFIG_OVERHEAD_BYTES;
+    let mut tasks = 0;
+    let mut variants: BTreeSet<usize> = BTreeSet::new();
+    // Functions defined by any file so far, which later files must not define again.
+    let mut defined: BTreeSet<usize> = BTreeSet::new();
+    for (unit, cost) in costs.iter().enumerate() {
+        let new_functions: BTreeSet<usize> = cost.functions.difference(&defined).copied().collect();
+        let added_bytes = |variants: &BTreeSet<usize>| {
+            let new_headers: usize = cost
+                .variants
+                .difference(variants)
+                .map(|v| headers[*v])
+                .sum();
+            let function_bytes: usize = new_functions.iter().map(|f| function_sizes[*f]).sum();
+            cost.bytes + new_headers + function_bytes
+        };
+        let added = added_bytes(&variants);
+        if !current.units.is_empty() && !limits.allows(bytes + added, tasks + cost.tasks) {
+            shards.push(std::mem::take(&mut current));
+            bytes = CONFIG_OVERHEAD_BYTES;
+            tasks = 0;
+            variants.clear();
+        }
+        bytes += added_bytes(&variants);
+        tasks += cost.tasks;
+        if current.units.is_empty() && !limits.allows(bytes, tasks) {
+            return Err(ShardError::TooLarge(units.names[unit].clone()));
+        }
+        variants.extend(cost.variants.iter().copied());
+        defined.extend(new_functions.iter().copied());
+        current.functions.extend(new_functions);
+        current.units.push(unit);
+    }
+    if !current.units.is_empty() || shards.is_empty() {
+        shards.push(current);
+    }
+
+    Ok(shards
+        .iter()
+        .enumerate()
+        .map(|(index, shard)| units.build(config, shard, index == 0))
+        .collect())
+}
+
+/// The units and functions that go in one file.
+#[derive(Default)]
+struct Shard {
+    units: Vec<usize>,
+    /// Functions defined in the file, as indexes into the config's functions.
+    functions: BTreeSet<usize>,
+}
+
+/// Something that has to be kept in the same file as everything connected to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node<'a> {
+    /// A task or task group, which share names.
+    Task(&'a str),
+    /// A display task on the build variant at the given index.
+    Display(usize, &'a str),
+}
+
+/// Groups of the parts of a config that have to be kept in the same file.
+struct Units {
+    /// Unit of each task definition.
+    tasks: Vec<usize>,
+    /// Unit of each task group definition.
+    task_groups: Vec<usize>,
+    /// Functions called by each unit, as indexes into the config's functions.
+    functions: Vec<BTreeSet<usize>>,
+    /// Unit of each task on each build variant.
+    task_refs: Vec<Vec<usize>>,
+    /// Unit of each display task on each build variant.
+    display_tasks: Vec<Vec<usize>>,
+    /// Names of the tasks and task groups in each unit.
+    names: Vec<Vec<String>>,
+}
+
+/// Amount each unit adds to a file.
+struct UnitCost {
+    bytes: usize,
+    tasks: usize,
+    /// Build variants the unit has tasks or display tasks on.
+    variants: BTreeSet<usize>,
+    /// Functions the unit calls.
+    functions: BTreeSet<usize>,
+}
+
+impl Units {
+    fn new(config: &GeneratedConfig) -> Self {
+        let mut sets = DisjointSets::default();
+        for task in &config.tasks {
+            sets.find(Node::Task(&task.name));
+        }
+        for task_group in &config.task_groups {
+            let node = sets.find(Node::Task(&task_group.name));
+            for task in &task_group.tasks {
+                sets.union(node, Node::Task(task));
+            }
+        }
+        for (index, build_variant) in config.buildvariants.iter().enumerate() {
+            for task_ref in &build_variant.tasks {
+                sets.find(Node::Task(&task_ref.name));
+            }
+            for display_task in build_variant.display_tasks.iter().flatten() {
+                let node = sets.find(Node::Display(index, &display_task.name));
+                for task in &display_task.execution_tasks {
+                    sets.union(node, Node::Task(task));
+                }
+            }
+        }
+
+        // Number the units in the order they are first seen, so files follow the config.
+        let mut numbers: HashMap<usize, usize> = HashMap::new();
+        let mut names: Vec<Vec<String>> = vec![];
+        let mut unit_of = |node, name: Option<&str>| {
+            let index = sets.find(node);
+            let root = sets.root_of(index);
+            let next = numbers.len();
+            let unit = *numbers.entry(root).or_insert(next);
+            if unit == names.len() {
+                names.push(vec![]);
+            }
+            if let Some(name) = name {
+                names[unit].push(name.to_string());
+            }
+            unit
+        };
+        let tasks: Vec<usize> = config
+            .tasks
+            .iter()
+            .map(|t| unit_of(Node::Task(&t.name), Some(&t.name)))
+            .collect();
+        let task_groups: Vec<usize> = config
+            .task_groups
+            .iter()
+            .map(|tg| unit_of(Node::Task(&tg.name), Some(&tg.name)))
+            .collect();
+        let task_refs = config
+            .buildvariants
+            .iter()
+            .map(|bv| {
+                bv.tasks
+                    .iter()
+                    .map(|t| unit_of(Node::Task(&t.name), None))
+                    .collect()
+            })
+            .collect();
+        let display_tasks = config
+            .buildvariants
+            .iter()
+            .enumerate()
+            .map(|(index, bv)| {
+                bv.display_tasks
+                    .iter()
+                    .flatten()
+                    .map(|dt| unit_of(Node::Display(index, &dt.name), None))
+                    .collect()
+            })
+            .collect();
+
+        let mut functions = vec![BTreeSet::new(); names.len()];
+        for (task, unit) in config.tasks.iter().zip(&tasks) {
+            functions[*unit].extend(called_functions(config, task.commands.iter().flatten()));
+        }
+        for (task_group, unit) in config.task_groups.iter().zip(&task_groups) {
+            functions[*unit].extend(called_functions(config, task_group_commands(task_group)));
+        }
+
+        Units {
+            tasks,
+            task_groups,
+            functions,
+            task_refs,
+            display_tasks,
+            names,
+        }
+    }
+
+    fn costs(&self, config: &GeneratedConfig) -> Result<Vec<UnitCost>, GenerateError> {
+        let mut costs: Vec<UnitCost> = self
+            .functions
+            .iter()
+            .map(|functions| UnitCost {
+                bytes: 0,
+                tasks: 0,
+                variants: BTreeSet::new(),
+                functions: functions.clone(),
+            })
+            .collect();
+        for (task, unit) in config.tasks.iter().zip(&self.tasks) {
+            costs[*unit].bytes += nested_size(task, 2)?;
+            costs[*unit].tasks += 1;
+        }
+        for (task_group, unit) in config.task_groups.iter().zip(&self.task_groups) {
+            costs[*unit].bytes += nested_size(task_group, 2)?;
+        }
+        for (index, build_variant) in config.buildvariants.iter().enumerate() {
+            for (task_ref, unit) in build_variant.tasks.iter().zip(&self.task_refs[index]) {
+                costs[*unit].bytes += nested_size(task_ref, 4)?;
+                costs[*unit].variants.insert(index);
+            }
+            let display_tasks = build_variant.display_tasks.iter().flatten();
+            for (display_task, unit) in display_tasks.zip(&self.display_tasks[index]) {
+                costs[*unit].bytes += nested_size(display_task, 4)?;
+                costs[*unit].variants.insert(index);
+            }
+        }
+        Ok(costs)
+    }
+
+    /// Build the config holding the given shard.
+    ///
+    /// Build variants without any tasks or display tasks and functions that aren't called are
+    /// put in the first config.
+    fn build(&self, config: &GeneratedConfig, shard: &Shard, first: bool) -> GeneratedConfig {
+        let keep = |unit: &usize| shard.units.contains(unit);
+        let called: BTreeSet<usize> = self.functions.iter().flatten().copied().collect();
+        let mut functions = shard.functions.clone();
+        if first {
+            functions.extend((0..config.functions.len()).filter(|f| !called.contains(f)));
+        }
+        let buildvariants = config
+            .buildvariants
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bv)| {
+                let tasks: Vec<_> = bv
+                    .tasks
+                    .iter()
+                    .zip(&self.task_refs[index])
+                    .filter(|(_, unit)| keep(unit))
+                    .map(|(task_ref, _)| task_ref.clone())
+                    .collect();
+                let display_tasks: Vec<_> = bv
+                    .display_tasks
+                    .iter()
+                    .flatten()
+                    .zip(&self.display_tasks[index])
+                    .filter(|(_, unit)| keep(unit))
+                    .map(|(display_task, _)| display_task.clone())
+                    .collect();
+                let is_empty =
+                    self.task_refs[index].is_empty() && self.display_tasks[index].is_empty();
+                if tasks.is_empty() && display_tasks.is_empty() && !(first && is_empty) {
+                    return None;
+                }
+                Some(BuildVariant {
+                    tasks,
+                    display_tasks: if display_tasks.is_empty() {
+                        None
+                    } else {
+                        Some(display_tasks)
+                    },
+                    ..bv.clone()
+                })
+            })
+            .collect();
+
+        GeneratedConfig {
+            buildvariants,
+            tasks: config
+                .tasks
+                .iter()
+                .zip(&self.tasks)
+                .filter(|(_, unit)| keep(unit))
+                .map(|(task, _)| task.clone())
+                .collect(),
+            functions: config
+                .functions
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| functions.contains(index))
+                .map(|(_, (name, function))| (name.to_string(), function.clone()))
+                .collect(),
+            task_groups: config
+                .task_groups
+                .iter()
+                .zip(&self.task_groups)
+                .filter(|(_, unit)| keep(unit))
+                .map(|(task_group, _)| task_group.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Get the indexes of the functions defined in the config that the given commands call.
+fn called_functions<'a>(
+    config: &GeneratedConfig,
+    commands: impl Iterator<Item = &'a EvgCommand>,
+) -> Vec<usize> {
+    commands
+        .filter_map(|command| match command {
+            EvgCommand::Function(call) => config.functions.keys().position(|f| *f == call.func),
+            _ => None,
+        })
+        .collect()
+}
+
+fn task_group_commands(task_group: &EvgTaskGroup) -> impl Iterator<Item = &EvgCommand> {
+    vec![
+        &task_group.setup_group,
+        &task_group.teardown_group,
+        &task_group.setup_task,
+        &task_group.teardown_task,
+        &task_group.timeout,
+    ]
+    .into_iter()
+    .flatten()
+    .flatten()
+}
+
+/// Upper bound on the size of a value written as an element of a pretty printed JSON array or
+/// object, nested the given number of levels deep.
+fn nested_size<T: Serialize>(value: &T, depth: usize) -> Result<usize, GenerateError> {
+    let json = serde_json::to_string_pretty(value)?;
+    let lines = json.matches('\n').count() + 1;
+    // Each line is indented two spaces per level, plus a newline and comma to separate it.
+    Ok(json.len() + lines * depth * 2 + 2)
+}
+
+/// Disjoint sets of nodes, to find the nodes connected to each other.
+#[derive(Default)]
+struct DisjointSets<'a> {
+    index: HashMap<Node<'a>, usize>,
+    parent: Vec<usize>,
+}
+
+impl<'a> DisjointSets<'a> {
+    /// Get the index of a node, adding it as a set of its own if it is new.
+    fn find(&mut self, node: Node<'a>) -> usize {
+        let next = self.parent.len();
+        let index = *self.index.entry(node).or_insert(next);
+        if index == next {
+            self.parent.push(index);
+        }
+        index
+    }
+
+    fn union(&mut self, index: usize, node: Node<'a>) {
+        let other = self.find(node);
+        let (a, b) = (self.root_of(index), self.root_of(other));
+        self.parent[b] = a;
+    }
+
+    fn root_of(&mut self, mut index: usize) -> usize {
+        while self.parent[index] != index {
+            self.parent[index] = self.parent[self.parent[index]];
+            index = self.parent[index];
+        }
+        index
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::generate::apply::apply_generated;
+    use std::collections::HashSet;
+
+    fn project(tasks: usize) -> EvgProject {
+        let mut yaml = String::from(
+            r#"
+functions:
+  setup:
+    command: shell.exec
+    params:
+      script: ./setup.sh
+task_groups:
+  - name: group
+    setup_group:
+      - func: setup
+    tasks: ["grouped_0", "grouped_1"]
+tasks:
+  - name: grouped_0
+  - name: grouped_1
+"#,
+        );
+        let mut refs = String::new();
+        for i in 0..tasks {
+            yaml.push_str(&format!("  - name: task_{}\n", i));
+            refs.push_str(&format!("      - name: task_{}\n", i));
+        }
+        yaml.push_str(&format!(
+            r#"buildvariants:
+  - name: linux
+    run_on: ["ubuntu2004"]
+    tasks:
+      - name: group
+{}    display_tasks:
+      - name: display
+        execution_tasks: ["task_0", "task_3"]
+  - name: empty
+    display_name: Empty
+    tasks: []
+"#,
+            refs
+        ));
+        EvgProject::from_yaml_str(&yaml).unwrap()
+    }
+
+    fn task_names(config: &GeneratedConfig) -> Vec<&str> {
+        config.tasks.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_shard_by_task_count() {
+        let limits = ShardLimits {
+            max_tasks: Some(3),
+            ..Default::default()
+        };
+
+        let shards = shard_project(&project(6), &limits).unwrap();
+
+        let names: Vec<Vec<&str>> = shards.iter().map(task_names).collect();
+        assert_eq!(
+            names,
+            vec![
+                vec!["grouped_0", "grouped_1"],
+                vec!["task_0", "task_1", "task_3"],
+                vec!["task_2", "task_4", "task_5"],
+            ]
+        );
+        assert!(shards[0].functions.contains_key("setup"));
+        assert_eq!(shards[0].task_groups[0].name, "group");
+        let variants: Vec<&str> = shards[0]
+            .buildvariants
+            .iter()
+            .map(|bv| bv.name.as_str())
+            .collect();
+        assert_eq!(variants, vec!["linux", "empty"]);
+        let linux = &shards[1].buildvariants[0];
+        assert_eq!(linux.run_on, Some(vec!["ubuntu2004".to_string()]));
+        assert_eq!(linux.display_tasks.as_ref().unwrap()[0].name, "display");
+        assert_eq!(shards[2].buildvariants.len(), 1);
+        assert!(shards[2].buildvariants[0].display_tasks.is_none());
+    }
+
+    #[test]
+    fn test_shard_by_size() {
+        let project = project(200);
+        let limits = ShardLimits {
+            max_bytes: Some(2_000),
+            ..Default::default()
+        };
+
+        let shards = shard_project(&project, &limits).unwrap();
+
+        assert!(shards.len() > 1);
+        for shard in &shards {
+            assert!(shard.to_json_string().unwrap().len() <= 2_000);
+            let defined: HashSet<&str> = task_names(shard).into_iter().collect();
+            for task_ref in &shard.buildvariants[0].tasks {
+                let is_group = shard.task_groups.iter().any(|tg| tg.name == task_ref.name);
+                assert!(is_group || defined.contains(task_ref.name.as_str()));
+            }
+        }
+
+        let base = EvgProject::from_yaml_str("buildvariants: []\ntasks: []").unwrap();
+        let (applied, _) = apply_generated(base, shards).unwrap();
+        assert_eq!(applied.tasks.len(), project.tasks.len());
+        assert_eq!(applied.buildvariants.len(), 2);
+        assert_eq!(
+            applied.buildvariants[0]
+                .build_variant()
+                .unwrap()
+                .tasks
+                .len(),
+            201
+        );
+        assert!(applied.validate().is_empty());
+    }
+
+    #[test]
+    fn test_tasks_calling_the_same_function_can_be_split() {
+        let mut yaml = String::from(
+            r#"
+functions:
+  run tests:
+    command: shell.exec
+    params:
+      script: ./run.sh
+  unused:
+    command: shell.exec
+    params:
+      script: ./unused.sh
+tasks:
+"#,
+        );
+        for i in 0..10 {
+            yaml.push_str(&format!(
+                "  - name: t{}\n    commands:\n      - func: run tests\n",
+                i
+            ));
+        }
+        yaml.push_str("buildvariants: []\n");
+        let project = EvgProject::from_yaml_str(&yaml).unwrap();
+        let limits = ShardLimits {
+            max_tasks: Some(5),
+            ..Default::default()
+        };
+
+        let shards = shard_project(&project, &limits).unwrap();
+
+        assert_eq!(shards.len(), 2);
+        let functions: Vec<Vec<&str>> = shards
+            .iter()
+            .map(|s| s.functions.keys().map(|f| f.as_str()).collect())
+            .collect();
+        assert_eq!(functions, vec![vec!["run tests", "unused"], vec![]]);
+        let base = EvgProject::from_yaml_str("buildvariants: []\ntasks: []").unwrap();
+        let (applied, report) = apply_generated(base, shards).unwrap();
+        assert_eq!(applied.tasks.len(), 10);
+        assert_eq!(report.added_functions.len(), 2);
+        assert!(applied.validate().is_empty());
+    }
+
+    #[test]
+    fn test_shard_too_large() {
+        let limits = ShardLimits {
+            max_tasks: Some(1),
+            ..Default::default()
+        };
+
+        let result = shard_project(&project(1), &limits);
+
+        match result {
+            Err(ShardError::TooLarge(names)) => {
+                assert_eq!(names, vec!["grouped_0", "grouped_1", "group"])
+            }
+            other => panic!("Expected shard to be too large, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Split a suite of tests into sub-tasks that take about the same time to run.
+//!
+//! The runtime of each test is usually taken from Evergreen's test statistics, saved to a local
+//! file as either JSON or CSV:
+//!
+//! ```json
+//! [
+//!   {"test_file": "jstests/core/find.js", "avg_duration_pass": 12.5},
+//!   {"test_file": "jstests/core/update.js", "avg_duration_pass": 3.2}
+//! ]
+//! ```
+//!
+//! ```text
+//! test_file,avg_duration_pass
+//! jstests/core/find.js,12.5
+//! jstests/core/update.js,3.2
+//! ```
+use crate::models::commands::EvgCommand;
+use crate::models::params::ParamValue;
+use crate::models::task::{EvgTask, TaskRef};
+use crate::models::variant::{BuildVariant, DisplayTask};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors that can occur while reading test runtimes.
+#[derive(Debug, Error)]
+pub enum SplitError {
+    /// The runtimes file could not be read.
+    #[error("Could not read '{}': {source}", .path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Error reading the file.
+        source: io::Error,
+    },
+
+    /// The runtimes file is not valid JSON.
+    #[error("Invalid test runtimes: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// The runtimes file is not valid CSV.
+    #[error("Invalid test runtimes: {0}")]
+    Csv(#[from] csv::Error),
+
+    /// The runtimes file is neither JSON nor CSV.
+    #[error("Unknown test runtimes format: '{}'", .0.display())]
+    UnknownFormat(PathBuf),
+}
+
+/// How long a test usually takes to run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestRuntime {
+    /// Name of the test.
+    pub name: String,
+    /// How long the test takes to run.
+    pub runtime: Duration,
+}
+
+impl TestRuntime {
+    /// Create the runtime of a test.
+    pub fn new(name: &str, runtime: Duration) -> Self {
+        TestRuntime {
+            name: name.to_string(),
+            runtime,
+        }
+    }
+}
+
+/// Runtime of a test as it is stored in files.
+#[derive(Debug, Deserialize)]
+struct RuntimeRecord {
+    test_file: String,
+    avg_duration_pass: f64,
+}
+
+impl From<RuntimeRecord> for TestRuntime {
+    /// Negative or invalid runtimes are treated as zero.
+    fn from(record: RuntimeRecord) -> Self {
+        TestRuntime {
+            name: record.test_file,
+            runtime: Duration::try_from_secs_f64(record.avg_duration_pass).unwrap_or_default(),
+        }
+    }
+}
+
+/// Read test runtimes from a JSON or CSV file, depending on its extension.
+///
+/// * `path`: Path of the file to read.
+pub fn read_runtimes(path: &Path) -> Result<Vec<TestRuntime>, SplitError> {
+    let parse = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => runtimes_from_json_str,
+        Some("csv") => runtimes_from_csv_str,
+        _ => return Err(SplitError::UnknownFormat(path.to_path_buf())),
+    };
+    let contents = fs::read_to_string(path).map_err(|source| SplitError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(&contents)
+}
+
+/// Parse test runtimes from JSON.
+pub fn runtimes_from_json_str(json: &str) -> Result<Vec<TestRuntime>, SplitError> {
+    let records: Vec<RuntimeRecord> = serde_json::from_str(json)?;
+    Ok(records.into_iter().map(TestRuntime::from).collect())
+}
+
+/// Parse test runtimes from CSV with a header row.
+pub fn runtimes_from_csv_str(csv: &str) -> Result<Vec<TestRuntime>, SplitError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv.as_bytes());
+    let records = reader
+        .deserialize::<RuntimeRecord>()
+        .collect::<Result<Vec<RuntimeRecord>, csv::Error>>()?;
+    Ok(records.into_iter().map(TestRuntime::from).collect())
+}
+
+/// How many sub-tasks to split tests into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitTarget {
+    /// Create as many sub-tasks as needed for each to take about this long.
+    Runtime(Duration),
+    /// Create this many sub-tasks.
+    Count(usize),
+}
+
+/// Sub-tasks created by splitting tests.
+#[derive(Debug, Clone)]
+pub struct SplitTasks {
+    /// Sub-tasks that each run some of the tests.
+    pub tasks: Vec<EvgTask>,
+    /// Expected runtime of each sub-task.
+    pub runtimes: Vec<Duration>,
+    /// Display task grouping the sub-tasks.
+    pub display_task: DisplayTask,
+    /// References to add the sub-tasks to a build variant.
+    pub task_refs: Vec<TaskRef>,
+}
+
+impl SplitTasks {
+    /// Add the sub-tasks and their display task to a build variant.
+    pub fn add_to_variant(&self, build_variant: &mut BuildVariant) {
+        build_variant.tasks.extend(self.task_refs.iter().cloned());
+        build_variant
+            .display_tasks
+            .get_or_insert_with(Vec::new)
+            .push(self.display_task.clone());
+    }
+}
+
+/// Split tests into sub-tasks of the template task so each takes about the same time.
+///
+/// Sub-tasks are copies of the template named `<template>_<index>`. Every function call in them
+/// that sets the `tests_var` variable has it replaced by the names of its tests, separated by
+/// spaces; other function calls are left as they are. Tests are given to sub-tasks longest
+/// first, each to the sub-task with the least runtime so far (or the fewest tests, if runtimes
+/// are tied), and keep their original order within a sub-task. Every sub-task is given at least
+/// one test, so there are no more sub-tasks than tests. The display task is named
+/// `<template>_display` so it does not collide with the template task.
+///
+/// * `template`: Task to create sub-tasks of.
+/// * `tests`: Tests to split, with their runtimes.
+/// * `target`: How many sub-tasks to create.
+/// * `tests_var`: Name of the function variable to give the tests to.
+pub fn split_tests(
+    template: &EvgTask,
+    tests: &[TestRuntime],
+    target: SplitTarget,
+    tests_var: &str,
+) -> SplitTasks {
+    let count = match target {
+        SplitTarget::Count(count) => count,
+        SplitTarget::Runtime(runtime) if runtime.is_zero() => tests.len(),
+        SplitTarget::Runtime(runtime) => {
+            let total: Duration = tests.iter().map(|t| t.runtime).sum();
+            (total.as_secs_f64() / runtime.as_secs_f64()).ceil() as usize
+        }
+    }
+    .max(1)
+    .min(tests.len());
+
+    let mut order: Vec<usize> = (0..tests.len()).collect();
+    order.sort_by(|a, b| tests[*b].runtime.cmp(&tests[*a].runtime));
+    let mut runtimes = vec![Duration::default(); count];
+    let mut assigned: Vec<Vec<usize>> = vec![vec![]; count];
+    for index in order {
+        let (shortest, _) = runtimes
+            .iter()
+            .enumerate()
+            .min_by_key(|(i, runtime)| (**runtime, assigned[*i].len()))
+            .unwrap();
+        runtimes[shortest] += tests[index].runtime;
+        assigned[shortest].push(index);
+    }
+
+    let tasks: Vec<EvgTask> = assigned
+        .iter_mut()
+        .enumerate()
+        .map(|(index, indexes)| {
+            indexes.sort_unstable();
+            let names: Vec<&str> = indexes.iter().map(|i| tests[*i].name.as_str()).collect();
+            sub_task(template, index, tests_var, &names.join(" "))
+        })
+        .collect();
+
+    SplitTasks {
+        display_task: DisplayTask {
+            name: format!("{}_display", template.name),
+            execution_tasks: tasks.iter().map(|t| t.name.to_string()).collect(),
+            extra: BTreeMap::new(),
+        },
+        task_refs: tasks.iter().map(|t| t.get_reference(None, None)).collect(),
+        tasks,
+        runtimes,
+    }
+}
+
+/// Create a copy of the template that runs the given tests.
+fn sub_task(template: &EvgTask, index: usize, tests_var: &str, tests: &str) -> EvgTask {
+    let mut task = template.clone();
+    task.name = format!("{}_{}", template.name, index);
+    for command in task.commands.iter_mut().flatten() {
+        if let EvgCommand::Function(call) = command {
+            if let Some(value) = call.vars.as_mut().and_then(|vars| vars.get_mut(tests_var)) {
+                *value = ParamValue::String(tests.to_string());
+            }
+        }
+    }
+    task
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::commands::{fn_call, fn_call_with_params};
+    use std::collections::HashMap;
+
+    fn template() -> EvgTask {
+        let mut vars = HashMap::new();
+        vars.insert("suite".to_string(), ParamValue::String("core".to_string()));
+        vars.insert("tests".to_string(), ParamValue::String(String::new()));
+        EvgTask {
+            name: "core".to_string(),
+            commands: Some(vec![
+                fn_call("setup"),
+                fn_call_with_params("run tests", vars),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn tests(runtimes: &[u64]) -> Vec<TestRuntime> {
+        runtimes
+            .iter()
+            .enumerate()
+            .map(|(i, secs)| TestRuntime::new(&format!("test_{}", i), Duration::from_secs(*secs)))
+            .collect()
+    }
+
+    fn tests_of(task: &EvgTask) -> String {
+        match &task.commands.as_ref().unwrap()[1] {
+            EvgCommand::Function(call) => call.vars.as_ref().unwrap()["tests"].to_string(),
+            _ => panic!("Expected a function call"),
+        }
+    }
+
+    #[test]
+    fn test_split_by_count() {
+        let split = split_tests(
+            &template(),
+            &tests(&[10, 60, 20, 30, 40]),
+            SplitTarget::Count(2),
+            "tests",
+        );
+
+        let names: Vec<&str> = split.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["core_0", "core_1"]);
+        assert_eq!(tests_of(&split.tasks[0]), "test_1 test_2");
+        assert_eq!(tests_of(&split.tasks[1]), "test_0 test_3 test_4");
+        assert_eq!(
+            split.runtimes,
+            vec![Duration::from_secs(80), Duration::from_secs(80)]
+        );
+        match &split.tasks[0].commands.as_ref().unwrap()[0] {
+            EvgCommand::Function(call) => assert!(call.vars.is_none()),
+            _ => panic!("Expected a function call"),
+        }
+        assert_eq!(split.display_task.name, "core_display");
+        assert_eq!(split.display_task.execution_tasks, names);
+
+        let mut build_variant = BuildVariant::default();
+        split.add_to_variant(&mut build_variant);
+        assert_eq!(build_variant.tasks[1].name, "core_1");
+        assert_eq!(build_variant.display_tasks.unwrap()[0].name, "core_display");
+    }
+
+    #[test]
+    fn test_split_by_runtime() {
+        let tests = tests(&[30, 30, 30, 30, 30]);
+
+        let by_runtime = |secs| {
+            split_tests(
+                &template(),
+                &tests,
+                SplitTarget::Runtime(Duration::from_secs(secs)),
+                "tests",
+            )
+            .tasks
+            .len()
+        };
+
+        assert_eq!(by_runtime(60), 3);
+        assert_eq!(by_runtime(1), 5);
+        assert_eq!(by_runtime(0), 5);
+        assert_eq!(by_runtime(1000), 1);
+    }
+
+    #[test]
+    fn test_tests_without_runtimes_are_spread_out() {
+        let split = split_tests(
+            &template(),
+            &tests(&[0, 0, 0]),
+            SplitTarget::Count(3),
+            "tests",
+        );
+
+        let tests: Vec<String> = split.tasks.iter().map(tests_of).collect();
+        assert_eq!(tests, vec!["test_0", "test_1", "test_2"]);
+    }
+
+    #[test]
+    fn test_no_empty_sub_tasks_are_created() {
+        let more_tasks_than_tests =
+            split_tests(&template(), &tests(&[5, 0]), SplitTarget::Count(4), "tests");
+        let no_tests = split_tests(&template(), &[], SplitTarget::Count(4), "tests");
+
+        let tests: Vec<String> = more_tasks_than_tests.tasks.iter().map(tests_of).collect();
+        assert_eq!(tests, vec!["test_0", "test_1"]);
+        assert!(no_tests.tasks.is_empty());
+        assert!(no_tests.display_task.execution_tasks.is_empty());
+    }
+
+    #[test]
+    fn test_read_runtimes() {
+        let json = r#"[{"test_file": "a.js", "avg_duration_pass": 1.5}]"#;
+        let csv = "test_file, avg_duration_pass\na.js, 1.5\nb.js,-2\n";
+
+        let from_json = runtimes_from_json_str(json).unwrap();
+        let from_csv = runtimes_from_csv_str(csv).unwrap();
+
+        assert_eq!(
+            from_json,
+            vec![TestRuntime::new("a.js", Duration::from_millis(1500))]
+        );
+        assert_eq!(from_csv[0], from_json[0]);
+        assert_eq!(from_csv[1].runtime, Duration::default());
+        assert!(matches!(
+            read_runtimes(Path::new("runtimes.txt")),
+            Err(SplitError::UnknownFormat(_))
+        ));
+        assert!(matches!(
+            read_runtimes(Path::new("missing_runtimes.json")),
+            Err(SplitError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,252 @@
+//! Create many similar tasks from a template.
+//!
+//! A [`TaskTemplate`] is parameterised by a matrix of values, and a task is created for each
+//! combination of them. Parameters are referenced in the template like expansions, so a template
+//! named `test_${os}` with `os` values `linux` and `windows` creates `test_linux` and
+//! `test_windows`.
+//!
+//! References to anything that is not a parameter are left as they are, so they can still be
+//! expanded by Evergreen when the task runs.
+use crate::interpolate::{InterpolationError, Interpolator};
+use crate::models::commands::EvgCommand;
+use crate::models::task::{EvgTask, TaskDependency, TaskRef};
+use crate::models::task_group::EvgTaskGroup;
+use crate::models::variant::DisplayTask;
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+
+/// Errors that can occur while creating tasks from a template.
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    /// Several combinations of parameters create tasks with the same name.
+    #[error("Task '{0}' is created more than once, its name must use every parameter")]
+    DuplicateName(String),
+
+    /// Parameters could not be substituted into the template.
+    #[error(transparent)]
+    Interpolation(#[from] InterpolationError),
+}
+
+/// Template of tasks that differ only by their parameters.
+#[derive(Debug, Clone, Default)]
+pub struct TaskTemplate {
+    /// Pattern of the names of tasks, which needs to reference each parameter.
+    pub name: String,
+    /// Commands each task runs.
+    pub commands: Vec<EvgCommand>,
+    /// Tags to add to each task.
+    pub tags: Vec<String>,
+    /// Tasks each task depends on.
+    pub depends_on: Vec<TaskDependency>,
+    /// Name and possible values of each parameter.
+    pub matrix: Vec<(String, Vec<String>)>,
+}
+
+/// Tasks created from a template.
+#[derive(Debug, Clone)]
+pub struct TemplateTasks {
+    /// Created tasks.
+    pub tasks: Vec<EvgTask>,
+    /// Parameters each task was created with.
+    pub parameters: Vec<BTreeMap<String, String>>,
+}
+
+impl TaskTemplate {
+    /// Create a task for each combination of parameters.
+    ///
+    /// Parameters are substituted into the name, commands, tags and dependencies of the template.
+    /// Each task is also tagged with `<parameter>_<value>` for each of its parameters, so they
+    /// can be selected with tag selectors. Combinations are in the order of the matrix, with the
+    /// values of the last parameter changing first.
+    pub fn instantiate(&self) -> Result<TemplateTasks, TemplateError> {
+        let mut names = HashSet::new();
+        let mut tasks = vec![];
+        let combinations = self.combinations();
+        for parameters in &combinations {
+            let interpolator = Interpolator::partial(parameters);
+            let name = interpolator.interpolate_str(&self.name).value;
+            if !names.insert(name.to_string()) {
+                return Err(TemplateError::DuplicateName(name));
+            }
+
+            let mut tags: Vec<String> = self
+                .tags
+                .iter()
+                .map(|tag| interpolator.interpolate_str(tag).value)
+                .collect();
+            tags.extend(
+                self.matrix
+                    .iter()
+                    .map(|(parameter, _)| format!("{}_{}", parameter, parameters[parameter])),
+            );
+            let depends_on = interpolator.interpolate(&self.depends_on)?.value;
+
+            tasks.push(EvgTask {
+                name,
+                commands: Some(interpolator.interpolate(&self.commands)?.value),
+                depends_on: if depends_on.is_empty() {
+                    None
+                } else {
+                    Some(depends_on)
+                },
+                tags: Some(tags),
+                ..Default::default()
+            });
+        }
+
+        Ok(TemplateTasks {
+            tasks,
+            parameters: combinations,
+        })
+    }
+
+    /// Get every combination of the values of the parameters.
+    fn combinations(&self) -> Vec<BTreeMap<String, String>> {
+        let mut combinations = vec![BTreeMap::new()];
+        for (parameter, values) in &self.matrix {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.insert(parameter.to_string(), value.to_string());
+                        combination
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+impl TemplateTasks {
+    /// Get references to add the tasks to a build variant.
+    pub fn task_refs(&self) -> Vec<TaskRef> {
+        self.tasks
+            .iter()
+            .map(|task| task.get_reference(None, None))
+            .collect()
+    }
+
+    /// Get a display task that groups the tasks.
+    ///
+    /// * `name`: Name of the display task.
+    pub fn display_task(&self, name: &str) -> DisplayTask {
+        DisplayTask {
+            name: name.to_string(),
+            execution_tasks: self.task_names(),
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Get a task group that runs the tasks.
+    ///
+    /// * `name`: Name of the task group.
+    pub fn task_group(&self, name: &str) -> EvgTaskGroup {
+        EvgTaskGroup {
+            name: name.to_string(),
+            tasks: self.task_names(),
+            max_hosts: None,
+            share_processes: None,
+            setup_group_can_fail_task: None,
+            setup_group_timeout_secs: None,
+            setup_group: None,
+            teardown_group: None,
+            setup_task: None,
+            teardown_task: None,
+            timeout: None,
+            tags: None,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    fn task_names(&self) -> Vec<String> {
+        self.tasks
+            .iter()
+            .map(|task| task.name.to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::commands::fn_call_with_params;
+    use crate::models::params::ParamValue;
+    use std::collections::HashMap;
+
+    fn values(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn template() -> TaskTemplate {
+        let mut vars = HashMap::new();
+        vars.insert(
+            "args".to_string(),
+            ParamValue::String("--os=${os} --version=${version} ${workdir}".to_string()),
+        );
+        TaskTemplate {
+            name: "test_${os}_${version}".to_string(),
+            commands: vec![fn_call_with_params("run tests", vars)],
+            tags: vec!["tests".to_string(), "${os}".to_string()],
+            depends_on: vec![TaskDependency {
+                name: "compile_${os}".to_string(),
+                variant: None,
+                extra: BTreeMap::new(),
+            }],
+            matrix: vec![
+                ("os".to_string(), values(&["linux", "windows"])),
+                ("version".to_string(), values(&["4.4", "5.0", "6.0"])),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_instantiate_template() {
+        let tasks = template().instantiate().unwrap();
+
+        let names: Vec<&str> = tasks.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "test_linux_4.4",
+                "test_linux_5.0",
+                "test_linux_6.0",
+                "test_windows_4.4",
+                "test_windows_5.0",
+                "test_windows_6.0",
+            ]
+        );
+        let task = &tasks.tasks[4];
+        assert_eq!(
+            task.tags,
+            Some(values(&["tests", "windows", "os_windows", "version_5.0"]))
+        );
+        assert_eq!(task.depends_on.as_ref().unwrap()[0].name, "compile_windows");
+        match &task.commands.as_ref().unwrap()[0] {
+            EvgCommand::Function(call) => assert_eq!(
+                call.vars.as_ref().unwrap()["args"],
+                ParamValue::String("--os=windows --version=5.0 ${workdir}".to_string())
+            ),
+            _ => panic!("Expected a function call"),
+        }
+        assert_eq!(tasks.parameters[4]["version"], "5.0");
+
+        let task_group = tasks.task_group("all_tests");
+        assert_eq!(
+            task_group.tasks,
+            tasks.display_task("tests").execution_tasks
+        );
+        assert_eq!(tasks.task_refs()[5].name, "test_windows_6.0");
+    }
+
+    #[test]
+    fn test_names_must_be_unique() {
+        let mut template = template();
+        template.name = "test_${os}".to_string();
+
+        let result = template.instantiate();
+
+        assert!(matches!(result, Err(TemplateError::DuplicateName(name)) if name == "test_linux"));
+    }
+}
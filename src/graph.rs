@@ -0,0 +1,478 @@
+//! Graph of the dependencies between tasks in an Evergreen project.
+//!
+//! Each node in the graph is a task running on a specific build variant. Edges come from the
+//! `depends_on` entries of task definitions, where a dependency without a `variant` refers to
+//! the same build variant and `*` can be used as a wildcard for either the task name or the
+//! build variant. Dependency names can also be selectors, like `.tag !.other`.
+use crate::models::project::EvgProject;
+use crate::models::task::TaskDependency;
+use crate::selector::Selector;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+const WILDCARD: &str = "*";
+
+/// A task running on a specific build variant.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskNode {
+    /// Name of the build variant.
+    pub variant: String,
+    /// Name of the task.
+    pub task: String,
+}
+
+impl TaskNode {
+    /// Create a new node for the given build variant and task.
+    pub fn new(variant: &str, task: &str) -> Self {
+        TaskNode {
+            variant: variant.to_string(),
+            task: task.to_string(),
+        }
+    }
+}
+
+impl Display for TaskNode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.variant, self.task)
+    }
+}
+
+/// A dependency that does not match any task in the project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsatisfiableDependency {
+    /// Task with the dependency.
+    pub node: TaskNode,
+    /// The dependency that cannot be satisfied.
+    pub dependency: TaskDependency,
+}
+
+/// Graph of the dependencies between tasks of a project.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Map of each task to the tasks it depends on.
+    upstream: BTreeMap<TaskNode, BTreeSet<TaskNode>>,
+    /// Map of each task to the tasks that depend on it.
+    downstream: BTreeMap<TaskNode, BTreeSet<TaskNode>>,
+    /// Dependencies that did not match any tasks.
+    unsatisfiable: Vec<UnsatisfiableDependency>,
+}
+
+impl DependencyGraph {
+    /// Build the dependency graph for the given project.
+    ///
+    /// * `project`: Project to build graph for.
+    pub fn from_project(project: &EvgProject) -> Self {
+        let mut graph = DependencyGraph::default();
+
+        let mut variant_tasks: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for build_variant in project.build_variants() {
+            let tasks = project.tasks_on_variant(build_variant);
+            let names = tasks.iter().map(|t| t.name.as_str()).collect();
+            for task in tasks {
+                graph.add_node(TaskNode::new(&build_variant.name, &task.name));
+            }
+            variant_tasks.insert(&build_variant.name, names);
+        }
+
+        let task_map = project.task_def_map();
+
+        for (variant, tasks) in &variant_tasks {
+            for task in tasks {
+                let node = TaskNode::new(variant, task);
+                for dependency in task_map[*task].depends_on.iter().flatten() {
+                    let selector = Selector::parse(&dependency.name).ok();
+                    // Wildcards and tags can match the task itself, which is not a dependency,
+                    // but naming the task itself is a cycle.
+                    let is_named = selector.as_ref().and_then(|s| s.name()).is_some();
+                    let target_variants: Vec<&str> = match dependency.variant.as_deref() {
+                        None => vec![variant],
+                        Some(WILDCARD) => variant_tasks.keys().copied().collect(),
+                        Some(v) => vec![v],
+                    };
+
+                    let mut matched = false;
+                    for target_variant in target_variants {
+                        for target_task in
+                            variant_tasks.get(target_variant).iter().copied().flatten()
+                        {
+                            let is_match = selector
+                                .iter()
+                                .any(|s| s.matches_task(task_map[*target_task]));
+                            let target = TaskNode::new(target_variant, target_task);
+                            if is_match && (is_named || target != node) {
+                                graph.add_edge(&node, target);
+                                matched = true;
+                            }
+                        }
+                    }
+
+                    let is_wildcard = dependency.name == WILDCARD;
+                    if !matched && !is_wildcard {
+                        graph.unsatisfiable.push(UnsatisfiableDependency {
+                            node: node.clone(),
+                            dependency: dependency.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        graph
+    }
+
+    fn add_node(&mut self, node: TaskNode) {
+        self.upstream.entry(node.clone()).or_default();
+        self.downstream.entry(node).or_default();
+    }
+
+    fn add_edge(&mut self, node: &TaskNode, depends_on: TaskNode) {
+        self.downstream
+            .entry(depends_on.clone())
+            .or_default()
+            .insert(node.clone());
+        self.upstream
+            .entry(node.clone())
+            .or_default()
+            .insert(depends_on);
+    }
+
+    /// Get all tasks in the graph.
+    pub fn nodes(&self) -> impl Iterator<Item = &TaskNode> {
+        self.upstream.keys()
+    }
+
+    /// Check if the given task is in the graph.
+    pub fn contains(&self, node: &TaskNode) -> bool {
+        self.upstream.contains_key(node)
+    }
+
+    /// Get the tasks the given task directly depends on.
+    pub fn dependencies(&self, node: &TaskNode) -> BTreeSet<&TaskNode> {
+        self.upstream.get(node).iter().copied().flatten().collect()
+    }
+
+    /// Get the tasks that directly depend on the given task.
+    pub fn dependents(&self, node: &TaskNode) -> BTreeSet<&TaskNode> {
+        self.downstream
+            .get(node)
+            .iter()
+            .copied()
+            .flatten()
+            .collect()
+    }
+
+    /// Get all the tasks the given task depends on, directly or transitively.
+    pub fn upstream(&self, node: &TaskNode) -> BTreeSet<&TaskNode> {
+        reachable(&self.upstream, node)
+    }
+
+    /// Get all the tasks that depend on the given task, directly or transitively.
+    pub fn downstream(&self, node: &TaskNode) -> BTreeSet<&TaskNode> {
+        reachable(&self.downstream, node)
+    }
+
+    /// Get the dependencies that do not match any task in the project.
+    pub fn unsatisfiable_dependencies(&self) -> &[UnsatisfiableDependency] {
+        &self.unsatisfiable
+    }
+
+    /// Get the dependencies of tasks on the given build variant that do not match any task.
+    pub fn unsatisfiable_dependencies_on(&self, variant: &str) -> Vec<&UnsatisfiableDependency> {
+        self.unsatisfiable
+            .iter()
+            .filter(|d| d.node.variant == variant)
+            .collect()
+    }
+
+    /// Find a cycle in the graph, if one exists.
+    ///
+    /// The cycle is returned as a path of tasks where each task depends on the next, with
+    /// the first task repeated at the end.
+    pub fn find_cycle(&self) -> Option<Vec<TaskNode>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            InProgress,
+            Done,
+        }
+
+        let mut states: HashMap<&TaskNode, State> = HashMap::new();
+        for start in self.upstream.keys() {
+            if states.contains_key(start) {
+                continue;
+            }
+
+            let mut path: Vec<&TaskNode> = vec![start];
+            let mut stack = vec![self.upstream[start].iter()];
+            states.insert(start, State::InProgress);
+            while let Some(edges) = stack.last_mut() {
+                match edges.next() {
+                    Some(next) => match states.get(next) {
+                        Some(State::InProgress) => {
+                            let index = path.iter().position(|n| *n == next).unwrap();
+                            let mut cycle: Vec<TaskNode> =
+                                path[index..].iter().map(|n| (*n).clone()).collect();
+                            cycle.push(next.clone());
+                            return Some(cycle);
+                        }
+                        Some(State::Done) => (),
+                        None => {
+                            states.insert(next, State::InProgress);
+                            path.push(next);
+                            stack.push(self.upstream[next].iter());
+                        }
+                    },
+                    None => {
+                        stack.pop();
+                        if let Some(node) = path.pop() {
+                            states.insert(node, State::Done);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Get the tasks ordered so that every task comes after the tasks it depends on.
+    ///
+    /// If the graph contains a cycle, the cycle is returned as the error.
+    pub fn topological_order(&self) -> Result<Vec<&TaskNode>, Vec<TaskNode>> {
+        let mut remaining: BTreeMap<&TaskNode, usize> = self
+            .upstream
+            .iter()
+            .map(|(node, deps)| (node, deps.len()))
+            .collect();
+        let mut ready: BTreeSet<&TaskNode> = remaining
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(node, _)| *node)
+            .collect();
+
+        let mut order = Vec::with_capacity(remaining.len());
+        while let Some(node) = ready.iter().next().copied() {
+            ready.remove(node);
+            order.push(node);
+            for dependent in &self.downstream[node] {
+                if let Some(count) = remaining.get_mut(dependent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.insert(dependent);
+                    }
+                }
+            }
+        }
+
+        if order.len() == self.upstream.len() {
+            Ok(order)
+        } else {
+            Err(self.find_cycle().unwrap_or_default())
+        }
+    }
+}
+
+/// Get all the nodes reachable from the given node by following the given edges.
+fn reachable<'a>(
+    edges: &'a BTreeMap<TaskNode, BTreeSet<TaskNode>>,
+    node: &TaskNode,
+) -> BTreeSet<&'a TaskNode> {
+    let mut found = BTreeSet::new();
+    let mut to_visit: Vec<&TaskNode> = edges.get(node).iter().copied().flatten().collect();
+    while let Some(next) = to_visit.pop() {
+        if found.insert(next) {
+            to_visit.extend(edges.get(next).iter().copied().flatten());
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn project(yaml: &str) -> EvgProject {
+        EvgProject::from_yaml_str(yaml).unwrap()
+    }
+
+    fn node(variant: &str, task: &str) -> TaskNode {
+        TaskNode::new(variant, task)
+    }
+
+    const PROJECT: &str = r#"
+functions: {}
+tasks:
+  - name: compile
+    tags: ["build"]
+  - name: package
+    depends_on:
+      - name: compile
+  - name: test
+    depends_on:
+      - name: .build
+      - name: package
+        variant: linux
+  - name: publish
+    depends_on:
+      - name: "*"
+        variant: "*"
+  - name: lint
+    depends_on:
+      - name: format
+buildvariants:
+  - name: linux
+    tasks:
+      - name: compile
+      - name: package
+  - name: windows
+    tasks:
+      - name: compile
+      - name: test
+      - name: lint
+  - name: release
+    tasks:
+      - name: publish
+"#;
+
+    #[test]
+    fn test_dependencies_are_resolved() {
+        let graph = DependencyGraph::from_project(&project(PROJECT));
+
+        assert_eq!(
+            graph.dependencies(&node("windows", "test")),
+            vec![&node("linux", "package"), &node("windows", "compile")]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            graph.dependencies(&node("linux", "package")),
+            vec![&node("linux", "compile")].into_iter().collect()
+        );
+        assert_eq!(graph.dependencies(&node("release", "publish")).len(), 5);
+    }
+
+    #[test]
+    fn test_transitive_queries() {
+        let graph = DependencyGraph::from_project(&project(PROJECT));
+
+        assert_eq!(
+            graph.upstream(&node("windows", "test")),
+            vec![
+                &node("linux", "compile"),
+                &node("linux", "package"),
+                &node("windows", "compile"),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            graph.downstream(&node("linux", "compile")),
+            vec![
+                &node("linux", "package"),
+                &node("release", "publish"),
+                &node("windows", "test"),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn test_topological_order_puts_dependencies_first() {
+        let graph = DependencyGraph::from_project(&project(PROJECT));
+
+        let order = graph.topological_order().unwrap();
+
+        assert_eq!(order.len(), 6);
+        for (index, task) in order.iter().enumerate() {
+            for dependency in graph.dependencies(task) {
+                assert!(order[..index].contains(&dependency));
+            }
+        }
+        assert_eq!(graph.find_cycle(), None);
+    }
+
+    #[test]
+    fn test_unsatisfiable_dependencies_are_reported() {
+        let graph = DependencyGraph::from_project(&project(PROJECT));
+
+        let unsatisfiable = graph.unsatisfiable_dependencies_on("windows");
+
+        assert_eq!(unsatisfiable.len(), 1);
+        assert_eq!(unsatisfiable[0].node, node("windows", "lint"));
+        assert_eq!(unsatisfiable[0].dependency.name, "format");
+        assert!(graph.unsatisfiable_dependencies_on("linux").is_empty());
+    }
+
+    #[test]
+    fn test_cycles_are_detected() {
+        let graph = DependencyGraph::from_project(&project(
+            r#"
+functions: {}
+tasks:
+  - name: a
+    depends_on:
+      - name: c
+  - name: b
+    depends_on:
+      - name: a
+  - name: c
+    depends_on:
+      - name: b
+  - name: d
+buildvariants:
+  - name: bv
+    tasks:
+      - name: a
+      - name: b
+      - name: c
+      - name: d
+"#,
+        ));
+
+        let cycle = graph.find_cycle().unwrap();
+
+        assert_eq!(
+            cycle,
+            vec![
+                node("bv", "a"),
+                node("bv", "c"),
+                node("bv", "b"),
+                node("bv", "a")
+            ]
+        );
+        assert_eq!(graph.topological_order(), Err(cycle));
+    }
+
+    #[test]
+    fn test_tasks_depending_on_themselves_are_cycles() {
+        let graph = DependencyGraph::from_project(&project(
+            r#"
+functions: {}
+tasks:
+  - name: a
+    tags: ["build"]
+    depends_on:
+      - name: a
+  - name: b
+    tags: ["build"]
+    depends_on:
+      - name: .build
+buildvariants:
+  - name: bv
+    tasks:
+      - name: a
+      - name: b
+"#,
+        ));
+
+        assert!(graph.unsatisfiable_dependencies().is_empty());
+        assert_eq!(
+            graph.find_cycle(),
+            Some(vec![node("bv", "a"), node("bv", "a")])
+        );
+        assert_eq!(
+            graph.dependencies(&node("bv", "b")),
+            vec![&node("bv", "a")].into_iter().collect()
+        );
+    }
+}
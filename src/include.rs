@@ -0,0 +1,607 @@
+//! Load project configurations that are split across several files with `include:`.
+//!
+//! Included files are merged into the root configuration following Evergreen's
+//! [merge rules](https://github.com/evergreen-ci/evergreen/wiki/Project-Configuration-Files#include):
+//!
+//! * Tasks, task groups, functions, modules and parameters are combined, but each one can only
+//!   be defined once.
+//! * Build variants with the same name are combined by adding their tasks and display tasks
+//!   together, any other field of a build variant can only be defined once.
+//! * All other fields can only be defined in one file.
+//!
+//! Files are read through a [`FileResolver`], so they can come from the local filesystem or,
+//! for tests, from memory. Files included from a module are looked for in the directory the
+//! module is cloned to (`<prefix>/<name>`), unless another directory is given for the module.
+use crate::error::ShrubError;
+use crate::models::project::{EvgModule, EvgProject};
+use crate::models::variant::{BuildVariant, VariantEntry};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A source of configuration files.
+pub trait FileResolver {
+    /// Read the contents of the file at the given path.
+    fn read(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Read configuration files from the local filesystem.
+#[derive(Debug, Clone)]
+pub struct LocalFileResolver {
+    /// Directory paths are relative to, usually the root of the repository.
+    root: PathBuf,
+}
+
+impl LocalFileResolver {
+    /// Create a resolver that reads files relative to the given directory.
+    pub fn new(root: &Path) -> Self {
+        LocalFileResolver {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl FileResolver for LocalFileResolver {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(self.root.join(path))
+    }
+}
+
+/// Read configuration files from a map of paths to their contents.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryFileResolver {
+    files: HashMap<PathBuf, String>,
+}
+
+impl InMemoryFileResolver {
+    /// Add a file that can be read.
+    pub fn add_file(&mut self, path: &str, contents: &str) {
+        self.files.insert(PathBuf::from(path), contents.to_string());
+    }
+}
+
+impl FileResolver for InMemoryFileResolver {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+}
+
+/// Something that was defined in more than one file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    /// File that contained the second definition.
+    pub file: PathBuf,
+    /// Field that was defined more than once, e.g. `tasks` or `buildvariants.run_on`.
+    pub field: String,
+    /// Name of the item that was defined more than once, if the field is a collection.
+    pub name: Option<String>,
+}
+
+impl Display for MergeConflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(
+                f,
+                "{}: {} '{}' is already defined",
+                self.file.display(),
+                self.field,
+                name
+            ),
+            None => write!(
+                f,
+                "{}: {} is already defined",
+                self.file.display(),
+                self.field
+            ),
+        }
+    }
+}
+
+/// Errors that can occur while loading a project with includes.
+#[derive(Debug, Error)]
+pub enum IncludeError {
+    /// A configuration file could not be read.
+    #[error("Could not read '{}': {source}", .path.display())]
+    Read {
+        /// Path of file being read.
+        path: PathBuf,
+        /// Error reading the file.
+        source: io::Error,
+    },
+
+    /// A configuration file could not be parsed.
+    #[error("Could not parse '{}': {source}", .path.display())]
+    Parse {
+        /// Path of file being parsed.
+        path: PathBuf,
+        /// Error parsing the file.
+        source: ShrubError,
+    },
+
+    /// A file is included from a module the root configuration does not declare.
+    #[error("'{filename}' is included from undeclared module '{module}'")]
+    UndeclaredModule {
+        /// File being included.
+        filename: String,
+        /// Module the file was included from.
+        module: String,
+    },
+
+    /// An included file includes other files, which Evergreen does not allow.
+    #[error("'{}' is included and cannot include other files", .path.display())]
+    NestedInclude {
+        /// Path of the included file.
+        path: PathBuf,
+    },
+
+    /// The same things were defined in multiple files.
+    #[error("Could not merge included files: {}", display_conflicts(.0))]
+    Conflicts(Vec<MergeConflict>),
+}
+
+fn display_conflicts(conflicts: &[MergeConflict]) -> String {
+    conflicts
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Load a project configuration along with all the files it includes.
+pub struct IncludeLoader<R: FileResolver> {
+    resolver: R,
+    module_dirs: HashMap<String, PathBuf>,
+}
+
+impl<R: FileResolver> IncludeLoader<R> {
+    /// Create a loader that reads files with the given resolver.
+    pub fn new(resolver: R) -> Self {
+        IncludeLoader {
+            resolver,
+            module_dirs: HashMap::new(),
+        }
+    }
+
+    /// Look for files included from the given module in the given directory.
+    ///
+    /// By default, files are looked for where Evergreen clones the module.
+    pub fn with_module_dir(mut self, module: &str, dir: &Path) -> Self {
+        self.module_dirs
+            .insert(module.to_string(), dir.to_path_buf());
+        self
+    }
+
+    /// Load the configuration at the given path and merge in all the files it includes.
+    ///
+    /// * `root`: Path to the root configuration file.
+    pub fn load(&self, root: &Path) -> Result<EvgProject, IncludeError> {
+        let mut project = self.load_file(root)?;
+        let includes = project.include.clone().unwrap_or_default();
+        let modules = project.modules.clone().unwrap_or_default();
+
+        let mut conflicts = vec![];
+        for include in includes {
+            let path = match &include.module {
+                Some(module_name) => {
+                    let module =
+                        modules
+                            .iter()
+                            .find(|m| &m.name == module_name)
+                            .ok_or_else(|| IncludeError::UndeclaredModule {
+                                filename: include.filename.to_string(),
+                                module: module_name.to_string(),
+                            })?;
+                    self.module_dir(module).join(&include.filename)
+                }
+                None => PathBuf::from(&include.filename),
+            };
+
+            let included = self.load_file(&path)?;
+            if included.include.is_some() {
+                return Err(IncludeError::NestedInclude { path });
+            }
+            merge_project(&mut project, included, &path, &mut conflicts);
+        }
+
+        if conflicts.is_empty() {
+            Ok(project)
+        } else {
+            Err(IncludeError::Conflicts(conflicts))
+        }
+    }
+
+    fn module_dir(&self, module: &EvgModule) -> PathBuf {
+        self.module_dirs
+            .get(&module.name)
+            .cloned()
+            .unwrap_or_else(|| Path::new(&module.prefix).join(&module.name))
+    }
+
+    fn load_file(&self, path: &Path) -> Result<EvgProject, IncludeError> {
+        let contents = self
+            .resolver
+            .read(path)
+            .map_err(|source| IncludeError::Read {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        EvgProject::from_yaml_str(&contents).map_err(|source| IncludeError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// Track conflicts found while merging a single file.
+struct Merger<'a> {
+    file: &'a Path,
+    conflicts: &'a mut Vec<MergeConflict>,
+}
+
+impl<'a> Merger<'a> {
+    fn conflict(&mut self, field: &str, name: Option<&str>) {
+        self.conflicts.push(MergeConflict {
+            file: self.file.to_path_buf(),
+            field: field.to_string(),
+            name: name.map(|n| n.to_string()),
+        });
+    }
+
+    /// Merge a field that can only be defined in one file.
+    fn merge_unique<T>(&mut self, field: &str, target: &mut Option<T>, value: Option<T>) {
+        if value.is_some() {
+            if target.is_some() {
+                self.conflict(field, None);
+            } else {
+                *target = value;
+            }
+        }
+    }
+
+    /// Merge lists of named items where each name can only be defined once.
+    fn merge_named<T>(
+        &mut self,
+        field: &str,
+        target: &mut Vec<T>,
+        values: Vec<T>,
+        name: impl Fn(&T) -> &str,
+    ) {
+        for value in values {
+            if target.iter().any(|t| name(t) == name(&value)) {
+                self.conflict(field, Some(name(&value)));
+            } else {
+                target.push(value);
+            }
+        }
+    }
+
+    /// Merge optional lists of named items where each name can only be defined once.
+    fn merge_optional_named<T>(
+        &mut self,
+        field: &str,
+        target: &mut Option<Vec<T>>,
+        values: Option<Vec<T>>,
+        name: impl Fn(&T) -> &str,
+    ) {
+        if let Some(values) = values {
+            let target = target.get_or_insert_with(Vec::new);
+            self.merge_named(field, target, values, name);
+        }
+    }
+
+    /// Merge maps where each key can only be defined once.
+    fn merge_map<K, V, M>(&mut self, field: &str, target: &mut M, values: M)
+    where
+        K: AsRef<str>,
+        M: Extend<(K, V)> + IntoIterator<Item = (K, V)> + MapContains<K>,
+    {
+        let mut new_entries = vec![];
+        for (key, value) in values {
+            if target.contains(&key) {
+                self.conflict(field, Some(key.as_ref()));
+            } else {
+                new_entries.push((key, value));
+            }
+        }
+        target.extend(new_entries);
+    }
+
+    fn merge_build_variant(&mut self, target: &mut BuildVariant, other: BuildVariant) {
+        let BuildVariant {
+            name: _,
+            tasks,
+            display_name,
+            run_on,
+            display_tasks,
+            batchtime,
+            expansions,
+            stepback,
+            modules,
+            activate,
+            tags,
+            extra,
+        } = other;
+
+        target.tasks.extend(tasks);
+        if let Some(display_tasks) = display_tasks {
+            target
+                .display_tasks
+                .get_or_insert_with(Vec::new)
+                .extend(display_tasks);
+        }
+        self.merge_unique(
+            "buildvariants.display_name",
+            &mut target.display_name,
+            display_name,
+        );
+        self.merge_unique("buildvariants.run_on", &mut target.run_on, run_on);
+        self.merge_unique("buildvariants.batchtime", &mut target.batchtime, batchtime);
+        self.merge_unique(
+            "buildvariants.expansions",
+            &mut target.expansions,
+            expansions,
+        );
+        self.merge_unique("buildvariants.stepback", &mut target.stepback, stepback);
+        self.merge_unique("buildvariants.modules", &mut target.modules, modules);
+        self.merge_unique("buildvariants.activate", &mut target.activate, activate);
+        self.merge_unique("buildvariants.tags", &mut target.tags, tags);
+        self.merge_map("buildvariants", &mut target.extra, extra);
+    }
+}
+
+/// Maps that can be checked for a key.
+trait MapContains<K> {
+    fn contains(&self, key: &K) -> bool;
+}
+
+impl<K: Eq + std::hash::Hash, V> MapContains<K> for HashMap<K, V> {
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+}
+
+impl<K: Ord, V> MapContains<K> for std::collections::BTreeMap<K, V> {
+    fn contains(&self, key: &K) -> bool {
+        self.contains_key(key)
+    }
+}
+
+/// Merge an included project into the given project.
+fn merge_project(
+    project: &mut EvgProject,
+    other: EvgProject,
+    file: &Path,
+    conflicts: &mut Vec<MergeConflict>,
+) {
+    let mut merger = Merger { file, conflicts };
+    let EvgProject {
+        buildvariants,
+        axes,
+        tasks,
+        task_groups,
+        functions,
+        pre,
+        post,
+        timeout,
+        modules,
+        stepback,
+        pre_error_fails_task,
+        oom_tracker,
+        command_type,
+        ignore,
+        parameters,
+        include: _,
+        extra,
+    } = other;
+
+    for entry in buildvariants {
+        match entry {
+            VariantEntry::Variant(build_variant) => {
+                let existing = project
+                    .build_variants_mut()
+                    .find(|bv| bv.name == build_variant.name);
+                match existing {
+                    Some(existing) => merger.merge_build_variant(existing, build_variant),
+                    None => project.buildvariants.push(build_variant.into()),
+                }
+            }
+            VariantEntry::Matrix(matrix) => {
+                if project
+                    .matrices()
+                    .any(|m| m.matrix_name == matrix.matrix_name)
+                {
+                    merger.conflict("buildvariants", Some(&matrix.matrix_name));
+                } else {
+                    project.buildvariants.push(matrix.into());
+                }
+            }
+        }
+    }
+    merger.merge_optional_named("axes", &mut project.axes, axes, |a| &a.id);
+    merger.merge_named("tasks", &mut project.tasks, tasks, |t| &t.name);
+    merger.merge_optional_named("task_groups", &mut project.task_groups, task_groups, |tg| {
+        &tg.name
+    });
+    merger.merge_map("functions", &mut project.functions, functions);
+    merger.merge_unique("pre", &mut project.pre, pre);
+    merger.merge_unique("post", &mut project.post, post);
+    merger.merge_unique("timeout", &mut project.timeout, timeout);
+    merger.merge_optional_named("modules", &mut project.modules, modules, |m| &m.name);
+    merger.merge_unique("stepback", &mut project.stepback, stepback);
+    merger.merge_unique(
+        "pre_error_fails_task",
+        &mut project.pre_error_fails_task,
+        pre_error_fails_task,
+    );
+    merger.merge_unique("oom_tracker", &mut project.oom_tracker, oom_tracker);
+    merger.merge_unique("command_type", &mut project.command_type, command_type);
+    merger.merge_unique("ignore", &mut project.ignore, ignore);
+    merger.merge_optional_named("parameters", &mut project.parameters, parameters, |p| {
+        &p.key
+    });
+    merger.merge_map("fields", &mut project.extra, extra);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loader(files: &[(&str, &str)]) -> IncludeLoader<InMemoryFileResolver> {
+        let mut resolver = InMemoryFileResolver::default();
+        for (path, contents) in files {
+            resolver.add_file(path, contents);
+        }
+        IncludeLoader::new(resolver)
+    }
+
+    const ROOT: &str = r#"
+include:
+  - filename: etc/tasks.yml
+  - filename: etc/module.yml
+    module: enterprise
+modules:
+  - name: enterprise
+    repo: git@github.com:10gen/enterprise.git
+    branch: master
+    prefix: src/modules
+functions:
+  setup:
+    command: shell.exec
+    params:
+      script: echo setup
+tasks:
+  - name: compile
+buildvariants:
+  - name: linux
+    run_on: ["ubuntu2004"]
+    tasks:
+      - name: compile
+"#;
+
+    const TASKS: &str = r#"
+tasks:
+  - name: test
+buildvariants:
+  - name: linux
+    tasks:
+      - name: test
+    display_tasks:
+      - name: tests
+        execution_tasks: ["test"]
+"#;
+
+    const MODULE: &str = r#"
+tasks:
+  - name: enterprise_test
+buildvariants:
+  - name: enterprise
+    tasks:
+      - name: enterprise_test
+"#;
+
+    #[test]
+    fn test_included_files_are_merged() {
+        let loader = loader(&[
+            ("etc/evergreen.yml", ROOT),
+            ("etc/tasks.yml", TASKS),
+            ("src/modules/enterprise/etc/module.yml", MODULE),
+        ]);
+
+        let project = loader.load(Path::new("etc/evergreen.yml")).unwrap();
+
+        let tasks: Vec<&str> = project.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(tasks, vec!["compile", "test", "enterprise_test"]);
+        assert_eq!(project.buildvariants.len(), 2);
+        let linux = project.buildvariants[0].build_variant().unwrap();
+        let linux_tasks: Vec<&str> = linux.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(linux_tasks, vec!["compile", "test"]);
+        assert_eq!(linux.run_on, Some(vec!["ubuntu2004".to_string()]));
+        assert_eq!(linux.display_tasks.as_ref().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_module_dirs_can_be_overridden() {
+        let loader = loader(&[
+            ("etc/evergreen.yml", ROOT),
+            ("etc/tasks.yml", TASKS),
+            ("../enterprise/etc/module.yml", MODULE),
+        ])
+        .with_module_dir("enterprise", Path::new("../enterprise"));
+
+        let project = loader.load(Path::new("etc/evergreen.yml")).unwrap();
+
+        assert_eq!(project.tasks.len(), 3);
+    }
+
+    #[test]
+    fn test_conflicts_are_reported() {
+        let loader = loader(&[
+            ("etc/evergreen.yml", ROOT),
+            (
+                "etc/tasks.yml",
+                r#"
+functions:
+  setup:
+    command: shell.exec
+    params:
+      script: echo other setup
+tasks:
+  - name: compile
+buildvariants:
+  - name: linux
+    run_on: ["windows"]
+    tasks: []
+"#,
+            ),
+            ("src/modules/enterprise/etc/module.yml", MODULE),
+        ]);
+
+        let result = loader.load(Path::new("etc/evergreen.yml"));
+
+        match result {
+            Err(IncludeError::Conflicts(conflicts)) => {
+                let conflicts: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+                assert_eq!(
+                    conflicts,
+                    vec![
+                        "etc/tasks.yml: buildvariants.run_on is already defined",
+                        "etc/tasks.yml: tasks 'compile' is already defined",
+                        "etc/tasks.yml: functions 'setup' is already defined",
+                    ]
+                );
+            }
+            _ => panic!("Expected conflicts, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_missing_files_are_reported() {
+        let loader = loader(&[("etc/evergreen.yml", ROOT)]);
+
+        let result = loader.load(Path::new("etc/evergreen.yml"));
+
+        assert!(
+            matches!(result, Err(IncludeError::Read { path, .. }) if path == Path::new("etc/tasks.yml"))
+        );
+    }
+
+    #[test]
+    fn test_nested_includes_are_rejected() {
+        let loader = loader(&[
+            (
+                "etc/evergreen.yml",
+                "include:\n  - filename: etc/tasks.yml\n",
+            ),
+            ("etc/tasks.yml", "include:\n  - filename: etc/other.yml\n"),
+        ]);
+
+        let result = loader.load(Path::new("etc/evergreen.yml"));
+
+        assert!(matches!(result, Err(IncludeError::NestedInclude { .. })));
+    }
+}
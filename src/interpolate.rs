@@ -0,0 +1,338 @@
+//! Substitute expansions into strings the way Evergreen does.
+//!
+//! References to expansions can take three forms:
+//!
+//! * `${name}` is replaced with the value of `name`.
+//! * `${name|default}` is replaced with the value of `name`, or `default` if `name` is not
+//!   defined.
+//! * `${name|*other}` is replaced with the value of `name`, or the value of `other` if `name` is
+//!   not defined.
+//!
+//! Evergreen replaces references to undefined expansions with an empty string. The same is done
+//! here, but the undefined references are also reported so they can be treated as errors.
+//! A `${` without a closing `}` is left as it is.
+use crate::yaml::from_value;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+/// Errors that can occur while interpolating expansions.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum InterpolationError {
+    /// Expansions were referenced that are not defined.
+    #[error("Undefined expansions: {}", .0.join(", "))]
+    Undefined(Vec<String>),
+
+    /// A value could not be converted to or from its substituted form.
+    #[error("Could not interpolate value: {0}")]
+    Conversion(String),
+}
+
+/// What to use when a referenced expansion is not defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fallback {
+    /// Use the given value (`${name|default}`).
+    Default(String),
+    /// Use the value of another expansion (`${name|*other}`).
+    Expansion(String),
+}
+
+/// A reference to an expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    /// Name of the expansion being referenced.
+    pub name: String,
+    /// What to use if the expansion is not defined.
+    pub fallback: Option<Fallback>,
+}
+
+impl Display for Reference {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.fallback {
+            None => write!(f, "${{{}}}", self.name),
+            Some(Fallback::Default(default)) => write!(f, "${{{}|{}}}", self.name, default),
+            Some(Fallback::Expansion(other)) => write!(f, "${{{}|*{}}}", self.name, other),
+        }
+    }
+}
+
+/// A part of a string that may contain references to expansions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Text to use as is.
+    Literal(String),
+    /// A reference to an expansion.
+    Reference(Reference),
+}
+
+/// Split the given string into literal text and references to expansions.
+///
+/// * `input`: String to parse.
+pub fn parse(input: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_string()));
+        }
+
+        let body = &rest[start + 2..end];
+        let reference = match body.split_once('|') {
+            Some((name, fallback)) => Reference {
+                name: name.to_string(),
+                fallback: Some(match fallback.strip_prefix('*') {
+                    Some(other) => Fallback::Expansion(other.to_string()),
+                    None => Fallback::Default(fallback.to_string()),
+                }),
+            },
+            None => Reference {
+                name: body.to_string(),
+                fallback: None,
+            },
+        };
+        segments.push(Segment::Reference(reference));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_string()));
+    }
+    segments
+}
+
+/// The result of substituting expansions into a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpolated<T> {
+    /// The value with all references substituted.
+    pub value: T,
+    /// Names of referenced expansions that were not defined.
+    pub undefined: BTreeSet<String>,
+}
+
+impl<T> Interpolated<T> {
+    /// Get the substituted value, failing if any referenced expansions were not defined.
+    pub fn into_result(self) -> Result<T, InterpolationError> {
+        if self.undefined.is_empty() {
+            Ok(self.value)
+        } else {
+            Err(InterpolationError::Undefined(
+                self.undefined.into_iter().collect(),
+            ))
+        }
+    }
+}
+
+/// Substitute expansions into strings and values containing strings.
+#[derive(Debug, Clone)]
+pub struct Interpolator<'a> {
+    expansions: &'a BTreeMap<String, String>,
+    keep_unresolved: bool,
+}
+
+impl<'a> Interpolator<'a> {
+    /// Create an interpolator that substitutes the given expansions.
+    pub fn new(expansions: &'a BTreeMap<String, String>) -> Self {
+        Interpolator {
+            expansions,
+            keep_unresolved: false,
+        }
+    }
+
+    /// Create an interpolator that only substitutes the given expansions.
+    ///
+    /// References that can't be resolved with the given expansions are left as they are instead
+    /// of being replaced and reported, so they can be substituted later when more expansions are
+    /// known.
+    pub fn partial(expansions: &'a BTreeMap<String, String>) -> Self {
+        Interpolator {
+            expansions,
+            keep_unresolved: true,
+        }
+    }
+
+    /// Substitute expansions into the given string.
+    ///
+    /// * `input`: String to substitute expansions into.
+    pub fn interpolate_str(&self, input: &str) -> Interpolated<String> {
+        let mut undefined = BTreeSet::new();
+        let value = self.expand(input, &mut undefined);
+        Interpolated { value, undefined }
+    }
+
+    /// Substitute expansions into every string in the given value.
+    ///
+    /// This works with anything that can be serialized, like an `EvgCommand` or `ParamValue`.
+    /// Only values are substituted, keys of maps are left as they are.
+    ///
+    /// * `value`: Value to substitute expansions into.
+    pub fn interpolate<T>(&self, value: &T) -> Result<Interpolated<T>, InterpolationError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut undefined = BTreeSet::new();
+        let tree = serde_yaml::to_value(value)
+            .map_err(|err| InterpolationError::Conversion(err.to_string()))?;
+        let tree = self.expand_value(tree, &mut undefined);
+        let value = from_value(tree)
+            .map_err(|err: serde_yaml::Error| InterpolationError::Conversion(err.to_string()))?;
+        Ok(Interpolated { value, undefined })
+    }
+
+    fn expand_value(&self, value: Value, undefined: &mut BTreeSet<String>) -> Value {
+        match value {
+            Value::String(s) => Value::String(self.expand(&s, undefined)),
+            Value::Sequence(seq) => Value::Sequence(
+                seq.into_iter()
+                    .map(|v| self.expand_value(v, undefined))
+                    .collect(),
+            ),
+            Value::Mapping(map) => Value::Mapping(
+                map.into_iter()
+                    .map(|(k, v)| (k, self.expand_value(v, undefined)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    fn expand(&self, input: &str, undefined: &mut BTreeSet<String>) -> String {
+        parse(input)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text,
+                Segment::Reference(reference) => self.resolve(&reference, undefined),
+            })
+            .collect()
+    }
+
+    fn resolve(&self, reference: &Reference, undefined: &mut BTreeSet<String>) -> String {
+        if let Some(value) = self.expansions.get(&reference.name) {
+            return value.to_string();
+        }
+        if self.keep_unresolved {
+            return reference.to_string();
+        }
+        match &reference.fallback {
+            Some(Fallback::Default(default)) => default.to_string(),
+            Some(Fallback::Expansion(other)) => match self.expansions.get(other) {
+                Some(value) => value.to_string(),
+                None => {
+                    undefined.insert(other.to_string());
+                    String::new()
+                }
+            },
+            None => {
+                undefined.insert(reference.name.to_string());
+                String::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::commands::EvgCommand;
+    use crate::models::project::EvgProject;
+
+    fn expansions() -> BTreeMap<String, String> {
+        vec![("distro", "ubuntu2004"), ("workdir", "/data/mci")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_references() {
+        assert_eq!(
+            parse("cd ${workdir} && ${cmd|make} ${args|*default_args}${"),
+            vec![
+                Segment::Literal("cd ".to_string()),
+                Segment::Reference(Reference {
+                    name: "workdir".to_string(),
+                    fallback: None
+                }),
+                Segment::Literal(" && ".to_string()),
+                Segment::Reference(Reference {
+                    name: "cmd".to_string(),
+                    fallback: Some(Fallback::Default("make".to_string()))
+                }),
+                Segment::Literal(" ".to_string()),
+                Segment::Reference(Reference {
+                    name: "args".to_string(),
+                    fallback: Some(Fallback::Expansion("default_args".to_string()))
+                }),
+                Segment::Literal("${".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interpolate_str() {
+        let expansions = expansions();
+        let interpolator = Interpolator::new(&expansions);
+
+        let result = interpolator.interpolate_str(
+            "${distro} ${missing|default} ${missing|*workdir} ${distro|unused} ${undefined}.",
+        );
+
+        assert_eq!(result.value, "ubuntu2004 default /data/mci ubuntu2004 .");
+        assert_eq!(
+            result.into_result(),
+            Err(InterpolationError::Undefined(vec!["undefined".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_partial_interpolation_keeps_unresolved_references() {
+        let expansions = expansions();
+        let interpolator = Interpolator::partial(&expansions);
+
+        let result = interpolator.interpolate_str("${distro} ${missing} ${a|b} ${c|*workdir}");
+
+        assert_eq!(result.value, "ubuntu2004 ${missing} ${a|b} ${c|*workdir}");
+        assert!(result.undefined.is_empty());
+    }
+
+    #[test]
+    fn test_interpolate_commands() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions:
+  run:
+    - command: shell.exec
+      params:
+        working_dir: ${workdir}/src
+        script: echo ${distro} ${other|*missing}
+        env:
+          DISTRO: ${distro}
+    - func: setup
+      vars:
+        target: ${distro|linux}
+"#,
+        )
+        .unwrap();
+        let expansions = expansions();
+        let interpolator = Interpolator::new(&expansions);
+
+        let commands = project.functions["run"].commands();
+        let result = interpolator.interpolate(&commands.to_vec()).unwrap();
+
+        let substituted: Vec<EvgCommand> = result.value;
+        let yaml = serde_yaml::to_string(&substituted).unwrap();
+        assert!(yaml.contains("working_dir: /data/mci/src"));
+        assert!(yaml.contains("script: \"echo ubuntu2004 \""));
+        assert!(yaml.contains("DISTRO: ubuntu2004"));
+        assert!(yaml.contains("target: ubuntu2004"));
+        assert_eq!(
+            result.undefined.into_iter().collect::<Vec<String>>(),
+            vec!["missing"]
+        );
+    }
+}
@@ -1,2 +1,19 @@
+pub mod archive;
+pub mod artifacts;
+pub mod error;
+pub mod evaluate;
+pub mod expansions;
+pub mod export;
+pub mod generate;
+pub mod graph;
+pub mod include;
+pub mod interpolate;
+pub mod matrix;
 #[allow(clippy::large_enum_variant)]
 pub mod models;
+pub mod render;
+pub mod results;
+pub mod runner;
+pub mod selector;
+pub mod validate;
+mod yaml;
@@ -0,0 +1,516 @@
+//! Expand matrices into the build variants they define, the way Evergreen does.
+//!
+//! A matrix defines a build variant for each combination of the values its `matrix_spec` selects,
+//! other than those matched by its `exclude_spec`. Each build variant is named
+//! `<matrix_name>__<axis>~<value>_<axis>~<value>`, with axes in the order they are declared in
+//! `axes`, and is built by:
+//!
+//! * Starting with the fields of the matrix.
+//! * Applying the settings of each of its axis values, in the order the axes are declared.
+//!   Variables are added
+//!   to the expansions, tags are added to the tags, and any other setting replaces the previous
+//!   one.
+//! * Applying each rule that matches it, in order. Settings are applied like those of axis
+//!   values, then tasks are removed and added.
+//! * Substituting `${axis}` references in the display name with the display name of the axis
+//!   value. References in `run_on`, task names and expansions are substituted with the ID of the
+//!   axis value or with the expansions of the build variant.
+use crate::interpolate::Interpolator;
+use crate::models::matrix::{Axis, AxisValue, MatrixDefinition, MatrixSettings, MatrixSpec};
+use crate::models::project::EvgProject;
+use crate::models::variant::{BuildVariant, VariantEntry};
+use crate::selector::{Selector, SelectorError};
+use std::collections::{BTreeMap, HashSet};
+use thiserror::Error;
+
+/// Errors that can occur while expanding matrices.
+#[derive(Debug, Error, PartialEq)]
+pub enum MatrixError {
+    /// A matrix references an axis that is not defined.
+    #[error("Matrix '{matrix}' references undefined axis '{axis}'")]
+    UndefinedAxis {
+        /// Name of the matrix.
+        matrix: String,
+        /// ID of the axis.
+        axis: String,
+    },
+
+    /// A matrix spec selects no values of an axis.
+    #[error("Selector '{selector}' in matrix '{matrix}' does not match any values of '{axis}'")]
+    UnmatchedSelector {
+        /// Name of the matrix.
+        matrix: String,
+        /// ID of the axis.
+        axis: String,
+        /// The selector that matched nothing.
+        selector: String,
+    },
+
+    /// A selector could not be parsed.
+    #[error("Invalid selector in matrix '{matrix}': {source}")]
+    InvalidSelector {
+        /// Name of the matrix.
+        matrix: String,
+        /// Why the selector is invalid.
+        source: SelectorError,
+    },
+
+    /// A matrix defines a build variant that is already defined.
+    #[error("Build variant '{0}' is defined more than once")]
+    DuplicateVariant(String),
+}
+
+/// Get the build variants of the given project, with each matrix replaced by the build variants
+/// it defines.
+///
+/// Fails if any build variant has the same name as another build variant.
+///
+/// * `project`: Project to expand matrices of.
+pub fn expand_matrices(project: &EvgProject) -> Result<Vec<BuildVariant>, MatrixError> {
+    let axes = project.axes.as_deref().unwrap_or_default();
+    let mut build_variants = vec![];
+    for entry in &project.buildvariants {
+        match entry {
+            VariantEntry::Variant(build_variant) => build_variants.push(build_variant.clone()),
+            VariantEntry::Matrix(matrix) => build_variants.extend(expand_matrix(matrix, axes)?),
+        }
+    }
+    let mut names = HashSet::new();
+    for build_variant in &build_variants {
+        if !names.insert(build_variant.name.as_str()) {
+            return Err(MatrixError::DuplicateVariant(
+                build_variant.name.to_string(),
+            ));
+        }
+    }
+    Ok(build_variants)
+}
+
+/// Expand a matrix into the build variants it defines.
+///
+/// * `matrix`: Matrix to expand.
+/// * `axes`: Axes the matrix can reference.
+pub fn expand_matrix(
+    matrix: &MatrixDefinition,
+    axes: &[Axis],
+) -> Result<Vec<BuildVariant>, MatrixError> {
+    let evaluator = Evaluator { matrix, axes };
+    for axis_id in matrix.matrix_spec.keys() {
+        evaluator.axis(axis_id)?;
+    }
+
+    let mut cells: Vec<Cell> = vec![vec![]];
+    for axis in axes {
+        let (axis_id, selection) = match matrix.matrix_spec.get_key_value(&axis.id) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut selected = vec![false; axis.values.len()];
+        for selector in selection.selectors() {
+            let matched = evaluator.select(axis, selector)?;
+            if !matched.iter().any(|is_match| *is_match) {
+                return Err(MatrixError::UnmatchedSelector {
+                    matrix: matrix.matrix_name.to_string(),
+                    axis: axis_id.to_string(),
+                    selector: selector.to_string(),
+                });
+            }
+            for (is_selected, is_match) in selected.iter_mut().zip(matched) {
+                *is_selected |= is_match;
+            }
+        }
+        let values: Vec<&AxisValue> = axis
+            .values
+            .iter()
+            .zip(selected)
+            .filter(|(_, is_selected)| *is_selected)
+            .map(|(value, _)| value)
+            .collect();
+
+        cells = cells
+            .into_iter()
+            .flat_map(|cell| {
+                values.iter().map(move |value| {
+                    let mut cell = cell.clone();
+                    cell.push((axis_id.as_str(), *value));
+                    cell
+                })
+            })
+            .collect();
+    }
+
+    let mut build_variants = vec![];
+    for cell in cells {
+        let excluded = match &matrix.exclude_spec {
+            Some(exclude_spec) => evaluator.matches_any(&cell, exclude_spec.specs())?,
+            None => false,
+        };
+        if !excluded {
+            build_variants.push(evaluator.build_variant(&cell)?);
+        }
+    }
+    Ok(build_variants)
+}
+
+/// The ID and value of each axis of one build variant of a matrix, in the order the axes are
+/// declared.
+type Cell<'a> = Vec<(&'a str, &'a AxisValue)>;
+
+/// Evaluate the specs of a matrix against its axes.
+struct Evaluator<'a> {
+    matrix: &'a MatrixDefinition,
+    axes: &'a [Axis],
+}
+
+impl<'a> Evaluator<'a> {
+    fn axis(&self, id: &str) -> Result<&'a Axis, MatrixError> {
+        self.axes
+            .iter()
+            .find(|axis| axis.id == id)
+            .ok_or_else(|| MatrixError::UndefinedAxis {
+                matrix: self.matrix.matrix_name.to_string(),
+                axis: id.to_string(),
+            })
+    }
+
+    /// Check which values of the axis the given selector matches.
+    fn select(&self, axis: &Axis, selector: &str) -> Result<Vec<bool>, MatrixError> {
+        let selector = self.parse(selector)?;
+        Ok(axis
+            .values
+            .iter()
+            .map(|value| matches(&selector, value))
+            .collect())
+    }
+
+    fn parse(&self, selector: &str) -> Result<Selector, MatrixError> {
+        Selector::parse(selector).map_err(|source| MatrixError::InvalidSelector {
+            matrix: self.matrix.matrix_name.to_string(),
+            source,
+        })
+    }
+
+    /// Check if a cell is matched by any of the given specs.
+    fn matches_any(&self, cell: &Cell, specs: &[MatrixSpec]) -> Result<bool, MatrixError> {
+        for spec in specs {
+            if self.matches_spec(cell, spec)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Check if a cell has a value matched by the spec for every axis in it.
+    fn matches_spec(&self, cell: &Cell, spec: &MatrixSpec) -> Result<bool, MatrixError> {
+        for (axis_id, selection) in spec {
+            let value = match cell.iter().find(|(id, _)| id == axis_id) {
+                Some((_, value)) => value,
+                None => return Ok(false),
+            };
+            let mut is_match = false;
+            for selector in selection.selectors() {
+                is_match |= matches(&self.parse(selector)?, value);
+            }
+            if !is_match {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn build_variant(&self, cell: &Cell) -> Result<BuildVariant, MatrixError> {
+        let matrix = self.matrix;
+        let name_parts: Vec<String> = cell
+            .iter()
+            .map(|(axis_id, value)| format!("{}~{}", axis_id, value.id))
+            .collect();
+        let mut build_variant = BuildVariant {
+            name: format!("{}__{}", matrix.matrix_name, name_parts.join("_")),
+            tasks: matrix.tasks.clone(),
+            display_name: None,
+            run_on: matrix.run_on.clone(),
+            display_tasks: matrix.display_tasks.clone(),
+            batchtime: matrix.batchtime,
+            expansions: matrix.expansions.clone(),
+            stepback: matrix.stepback,
+            modules: matrix.modules.clone(),
+            activate: matrix.activate,
+            tags: matrix.tags.clone(),
+            extra: matrix.extra.clone(),
+        };
+
+        for (_, value) in cell {
+            apply_settings(&mut build_variant, &value.settings);
+        }
+        for rule in matrix.rules.iter().flatten() {
+            if !self.matches_any(cell, rule.if_spec.specs())? {
+                continue;
+            }
+            if let Some(settings) = &rule.then.set {
+                apply_settings(&mut build_variant, settings);
+            }
+            if let Some(remove_tasks) = &rule.then.remove_tasks {
+                build_variant
+                    .tasks
+                    .retain(|task| !remove_tasks.contains(&task.name));
+            }
+            if let Some(add_tasks) = &rule.then.add_tasks {
+                build_variant.tasks.extend(add_tasks.iter().cloned());
+            }
+        }
+
+        let display_names: BTreeMap<String, String> = cell
+            .iter()
+            .map(|(axis_id, value)| {
+                let display_name = value.display_name.as_ref().unwrap_or(&value.id);
+                (axis_id.to_string(), display_name.to_string())
+            })
+            .collect();
+        build_variant.display_name = matrix.display_name.as_ref().map(|name| {
+            Interpolator::partial(&display_names)
+                .interpolate_str(name)
+                .value
+        });
+
+        let mut expansions: BTreeMap<String, String> = cell
+            .iter()
+            .map(|(axis_id, value)| (axis_id.to_string(), value.id.to_string()))
+            .collect();
+        expansions.extend(build_variant.expansions.clone().unwrap_or_default());
+        let interpolator = Interpolator::partial(&expansions);
+        let expand = |s: &String| interpolator.interpolate_str(s).value;
+        for task in &mut build_variant.tasks {
+            task.name = expand(&task.name);
+        }
+        if let Some(run_on) = &mut build_variant.run_on {
+            *run_on = run_on.iter().map(expand).collect();
+        }
+        if let Some(variables) = &mut build_variant.expansions {
+            for value in variables.values_mut() {
+                *value = expand(value);
+            }
+        }
+
+        Ok(build_variant)
+    }
+}
+
+/// Check if an axis value is matched by a selector, using its ID as its name.
+fn matches(selector: &Selector, value: &AxisValue) -> bool {
+    selector.matches(
+        &value.id,
+        value.settings.tags.as_deref().unwrap_or_default(),
+    )
+}
+
+/// Apply the settings of an axis value or rule to a build variant.
+fn apply_settings(build_variant: &mut BuildVariant, settings: &MatrixSettings) {
+    if let Some(variables) = &settings.variables {
+        build_variant
+            .expansions
+            .get_or_insert_with(BTreeMap::new)
+            .extend(variables.clone());
+    }
+    if let Some(tags) = &settings.tags {
+        let existing = build_variant.tags.get_or_insert_with(Vec::new);
+        for tag in tags {
+            if !existing.contains(tag) {
+                existing.push(tag.to_string());
+            }
+        }
+    }
+    if settings.run_on.is_some() {
+        build_variant.run_on = settings.run_on.clone();
+    }
+    if settings.modules.is_some() {
+        build_variant.modules = settings.modules.clone();
+    }
+    if settings.batchtime.is_some() {
+        build_variant.batchtime = settings.batchtime;
+    }
+    if settings.stepback.is_some() {
+        build_variant.stepback = settings.stepback;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::matrix::AxisSelection;
+
+    const PROJECT: &str = r#"
+axes:
+  - id: os
+    display_name: OS
+    values:
+      - id: linux
+        display_name: Linux
+        run_on: ["ubuntu2004"]
+        tags: ["posix"]
+        variables:
+          python: /usr/bin/python3
+      - id: macos
+        display_name: macOS
+        run_on: ["macos-1100"]
+        tags: ["posix"]
+      - id: windows
+        display_name: Windows
+        run_on: ["windows-vsCurrent"]
+        variables:
+          python: C:/python/python.exe
+  - id: version
+    values:
+      - id: "5.0"
+      - id: "6.0"
+        batchtime: 60
+
+tasks:
+  - name: compile
+  - name: test
+  - name: lint
+
+buildvariants:
+  - name: lint
+    tasks:
+      - name: lint
+  - matrix_name: tests
+    matrix_spec:
+      os: ".posix"
+      version: "*"
+    exclude_spec:
+      - os: macos
+        version: "5.0"
+    display_name: ${os} ${version}
+    expansions:
+      server: mongodb-${version}
+    tasks:
+      - name: compile
+      - name: test
+    rules:
+      - if:
+          version: "6.0"
+        then:
+          add_tasks:
+            - name: lint
+          remove_tasks: ["compile"]
+          set:
+            stepback: true
+"#;
+
+    #[test]
+    fn test_matrices_are_parsed() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        assert_eq!(project.buildvariants.len(), 2);
+        assert_eq!(
+            project.buildvariants[1].matrix().unwrap().matrix_name,
+            "tests"
+        );
+        assert_eq!(project.axes.as_ref().unwrap()[0].values.len(), 3);
+
+        let yaml = serde_yaml::to_string(&project).unwrap();
+        let read_back = EvgProject::from_yaml_str(&yaml).unwrap();
+        assert_eq!(read_back.build_variants().count(), 1);
+        let matrix = read_back.buildvariants[1].matrix().unwrap();
+        assert_eq!(matrix.rules.as_ref().unwrap().len(), 1);
+        assert_eq!(read_back.axes, project.axes);
+    }
+
+    #[test]
+    fn test_expand_matrices() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let build_variants = expand_matrices(&project).unwrap();
+
+        let names: Vec<&str> = build_variants.iter().map(|bv| bv.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "lint",
+                "tests__os~linux_version~5.0",
+                "tests__os~linux_version~6.0",
+                "tests__os~macos_version~6.0",
+            ]
+        );
+        let linux = &build_variants[1];
+        assert_eq!(linux.display_name.as_deref(), Some("Linux 5.0"));
+        assert_eq!(linux.run_on, Some(vec!["ubuntu2004".to_string()]));
+        let expansions = linux.expansions.as_ref().unwrap();
+        assert_eq!(expansions["server"], "mongodb-5.0");
+        assert_eq!(expansions["python"], "/usr/bin/python3");
+        assert_eq!(linux.tags, Some(vec!["posix".to_string()]));
+
+        let macos = &build_variants[3];
+        let tasks: Vec<&str> = macos.tasks.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(tasks, vec!["test", "lint"]);
+        assert_eq!(macos.batchtime, Some(60));
+        assert_eq!(macos.stepback, Some(true));
+        assert_eq!(linux.stepback, None);
+    }
+
+    #[test]
+    fn test_axes_are_used_in_declaration_order() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+axes:
+  - id: version
+    values:
+      - id: "6.0"
+        run_on: ["rhel80"]
+  - id: arch
+    values:
+      - id: x86
+        run_on: ["ubuntu2004"]
+buildvariants:
+  - matrix_name: tests
+    matrix_spec:
+      arch: "*"
+      version: "*"
+    tasks:
+      - name: test
+"#,
+        )
+        .unwrap();
+
+        let build_variants = expand_matrices(&project).unwrap();
+
+        assert_eq!(build_variants[0].name, "tests__version~6.0_arch~x86");
+        assert_eq!(
+            build_variants[0].run_on,
+            Some(vec!["ubuntu2004".to_string()])
+        );
+    }
+
+    fn matrix_of(project: &mut EvgProject) -> &mut MatrixDefinition {
+        project.buildvariants[1].matrix_mut().unwrap()
+    }
+
+    #[test]
+    fn test_invalid_matrices() {
+        let mut project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        matrix_of(&mut project)
+            .matrix_spec
+            .insert("arch".to_string(), AxisSelection::One("*".to_string()));
+        assert!(matches!(
+            expand_matrices(&project),
+            Err(MatrixError::UndefinedAxis { axis, .. }) if axis == "arch"
+        ));
+
+        let mut project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        matrix_of(&mut project)
+            .matrix_spec
+            .insert("os".to_string(), AxisSelection::One("solaris".to_string()));
+        assert!(matches!(
+            expand_matrices(&project),
+            Err(MatrixError::UnmatchedSelector { selector, .. }) if selector == "solaris"
+        ));
+
+        let mut project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        project.buildvariants[0].build_variant_mut().unwrap().name =
+            "tests__os~linux_version~6.0".to_string();
+        assert!(matches!(
+            expand_matrices(&project),
+            Err(MatrixError::DuplicateVariant(name)) if name == "tests__os~linux_version~6.0"
+        ));
+    }
+}
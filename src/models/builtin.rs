@@ -1,7 +1,9 @@
 use crate::models::params::{KeyValueParam, S3CopyFile};
+use crate::yaml::from_value;
 
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 
 /// Describe how task failures should be indicated.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -102,6 +104,24 @@ pub struct ArchiveTargzExtractParams {
     /// A list of filename globs to exclude.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exclude_files: Option<Vec<String>>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Parameters describing how to extract files from an archive of any supported format.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArchiveAutoExtractParams {
+    /// Path to archive to extract.
+    pub path: String,
+    /// Path of directory to extract files to.
+    pub destination: String,
+    /// A list of filename globs to exclude.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_files: Option<Vec<String>>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to create a gzipped tarball.
@@ -116,6 +136,9 @@ pub struct ArchiveTargzPackParams {
     /// A list of filename globs to exclude.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exclude_files: Option<Vec<String>>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to attach artifacts to a task.
@@ -133,6 +156,10 @@ pub struct AttachArtifactsParams {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ignore_artifacts_for_spawn: Option<bool>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to attach Evergreen test results format to task.
@@ -140,6 +167,9 @@ pub struct AttachArtifactsParams {
 pub struct AttachResultsParams {
     /// Path to a json file to parse and upload.
     pub file_location: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to attach XUnit test results format to task.
@@ -152,6 +182,10 @@ pub struct AttachXUnitResultsParams {
     /// List of paths to a xunit file to parse and upload.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub files: Option<Vec<String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to update task expansions at runtime.
@@ -171,6 +205,10 @@ pub struct ExpansionsUpdateParams {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env: Option<HashMap<String, String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to write task's expansions to a file.
@@ -182,6 +220,10 @@ pub struct ExpansionsWriteParams {
     /// Include redacted landscape variable.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub redacted: Option<bool>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to generate dynamic tasks.
@@ -189,6 +231,9 @@ pub struct ExpansionsWriteParams {
 pub struct GenerateTasksParams {
     /// List of json files to generate tasks from.
     pub files: Vec<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to clone tracked landscape and apply revision associated with task.
@@ -205,6 +250,10 @@ pub struct GitGetProjectParams {
     /// Map of revisions to use for any modules.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub revisions: Option<HashMap<String, String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to parse gotest results and attach them to the task.
@@ -212,6 +261,9 @@ pub struct GitGetProjectParams {
 pub struct GotestParseFilesParams {
     /// List of globs to parse and attach.
     pub files: Vec<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -231,6 +283,9 @@ pub struct EbsDevice {
     pub ebs_iops: u64,
     pub ebs_size: u64,
     pub ebs_snapshot_id: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Docker registry settings.
@@ -246,6 +301,10 @@ pub struct RegistrySettings {
     /// Password for the registry.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub registry_password: Option<String>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to start a new host from a task.
@@ -378,6 +437,10 @@ pub struct HostCreateParams {
     /// Map of environment variables to pass to container.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub environment_vars: Option<HashMap<String, String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to get information about hosts previously created.
@@ -395,6 +458,9 @@ pub struct HostListParams {
     /// If true, do not log host info to the task logs.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub silent: Option<bool>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to save json-formatted task data.
@@ -405,6 +471,10 @@ pub struct JsonSendParams {
 
     /// Name of the file you're saving.
     pub name: String,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to increment a key val.
@@ -412,6 +482,9 @@ pub struct JsonSendParams {
 pub struct KeyValIncParams {
     pub destination: String,
     pub key: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to send perf results to cedar.
@@ -435,6 +508,10 @@ pub struct PerfSendParams {
     /// AWS region of the bucket.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub region: Option<String>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to download a file from S3.
@@ -461,6 +538,10 @@ pub struct S3GetParams {
     // List of build variants to run command for.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub build_variants: Option<Vec<String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to upload a file from S3.
@@ -511,6 +592,10 @@ pub struct S3PutParams {
     // AWS visibility of uploaded file.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub visibility: Option<S3Visibility>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to copy an S3 file.
@@ -524,6 +609,10 @@ pub struct S3CopyParams {
 
     /// AWS secret to use to download file.
     pub aws_secret: String,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to run a shell script.
@@ -579,6 +668,10 @@ pub struct ShellExecParams {
     /// If true, send stderr to stdout.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub redirect_standard_error_to_output: Option<bool>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters common to SubprocessExec and SubprocessScripting.
@@ -619,6 +712,10 @@ pub struct SubprocessExecutionConfig {
     /// Specify 1 or more expansions to include in the shell's env.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub include_expansions_in_env: Option<Vec<String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to run a binary file.
@@ -677,6 +774,10 @@ pub struct ScriptingTestOptions {
     /// The number of times test should be run.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub count: Option<u64>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Parameters describing how to execute a command insdie a scripting harness.
@@ -736,11 +837,43 @@ pub struct TimeoutUpdateParams {
     /// Set the maximum time that can elapse with no output to stdout.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout_secs: Option<TimeoutValue>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
+/// Names of the built-in commands described by `EvgCommandSpec`.
+const BUILTIN_COMMANDS: &[&str] = &[
+    "archive.targz_extract",
+    "archive.targz_pack",
+    "archive.auto_extract",
+    "attach.artifacts",
+    "attach.results",
+    "attach.xunit_results",
+    "expansions.update",
+    "expansions.write",
+    "generate.tasks",
+    "git.get_project",
+    "gotest.parse_files",
+    "host.create",
+    "host.list",
+    "json.send",
+    "keyval.inc",
+    "manifest.load",
+    "perf.send",
+    "s3.get",
+    "s3.put",
+    "s3Copy.copy",
+    "shell.exec",
+    "subprocess.exec",
+    "subprocess.scripting",
+    "timeout.update",
+];
+
 /// Built-in Evergreen Commands.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(tag = "command", content = "params")]
+#[serde(remote = "Self", tag = "command", content = "params")]
 pub enum EvgCommandSpec {
     /// Extract files from a a gzipped tarball.
     #[serde(rename = "archive.targz_extract")]
@@ -750,8 +883,9 @@ pub enum EvgCommandSpec {
     #[serde(rename = "archive.targz_pack")]
     ArchiveTargzPack(ArchiveTargzPackParams),
 
+    /// Extract files from an archive, detecting its format.
     #[serde(rename = "archive.auto_extract")]
-    ArchiveAutoExtract,
+    ArchiveAutoExtract(ArchiveAutoExtractParams),
 
     /// Upload files to be include in the "Files" section of a task.
     #[serde(rename = "attach.artifacts")]
@@ -835,6 +969,107 @@ pub enum EvgCommandSpec {
     /// Set the timeouts for the current task.
     #[serde(rename = "timeout.update")]
     TimeoutUpdate(TimeoutUpdateParams),
+
+    /// A command shrub does not know about, kept as-is so it can be written back out.
+    #[serde(skip)]
+    Unknown {
+        /// Name of the command.
+        command: String,
+        /// Parameters given to the command, `Null` if none were given.
+        params: serde_yaml::Value,
+    },
+}
+
+impl EvgCommandSpec {
+    /// Get the name of the command, e.g. `shell.exec`.
+    pub fn name(&self) -> &str {
+        use EvgCommandSpec::*;
+        match self {
+            ArchiveTargzExtract(_) => "archive.targz_extract",
+            ArchiveTargzPack(_) => "archive.targz_pack",
+            ArchiveAutoExtract(_) => "archive.auto_extract",
+            AttachArtifacts(_) => "attach.artifacts",
+            AttachResults(_) => "attach.results",
+            AttachXUnitResults(_) => "attach.xunit_results",
+            ExpansionsUpdate(_) => "expansions.update",
+            ExpansionsWrite(_) => "expansions.write",
+            GenerateTasks(_) => "generate.tasks",
+            GitGetProject(_) => "git.get_project",
+            GotestParseFiles(_) => "gotest.parse_files",
+            HostCreate(_) => "host.create",
+            HostList(_) => "host.list",
+            JsonSend(_) => "json.send",
+            KeyValInc(_) => "keyval.inc",
+            ManifestLoad => "manifest.load",
+            PerfSend(_) => "perf.send",
+            S3Get(_) => "s3.get",
+            S3Put(_) => "s3.put",
+            S3Copy(_) => "s3Copy.copy",
+            ShellExec(_) => "shell.exec",
+            SubprocessExec(_) => "subprocess.exec",
+            SubprocessScripting(_) => "subprocess.scripting",
+            TimeoutUpdate(_) => "timeout.update",
+            Unknown { command, .. } => command,
+        }
+    }
+}
+
+impl Serialize for EvgCommandSpec {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            EvgCommandSpec::Unknown { command, params } => {
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("command", command)?;
+                if !params.is_null() {
+                    map.serialize_entry("params", params)?;
+                }
+                map.end()
+            }
+            _ => EvgCommandSpec::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for EvgCommandSpec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawCommandSpec {
+            command: String,
+            #[serde(default)]
+            params: serde_yaml::Value,
+        }
+
+        /// Wrapper to deserialize a command using the derived implementation.
+        struct KnownCommandSpec(EvgCommandSpec);
+
+        impl<'de> Deserialize<'de> for KnownCommandSpec {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                EvgCommandSpec::deserialize(deserializer).map(KnownCommandSpec)
+            }
+        }
+
+        let RawCommandSpec { command, params } = RawCommandSpec::deserialize(deserializer)?;
+        if !BUILTIN_COMMANDS.contains(&command.as_str()) {
+            return Ok(EvgCommandSpec::Unknown { command, params });
+        }
+
+        let mut raw = serde_yaml::Mapping::new();
+        raw.insert("command".into(), command.into());
+        if !params.is_null() {
+            raw.insert("params".into(), params);
+        }
+        let known: KnownCommandSpec = from_value(serde_yaml::Value::Mapping(raw))?;
+        Ok(known.0)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -849,4 +1084,150 @@ pub struct BuiltInCommand {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub params_yaml: Option<String>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+#[cfg(test)]
+mod command_spec_tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_commands_are_preserved() {
+        let yaml = "command: new.command\nparams:\n  b: 1\n  a:\n    - x\n    - y\ntype: setup\n";
+
+        let command: BuiltInCommand = serde_yaml::from_str(yaml).unwrap();
+
+        match &command.command {
+            EvgCommandSpec::Unknown { command, params } => {
+                assert_eq!(command, "new.command");
+                assert_eq!(params["b"], serde_yaml::Value::from(1));
+            }
+            _ => panic!("Expected an unknown command, got {:?}", command),
+        }
+        assert_eq!(
+            serde_yaml::to_string(&command).unwrap(),
+            format!("---\n{}", yaml)
+        );
+    }
+
+    #[test]
+    fn test_unknown_commands_without_params() {
+        let yaml = "---\ncommand: new.command\n";
+
+        let command: BuiltInCommand = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(command.command.name(), "new.command");
+        assert_eq!(serde_yaml::to_string(&command).unwrap(), yaml);
+    }
+
+    #[test]
+    fn test_known_commands_with_bad_params_fail() {
+        let yaml = "command: shell.exec\nparams:\n  working_dir: src\n";
+
+        let result: Result<BuiltInCommand, _> = serde_yaml::from_str(yaml);
+
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("script"), "{}", message);
+    }
+
+    #[test]
+    fn test_builtin_command_names_match_the_command_specs() {
+        use EvgCommandSpec::*;
+        // Each name paired with a check for the variant it deserializes into.
+        type IsSpec = fn(&EvgCommandSpec) -> bool;
+        let specs: &[(&str, IsSpec)] = &[
+            ("archive.targz_extract", |c| {
+                matches!(c, ArchiveTargzExtract(_))
+            }),
+            ("archive.targz_pack", |c| matches!(c, ArchiveTargzPack(_))),
+            ("archive.auto_extract", |c| {
+                matches!(c, ArchiveAutoExtract(_))
+            }),
+            ("attach.artifacts", |c| matches!(c, AttachArtifacts(_))),
+            ("attach.results", |c| matches!(c, AttachResults(_))),
+            ("attach.xunit_results", |c| {
+                matches!(c, AttachXUnitResults(_))
+            }),
+            ("expansions.update", |c| matches!(c, ExpansionsUpdate(_))),
+            ("expansions.write", |c| matches!(c, ExpansionsWrite(_))),
+            ("generate.tasks", |c| matches!(c, GenerateTasks(_))),
+            ("git.get_project", |c| matches!(c, GitGetProject(_))),
+            ("gotest.parse_files", |c| matches!(c, GotestParseFiles(_))),
+            ("host.create", |c| matches!(c, HostCreate(_))),
+            ("host.list", |c| matches!(c, HostList(_))),
+            ("json.send", |c| matches!(c, JsonSend(_))),
+            ("keyval.inc", |c| matches!(c, KeyValInc(_))),
+            ("manifest.load", |c| matches!(c, ManifestLoad)),
+            ("perf.send", |c| matches!(c, PerfSend(_))),
+            ("s3.get", |c| matches!(c, S3Get(_))),
+            ("s3.put", |c| matches!(c, S3Put(_))),
+            ("s3Copy.copy", |c| matches!(c, S3Copy(_))),
+            ("shell.exec", |c| matches!(c, ShellExec(_))),
+            ("subprocess.exec", |c| matches!(c, SubprocessExec(_))),
+            ("subprocess.scripting", |c| {
+                matches!(c, SubprocessScripting(_))
+            }),
+            ("timeout.update", |c| matches!(c, TimeoutUpdate(_))),
+        ];
+        let names: Vec<&str> = specs.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, BUILTIN_COMMANDS);
+
+        // Parameters required by any of the commands.
+        let params = r#"
+path: archive.tgz
+destination: dst
+target: archive.tgz
+source_dir: src
+include: ["*"]
+files: ["results.json"]
+file_location: results.json
+file: data.json
+name: data
+key: counter
+num_hosts: 1
+timeout_seconds: 60
+wait: true
+provider: ec2
+remote_file: remote/file
+aws_key: key
+aws_secret: secret
+bucket: bucket
+prefix: perf
+permissions: private
+content_type: text/plain
+s3_copy_files: []
+script: echo hello
+harness: python
+"#;
+        for (name, is_spec) in specs {
+            let yaml = format!(
+                "command: {}\nparams: {}",
+                name,
+                params.replace('\n', "\n  ")
+            );
+            let command: BuiltInCommand = serde_yaml::from_str(&yaml)
+                .or_else(|_| serde_yaml::from_str(&format!("command: {}", name)))
+                .unwrap_or_else(|err| panic!("{}: {}", name, err));
+            assert!(is_spec(&command.command), "{}", name);
+            assert_eq!(command.command.name(), *name);
+
+            let written = serde_yaml::to_string(&command).unwrap();
+            let command: BuiltInCommand = serde_yaml::from_str(&written).unwrap();
+            assert!(is_spec(&command.command), "{}", name);
+            assert_eq!(command.command.name(), *name);
+        }
+    }
+
+    #[test]
+    fn test_known_commands_round_trip() {
+        let yaml = "---\ncommand: shell.exec\nparams:\n  script: echo hello\n";
+
+        let command: BuiltInCommand = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(command.command.name(), "shell.exec");
+        assert_eq!(serde_yaml::to_string(&command).unwrap(), yaml);
+    }
 }
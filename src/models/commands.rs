@@ -6,30 +6,52 @@
 //! for more details.
 use crate::models::builtin::BuiltInCommand;
 use crate::models::params::ParamValue;
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::yaml::{from_value, lenient};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FunctionCall {
+    #[serde(deserialize_with = "lenient::string")]
     pub func: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub vars: Option<HashMap<String, ParamValue>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timeout_secs: Option<u64>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Clone)]
 #[serde(untagged)]
 pub enum EvgCommand {
     Function(FunctionCall),
     BuiltIn(BuiltInCommand),
 }
 
+impl<'de> Deserialize<'de> for EvgCommand {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Decide which kind of command this is up front so errors describe what is wrong
+        // with the command instead of just failing to match either kind.
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        if value.get("func").is_some() {
+            Ok(EvgCommand::Function(from_value(value)?))
+        } else {
+            Ok(EvgCommand::BuiltIn(from_value(value)?))
+        }
+    }
+}
+
 pub fn fn_call(name: &str) -> EvgCommand {
     EvgCommand::Function(FunctionCall {
         func: name.to_string(),
         vars: None,
         timeout_secs: None,
+        extra: BTreeMap::new(),
     })
 }
 
@@ -38,6 +60,7 @@ pub fn fn_call_with_params(name: &str, vars: HashMap<String, ParamValue>) -> Evg
         func: String::from(name),
         vars: Some(vars),
         timeout_secs: None,
+        extra: BTreeMap::new(),
     })
 }
 
@@ -0,0 +1,254 @@
+//! Matrices define a build variant for each combination of the values of several axes.
+//!
+//! Axes are defined at the top level of a project and matrices are defined in the list of build
+//! variants, alongside other build variants. See Evergreen's
+//! [documentation](https://github.com/evergreen-ci/evergreen/wiki/Matrix-Variant-Definition)
+//! for more details. Matrices are expanded into build variants with
+//! [`crate::matrix::expand_matrices`].
+use crate::models::task::TaskRef;
+use crate::models::variant::DisplayTask;
+use crate::yaml::lenient;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Settings applied to the build variants an axis value or matrix rule applies to.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MatrixSettings {
+    /// Expansions to add to the build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub variables: Option<BTreeMap<String, String>>,
+    /// List of distros tasks run on by default.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub run_on: Option<Vec<String>>,
+    /// Tags to add to the build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tags: Option<Vec<String>>,
+    /// List of modules that should be included in tasks for the build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modules: Option<Vec<String>>,
+    /// How frequently tasks should be run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batchtime: Option<u64>,
+    /// Should failed tasks be run on skipped versions to find their source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stepback: Option<bool>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// A possible value of an axis.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AxisValue {
+    /// ID of the value, used in the names of build variants.
+    #[serde(deserialize_with = "lenient::string")]
+    pub id: String,
+    /// Name of the value to use in display names of build variants.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<String>,
+    /// Settings of build variants with this value.
+    #[serde(flatten)]
+    pub settings: MatrixSettings,
+}
+
+/// Something build variants can vary by, like the operating system or a server version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Axis {
+    /// ID of the axis, used to refer to it in matrices.
+    #[serde(deserialize_with = "lenient::string")]
+    pub id: String,
+    /// Display name of the axis.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<String>,
+    /// Possible values of the axis.
+    pub values: Vec<AxisValue>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Selectors of the values of an axis.
+///
+/// Each selector is a value ID, a tag selector (`.tag !.other`) or `*` for every value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AxisSelection {
+    /// A single selector.
+    One(String),
+    /// Several selectors, a value is selected if any of them match.
+    Many(Vec<String>),
+}
+
+impl AxisSelection {
+    /// Get the selectors of this selection.
+    pub fn selectors(&self) -> &[String] {
+        match self {
+            AxisSelection::One(selector) => std::slice::from_ref(selector),
+            AxisSelection::Many(selectors) => selectors,
+        }
+    }
+}
+
+/// Selected values of each axis, keyed by axis ID.
+pub type MatrixSpec = BTreeMap<String, AxisSelection>;
+
+/// One or more matrix specs, written as a single spec or a list of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum MatrixSpecs {
+    /// A single spec.
+    One(MatrixSpec),
+    /// Several specs.
+    Many(Vec<MatrixSpec>),
+}
+
+impl MatrixSpecs {
+    /// Get the specs.
+    pub fn specs(&self) -> &[MatrixSpec] {
+        match self {
+            MatrixSpecs::One(spec) => std::slice::from_ref(spec),
+            MatrixSpecs::Many(specs) => specs,
+        }
+    }
+}
+
+/// Changes a rule makes to the build variants it matches.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatrixRuleAction {
+    /// Tasks to add to the build variant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_tasks: Option<Vec<TaskRef>>,
+    /// Names of tasks to remove from the build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub remove_tasks: Option<Vec<String>>,
+    /// Settings to apply to the build variant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub set: Option<MatrixSettings>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Changes to make to the build variants of a matrix that match some specs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatrixRule {
+    /// Build variants the rule applies to.
+    #[serde(rename = "if")]
+    pub if_spec: MatrixSpecs,
+    /// Changes to make to them.
+    pub then: MatrixRuleAction,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Definition of a matrix of build variants.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatrixDefinition {
+    /// Name of the matrix, used as the start of the names of its build variants.
+    #[serde(deserialize_with = "lenient::string")]
+    pub matrix_name: String,
+
+    /// Values of each axis to create build variants for.
+    pub matrix_spec: MatrixSpec,
+
+    /// Combinations of values to not create build variants for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_spec: Option<MatrixSpecs>,
+
+    /// Display name of build variants, which can reference axes like `${os}`.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<String>,
+
+    /// List of tasks to add to each build variant.
+    #[serde(default)]
+    pub tasks: Vec<TaskRef>,
+
+    /// List of display tasks in each build variant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_tasks: Option<Vec<DisplayTask>>,
+
+    /// List of distros tasks run on by default.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub run_on: Option<Vec<String>>,
+
+    /// How frequently tasks should be run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batchtime: Option<u64>,
+
+    /// Map of expansions that should be passed to tasks at runtime.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expansions: Option<BTreeMap<String, String>>,
+
+    /// Should failed tasks be run on skipped versions to find their source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stepback: Option<bool>,
+
+    /// List of modules that should be included in tasks for each build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modules: Option<Vec<String>>,
+
+    /// Should created tasks for each build variant be scheduled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activate: Option<bool>,
+
+    /// Tags used to select each build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tags: Option<Vec<String>>,
+
+    /// Changes to make to some of the build variants.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<MatrixRule>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
@@ -1,5 +1,6 @@
 pub mod builtin;
 pub mod commands;
+pub mod matrix;
 pub mod params;
 pub mod project;
 pub mod task;
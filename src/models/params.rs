@@ -1,7 +1,7 @@
 use core::fmt;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{Display, Formatter},
 };
 
@@ -12,6 +12,9 @@ pub struct S3Location {
     pub bucket: String,
     /// Path within S3 bucket.
     pub path: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Description of how to copy an AWS S3 file.
@@ -29,6 +32,10 @@ pub struct S3CopyFile {
     pub build_variants: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub optional: Option<bool>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Key-Value pair used to create a parameter map.
@@ -38,6 +45,9 @@ pub struct KeyValueParam {
     pub key: String,
     /// Value of Key-Value pair.
     pub value: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -3,16 +3,21 @@
 //! See Evergreen's
 //! [documentation](https://github.com/evergreen-ci/evergreen/wiki/Project-Configuration-Files)
 //! for more details on how a projects configuration.
-use crate::models::builtin::EvgCommandType;
+use crate::error::ShrubError;
+use crate::models::builtin::{BuiltInCommand, EvgCommandSpec, EvgCommandType};
 use crate::models::commands::EvgCommand;
+use crate::models::matrix::{Axis, MatrixDefinition};
 use crate::models::task::EvgTask;
 use crate::models::task_group::EvgTaskGroup;
-use crate::models::variant::BuildVariant;
-use serde::{Deserialize, Serialize};
-use simple_error::bail;
-use std::{collections::HashMap, error::Error};
+use crate::models::variant::{BuildVariant, VariantEntry};
+use crate::selector::Selector;
+use crate::validate::{validate, Diagnostic};
+use crate::yaml::{from_value, lenient};
+use crate::yaml::{into_value, join_paths, locate, split_nested_path};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use yaml_merge_keys::merge_keys;
-use yaml_rust::{YamlEmitter, YamlLoader};
+use yaml_rust::YamlLoader;
 
 /// Description of an evergreen parameter.
 ///
@@ -23,29 +28,63 @@ use yaml_rust::{YamlEmitter, YamlLoader};
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EvgParameter {
     /// Name of parameter.
+    #[serde(deserialize_with = "lenient::string")]
     pub key: String,
     /// Default value to use for parameter.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub value: Option<String>,
     /// Description of parameter.
+    #[serde(deserialize_with = "lenient::string")]
     pub description: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Description of a module to include in a landscape.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EvgModule {
     /// Name of module being defined.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
     /// Repository containing module to be included.
+    #[serde(deserialize_with = "lenient::string")]
     pub repo: String,
     /// Branch of repository to use.
+    #[serde(deserialize_with = "lenient::string")]
     pub branch: String,
     /// Path to store module code at.
+    #[serde(deserialize_with = "lenient::string")]
     pub prefix: String,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Description of another configuration file to include in a landscape.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EvgInclude {
+    /// Path of the file to include.
+    #[serde(deserialize_with = "lenient::string")]
+    pub filename: String,
+    /// Name of the module the file belongs to, if it is not part of this landscape.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub module: Option<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Definition of an Evergreen function.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum FunctionDefinition {
     /// Function composed of a single Evergreen command.
@@ -54,18 +93,47 @@ pub enum FunctionDefinition {
     CommandList(Vec<EvgCommand>),
 }
 
+impl FunctionDefinition {
+    /// Get the commands that make up this function.
+    pub fn commands(&self) -> &[EvgCommand] {
+        match self {
+            FunctionDefinition::SingleCommand(command) => std::slice::from_ref(command),
+            FunctionDefinition::CommandList(commands) => commands,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FunctionDefinition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        if value.is_sequence() {
+            Ok(FunctionDefinition::CommandList(from_value(value)?))
+        } else {
+            Ok(FunctionDefinition::SingleCommand(from_value(value)?))
+        }
+    }
+}
+
 /// Description of an Evergreen Project.
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct EvgProject {
-    /// List of build variants belonging to this landscape.
-    pub buildvariants: Vec<BuildVariant>,
+    /// List of build variants and matrices of build variants belonging to this landscape.
+    #[serde(default)]
+    pub buildvariants: Vec<VariantEntry>,
+    /// Axes the build variant matrices are made from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub axes: Option<Vec<Axis>>,
     /// List of task definitions.
+    #[serde(default)]
     pub tasks: Vec<EvgTask>,
     /// List of task group definitions.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub task_groups: Option<Vec<EvgTaskGroup>>,
     /// Definitions of functions belonging to this landscape.
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub functions: HashMap<String, FunctionDefinition>,
     /// List of commands to run at the start of each task.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -94,38 +162,81 @@ pub struct EvgProject {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub command_type: Option<EvgCommandType>,
     /// List of globs that describe file changes that won't trigger a new build.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub ignore: Option<Vec<String>>,
     /// Parameters that can be specified to customize patch build functionality.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub parameters: Option<Vec<EvgParameter>>,
+
+    /// Other configuration files to include in this landscape.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<EvgInclude>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl EvgProject {
     /// Parse the given YAML string into an Evergreen Project.
-    pub fn from_yaml_str(yaml_contents: &str) -> Result<EvgProject, Box<dyn Error>> {
+    ///
+    /// Schema errors report the path to the offending node along with its location in
+    /// `yaml_contents`.
+    pub fn from_yaml_str(yaml_contents: &str) -> Result<EvgProject, ShrubError> {
         // Evergreen config can use merge-keys, which is not supported by
-        // serde-yaml, so we need to merge the keys first.
+        // serde-yaml, so we need to merge the keys first. The merged document is
+        // deserialized directly instead of being written back out and re-parsed.
         let mut raw = YamlLoader::load_from_str(yaml_contents)?;
         if raw.len() != 1 {
-            bail!("Expected 1 and only 1 yaml document")
+            return Err(ShrubError::MultiDocument { found: raw.len() });
         }
         let raw = raw.remove(0);
         let merged = merge_keys(raw)?;
 
-        let mut out_str = String::new();
-        {
-            let mut emitter = YamlEmitter::new(&mut out_str);
-            emitter.dump(&merged)?;
-        }
+        serde_path_to_error::deserialize(into_value(merged)).map_err(|err| {
+            let path = err.path().to_string();
+            let message = err.into_inner().to_string();
+            // Errors inside of commands are reported relative to the command, so join them
+            // onto the path to the command.
+            let (message, path) = match split_nested_path(&message) {
+                (message, Some(nested)) => (message.to_string(), join_paths(&path, nested)),
+                (message, None) => (message.to_string(), path),
+            };
+            ShrubError::Schema {
+                location: locate(yaml_contents, &path),
+                path,
+                message,
+            }
+        })
+    }
 
-        Ok(serde_yaml::from_str(&out_str)?)
+    /// Get the build variants of this project, leaving out matrices.
+    pub fn build_variants(&self) -> impl Iterator<Item = &BuildVariant> {
+        self.buildvariants
+            .iter()
+            .filter_map(|entry| entry.build_variant())
+    }
+
+    /// Get mutable references to the build variants of this project, leaving out matrices.
+    pub fn build_variants_mut(&mut self) -> impl Iterator<Item = &mut BuildVariant> {
+        self.buildvariants
+            .iter_mut()
+            .filter_map(|entry| entry.build_variant_mut())
+    }
+
+    /// Get the matrices of build variants of this project.
+    pub fn matrices(&self) -> impl Iterator<Item = &MatrixDefinition> {
+        self.buildvariants.iter().filter_map(|entry| entry.matrix())
     }
 
     /// Build a map of the defined build variants.
     pub fn build_variant_map(&self) -> HashMap<String, &BuildVariant> {
         let mut map = HashMap::with_capacity(self.buildvariants.len());
-        self.buildvariants.iter().for_each(|bv| {
+        self.build_variants().for_each(|bv| {
             map.insert(bv.name.to_string(), bv);
         });
         map
@@ -139,11 +250,103 @@ impl EvgProject {
         });
         map
     }
+
+    /// Build a map of the defined task groups.
+    pub fn task_group_map(&self) -> HashMap<String, &EvgTaskGroup> {
+        let mut map = HashMap::new();
+        self.task_groups.iter().flatten().for_each(|tg| {
+            map.insert(tg.name.to_string(), tg);
+        });
+        map
+    }
+
+    /// Get the tasks that run on the given build variant.
+    ///
+    /// Selectors (`.tag !.other`) are expanded to the tasks and task groups they match and task
+    /// groups to the tasks they contain. References to tasks that are not defined and malformed
+    /// selectors are ignored.
+    pub fn tasks_on_variant(&self, build_variant: &BuildVariant) -> Vec<&EvgTask> {
+        let task_map = self.task_def_map();
+        let task_group_map = self.task_group_map();
+        let mut names: Vec<&str> = vec![];
+        for task_ref in &build_variant.tasks {
+            let units = Selector::parse(&task_ref.name).and_then(|s| s.select_task_units(self));
+            for name in units.unwrap_or_default() {
+                match task_group_map.get(name) {
+                    Some(task_group) => names.extend(task_group.tasks.iter().map(|t| t.as_str())),
+                    None => names.push(name),
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        names
+            .into_iter()
+            .filter(|name| seen.insert(*name))
+            .filter_map(|name| task_map.get(name).copied())
+            .collect()
+    }
+
+    /// Check this project for references to things that are not defined.
+    ///
+    /// See [`crate::validate`] for details on what is checked.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validate(self)
+    }
+
+    /// Get every command in this project, including those in functions and task groups.
+    pub fn all_commands(&self) -> Vec<&EvgCommand> {
+        let mut command_lists: Vec<&[EvgCommand]> = vec![];
+        for block in [&self.pre, &self.post, &self.timeout]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            command_lists.push(block);
+        }
+        for function in self.functions.values() {
+            command_lists.push(function.commands());
+        }
+        for task in &self.tasks {
+            if let Some(commands) = &task.commands {
+                command_lists.push(commands);
+            }
+        }
+        for task_group in self.task_groups.iter().flatten() {
+            let blocks = [
+                &task_group.setup_group,
+                &task_group.teardown_group,
+                &task_group.setup_task,
+                &task_group.teardown_task,
+                &task_group.timeout,
+            ];
+            for block in blocks.iter().copied().flatten() {
+                command_lists.push(block);
+            }
+        }
+
+        command_lists.into_iter().flatten().collect()
+    }
+
+    /// Get the names of commands used in this project that shrub does not recognize.
+    pub fn unknown_commands(&self) -> BTreeSet<String> {
+        self.all_commands()
+            .into_iter()
+            .filter_map(|command| match command {
+                EvgCommand::BuiltIn(BuiltInCommand {
+                    command: EvgCommandSpec::Unknown { command, .. },
+                    ..
+                }) => Some(command.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::SourceLocation;
 
     #[test]
     fn test_an_empty_document_fails() {
@@ -160,4 +363,371 @@ mod test {
         let result = EvgProject::from_yaml_str(document);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_multiple_documents_fail() {
+        let document = "tasks: []\n---\ntasks: []\n";
+
+        let result = EvgProject::from_yaml_str(document);
+        assert!(matches!(
+            result,
+            Err(ShrubError::MultiDocument { found: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_yaml_syntax_errors_include_location() {
+        let document = "tasks:\n  - name: [unclosed\n";
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::YamlSyntax { location, .. }) => assert_eq!(location.line, 3),
+            _ => panic!("Expected a syntax error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unquoted_scalars_are_read_as_strings() {
+        let document = r#"
+parameters:
+  - key: jobs
+    value: 10
+    description: Number of jobs
+axes:
+  - id: version
+    values:
+      - id: 5.0
+        variables:
+          legacy: false
+tasks:
+  - name: 123
+    tags: [1]
+buildvariants:
+  - name: linux
+    expansions:
+      num_jobs: 8
+      flag: true
+    tasks:
+      - name: 123
+"#;
+
+        let project = EvgProject::from_yaml_str(document).unwrap();
+
+        assert_eq!(project.parameters.unwrap()[0].value.as_deref(), Some("10"));
+        let value = &project.axes.unwrap()[0].values[0];
+        assert_eq!(value.id, "5.0");
+        assert_eq!(
+            value.settings.variables.as_ref().unwrap()["legacy"],
+            "false"
+        );
+        assert_eq!(project.tasks[0].name, "123");
+        assert_eq!(project.tasks[0].tags, Some(vec!["1".to_string()]));
+        let linux = project.buildvariants[0].build_variant().unwrap();
+        let expansions = linux.expansions.as_ref().unwrap();
+        assert_eq!(expansions["num_jobs"], "8");
+        assert_eq!(expansions["flag"], "true");
+        assert_eq!(linux.tasks[0].name, "123");
+    }
+
+    #[test]
+    fn test_build_variant_order_survives_a_round_trip() {
+        let document = r#"
+axes:
+  - id: os
+    values:
+      - id: linux
+buildvariants:
+  - matrix_name: tests
+    matrix_spec:
+      os: "*"
+    tasks:
+      - name: test
+  - name: lint
+    tasks:
+      - name: lint
+"#;
+        let project = EvgProject::from_yaml_str(document).unwrap();
+
+        let yaml = serde_yaml::to_string(&project).unwrap();
+        let read_back = EvgProject::from_yaml_str(&yaml).unwrap();
+
+        assert!(read_back.buildvariants[0].matrix().is_some());
+        assert_eq!(
+            read_back.buildvariants[1].build_variant().unwrap().name,
+            "lint"
+        );
+    }
+
+    #[test]
+    fn test_schema_errors_include_path_and_location() {
+        let document = r#"
+tasks:
+  - name: compile
+buildvariants:
+  - name: bv_0
+    tasks:
+      - name: compile
+  - name: bv_1
+    tasks:
+      - name: compile
+        distros: ubuntu
+"#;
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::Schema { path, location, .. }) => {
+                assert_eq!(path, "buildvariants[1].tasks[0].distros");
+                assert_eq!(
+                    location,
+                    Some(SourceLocation {
+                        line: 11,
+                        column: 18
+                    })
+                );
+            }
+            _ => panic!("Expected a schema error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema_errors_inside_matrices_include_path() {
+        let document = r#"
+buildvariants:
+  - name: bv_0
+    tasks:
+      - name: compile
+  - matrix_name: tests
+    matrix_spec:
+      os: "*"
+    batchtime: daily
+"#;
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::Schema { path, location, .. }) => {
+                assert_eq!(path, "buildvariants[1].batchtime");
+                assert_eq!(
+                    location,
+                    Some(SourceLocation {
+                        line: 9,
+                        column: 16
+                    })
+                );
+            }
+            _ => panic!("Expected a schema error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema_errors_inside_commands_include_path() {
+        let document = r#"
+tasks:
+  - name: compile
+    commands:
+      - func: setup
+      - command: shell.exec
+        params:
+          script: echo hello
+          working_dir: [src]
+buildvariants: []
+"#;
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::Schema {
+                path,
+                location,
+                message,
+            }) => {
+                assert_eq!(path, "tasks[0].commands[1].params.working_dir");
+                assert_eq!(
+                    location,
+                    Some(SourceLocation {
+                        line: 9,
+                        column: 24
+                    })
+                );
+                assert!(message.starts_with("invalid type"), "{}", message);
+            }
+            _ => panic!("Expected a schema error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema_errors_inside_functions_include_path() {
+        let document = r#"
+functions:
+  setup:
+    - command: shell.exec
+      params:
+        script: echo hello
+    - command: subprocess.exec
+      params:
+        binary: [bash]
+tasks: []
+buildvariants: []
+"#;
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::Schema { path, location, .. }) => {
+                assert_eq!(path, "functions.setup[1].params.binary");
+                assert_eq!(
+                    location,
+                    Some(SourceLocation {
+                        line: 9,
+                        column: 17
+                    })
+                );
+            }
+            _ => panic!("Expected a schema error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_schema_errors_from_merge_keys_point_at_closest_node() {
+        let document = r#"
+variables:
+  - &defaults
+    distros: ubuntu
+tasks: []
+buildvariants:
+  - name: bv_0
+    tasks:
+      - <<: *defaults
+        name: compile
+"#;
+
+        let result = EvgProject::from_yaml_str(document);
+        match result {
+            Err(ShrubError::Schema { path, location, .. }) => {
+                assert_eq!(path, "buildvariants[0].tasks[0].distros");
+                assert_eq!(location, Some(SourceLocation { line: 9, column: 9 }));
+            }
+            _ => panic!("Expected a schema error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_unknown_commands_are_listed() {
+        let document = r#"
+functions:
+  setup:
+    - command: new.command
+      params:
+        value: 42
+    - command: shell.exec
+      params:
+        script: echo hello
+pre:
+  - command: other.command
+tasks:
+  - name: compile
+    commands:
+      - func: setup
+      - command: new.command
+buildvariants: []
+"#;
+
+        let project = EvgProject::from_yaml_str(document).unwrap();
+
+        let unknown: Vec<String> = project.unknown_commands().into_iter().collect();
+        assert_eq!(unknown, vec!["new.command", "other.command"]);
+    }
+
+    /// Sort the keys of all mappings so values can be compared regardless of key order.
+    fn normalize(value: serde_yaml::Value) -> serde_yaml::Value {
+        match value {
+            serde_yaml::Value::Mapping(mapping) => {
+                let mut entries: Vec<(serde_yaml::Value, serde_yaml::Value)> = mapping
+                    .into_iter()
+                    .map(|(k, v)| (normalize(k), normalize(v)))
+                    .collect();
+                entries.sort_by_key(|(k, _)| serde_yaml::to_string(k).unwrap());
+                serde_yaml::Value::Mapping(entries.into_iter().collect())
+            }
+            serde_yaml::Value::Sequence(sequence) => {
+                serde_yaml::Value::Sequence(sequence.into_iter().map(normalize).collect())
+            }
+            _ => value,
+        }
+    }
+
+    #[test]
+    fn test_unmodelled_fields_survive_a_round_trip() {
+        let document = r#"
+exec_timeout_secs: 7200
+include:
+  - filename: etc/other.yml
+modules:
+  - name: enterprise
+    repo: git@github.com:10gen/enterprise.git
+    branch: master
+    prefix: src/modules
+    auto_update: true
+parameters:
+  - key: suite
+    description: Suite to run
+    value: core
+    hidden: true
+functions:
+  setup:
+    - command: shell.exec
+      display_name: setup shell
+      retry_on_failure: true
+      params:
+        script: echo hello
+        new_param: 1
+    - command: subprocess.exec
+      params:
+        binary: bash
+        new_param: 2
+pre:
+  - func: setup
+    timeout_secs: 60
+    retry_on_failure: true
+tasks:
+  - name: compile
+    run_on: rhel80-large
+    priority: 50
+    allowed_requesters: ["patch", "commit"]
+    commands:
+      - func: setup
+        vars:
+          suite: core
+      - command: new.command
+        params:
+          nested:
+            - a: 1
+  - name: lint
+    disable: true
+    depends_on:
+      - name: compile
+        status: "*"
+        patch_optional: true
+task_groups:
+  - name: compile_group
+    tasks: ["compile"]
+    teardown_task_can_fail_task: true
+buildvariants:
+  - name: bv_0
+    display_name: BV 0
+    cron: "0 * * * *"
+    tags: ["nightly"]
+    tasks:
+      - name: compile_group
+        priority: 10
+        batchtime: 60
+    display_tasks:
+      - name: everything
+        execution_tasks: ["compile"]
+        new_field: value
+"#;
+        let expected: serde_yaml::Value = serde_yaml::from_str(document).unwrap();
+
+        let project = EvgProject::from_yaml_str(document).unwrap();
+        let actual = serde_yaml::to_value(&project).unwrap();
+
+        assert_eq!(normalize(actual), normalize(expected));
+    }
 }
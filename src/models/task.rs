@@ -6,54 +6,167 @@
 //! or
 //! [functions](https://github.com/evergreen-ci/evergreen/wiki/Project-Configuration-Files#functions)
 //! unique to the landscape.
+use crate::models::builtin::TimeoutValue;
 use crate::models::commands::EvgCommand;
+use crate::yaml::lenient;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Description of a depedency for a task.
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TaskDependency {
     /// Name of task that needs to be run.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
     /// Build variant where dependent task is run.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub variant: Option<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Reference to a task that is being added to a build variant.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TaskRef {
     /// Name of task.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
     /// List of distros that task should be run on.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub distros: Option<Vec<String>>,
     /// Should task be scheduled when created.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub activate: Option<bool>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Kinds of requests that can create tasks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvgRequester {
+    /// Patches created by users.
+    Patch,
+    /// Patches created for GitHub pull requests.
+    GithubPr,
+    /// Versions created for GitHub tags.
+    GithubTag,
+    /// Versions created for commits to the tracked branch.
+    Commit,
+    /// Versions created by triggers from other projects.
+    Trigger,
+    /// Versions created by periodic builds or the API.
+    AdHoc,
+    /// Versions created by the GitHub merge queue.
+    GithubMergeQueue,
+    /// A requester shrub does not know about, kept as-is so it can be written back out.
+    #[serde(untagged)]
+    Other(String),
+}
+
+/// Distros a task runs on, written as a single distro or a list of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RunOn {
+    /// A single distro.
+    One(String),
+    /// Several distros.
+    Many(Vec<String>),
+}
+
+impl RunOn {
+    /// Get the distros.
+    pub fn distros(&self) -> &[String] {
+        match self {
+            RunOn::One(distro) => std::slice::from_ref(distro),
+            RunOn::Many(distros) => distros,
+        }
+    }
 }
 
 /// Definition of an Evergreen task.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EvgTask {
     /// Name of task being defined.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
     /// List of command that make up the task.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub commands: Option<Vec<EvgCommand>>,
     /// List of other tasks that need to be completed before this is done.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub depends_on: Option<Vec<TaskDependency>>,
-    /// How long this task can run before timing out (in seconds).
+    /// How long this task can run before timing out (in seconds), which can be an expansion.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub exec_timeout_secs: Option<u64>,
+    pub exec_timeout_secs: Option<TimeoutValue>,
     /// List of tags describing this task.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub tags: Option<Vec<String>>,
     /// Describe if this patch should be runnable in patch builds.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub patchable: Option<bool>,
+    /// Describe if this task should only be run in patch builds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch_only: Option<bool>,
+    /// Kinds of requests this task can be created for, instead of `patchable` and `patch_only`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_requesters: Option<Vec<EvgRequester>>,
     /// Describe if previously skipped versions of this task should be run on failure.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stepback: Option<bool>,
+    /// Name to display for this task.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub display_name: Option<String>,
+    /// List of distros this task runs on, overriding those of the build variant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_on: Option<RunOn>,
+    /// Scheduling priority of this task, a negative priority keeps it from being scheduled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+    /// How often this task should be activated on the tracked branch (in minutes).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batchtime: Option<u64>,
+    /// Cron specification of when this task should be activated, instead of `batchtime`.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cron: Option<String>,
+    /// Describe if this task should never be created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+    /// Describe if this task should fail if it does not report any test results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub must_have_test_results: Option<bool>,
+    /// Command to list running processes when diagnosing the task.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ps: Option<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl EvgTask {
@@ -65,6 +178,7 @@ impl EvgTask {
             name: self.name.clone(),
             distros,
             activate,
+            extra: BTreeMap::new(),
         }
     }
 }
@@ -78,7 +192,71 @@ impl Default for EvgTask {
             exec_timeout_secs: None,
             tags: None,
             patchable: None,
+            patch_only: None,
+            allowed_requesters: None,
             stepback: None,
+            display_name: None,
+            run_on: None,
+            priority: None,
+            batchtime: None,
+            cron: None,
+            disable: None,
+            must_have_test_results: None,
+            ps: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_on_can_be_a_string_or_a_list() {
+        let one: EvgTask = serde_yaml::from_str("name: compile\nrun_on: rhel80").unwrap();
+        let many: EvgTask =
+            serde_yaml::from_str("name: compile\nrun_on: [rhel80, ubuntu2004]").unwrap();
+
+        assert_eq!(one.run_on, Some(RunOn::One("rhel80".to_string())));
+        assert_eq!(one.run_on.unwrap().distros(), ["rhel80"]);
+        assert_eq!(many.run_on.unwrap().distros(), ["rhel80", "ubuntu2004"]);
+    }
+
+    #[test]
+    fn test_unknown_requesters_are_kept() {
+        let yaml = "name: compile\nallowed_requesters: [patch, github_pr, pull_request]";
+
+        let task: EvgTask = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(
+            task.allowed_requesters,
+            Some(vec![
+                EvgRequester::Patch,
+                EvgRequester::GithubPr,
+                EvgRequester::Other("pull_request".to_string()),
+            ])
+        );
+        let written = serde_yaml::to_string(&task).unwrap();
+        assert!(written.contains("- pull_request"));
+        let reread: EvgTask = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(reread.allowed_requesters, task.allowed_requesters);
+    }
+
+    #[test]
+    fn test_exec_timeout_secs_round_trips() {
+        for (yaml, expected) in [
+            ("3600", TimeoutValue::Int(3600)),
+            ("${exec_timeout}", TimeoutValue::from("${exec_timeout}")),
+        ] {
+            let task: EvgTask =
+                serde_yaml::from_str(&format!("name: compile\nexec_timeout_secs: {}", yaml))
+                    .unwrap();
+            assert_eq!(task.exec_timeout_secs.as_ref(), Some(&expected));
+
+            let written = serde_yaml::to_string(&task).unwrap();
+            let reread: EvgTask = serde_yaml::from_str(&written).unwrap();
+            assert_eq!(reread.exec_timeout_secs, Some(expected));
         }
     }
 }
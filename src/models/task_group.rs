@@ -1,4 +1,6 @@
+use crate::yaml::lenient;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use super::{builtin::TimeoutValue, commands::EvgCommand};
 
@@ -6,8 +8,10 @@ use super::{builtin::TimeoutValue, commands::EvgCommand};
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EvgTaskGroup {
     /// Name of task group.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
     /// Ordered list of tasks to include in group.
+    #[serde(deserialize_with = "lenient::strings")]
     pub tasks: Vec<String>,
 
     /// Number of hosts to spread group accross.
@@ -41,6 +45,14 @@ pub struct EvgTaskGroup {
     pub timeout: Option<Vec<EvgCommand>>,
 
     /// Task tags for this task group.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub tags: Option<Vec<String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
@@ -1,28 +1,44 @@
+use crate::models::matrix::MatrixDefinition;
 use crate::models::task::TaskRef;
-use serde::{Deserialize, Serialize};
+use crate::yaml::{from_value, lenient};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DisplayTask {
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
+    #[serde(deserialize_with = "lenient::strings")]
     pub execution_tasks: Vec<String>,
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 /// Representation of an Evergreen Build Variant.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildVariant {
     /// Name of build variant.
+    #[serde(deserialize_with = "lenient::string")]
     pub name: String,
 
     /// List of tasks to add to build variant.
     pub tasks: Vec<TaskRef>,
 
     /// Display name of build variant.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub display_name: Option<String>,
 
     /// List of distros tasks run on by default.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub run_on: Option<Vec<String>>,
 
     /// List of display tasks in build variant.
@@ -34,7 +50,11 @@ pub struct BuildVariant {
     pub batchtime: Option<u64>,
 
     /// Map of expansions that should be passed to tasks at runtime.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_string_map",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub expansions: Option<BTreeMap<String, String>>,
 
     /// Should failed tasks be run on skipped versions to find their source.
@@ -42,12 +62,28 @@ pub struct BuildVariant {
     pub stepback: Option<bool>,
 
     /// List of modules that should be included in tasks for this build variant.
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub modules: Option<Vec<String>>,
 
     /// Should created tasks for this build variant be scheduled.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub activate: Option<bool>,
+
+    /// Tags used to select this build variant.
+    #[serde(
+        default,
+        deserialize_with = "lenient::option_strings",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tags: Option<Vec<String>>,
+
+    /// Fields not modelled by shrub, kept so they are written back out unchanged.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
 }
 
 impl Default for BuildVariant {
@@ -63,6 +99,79 @@ impl Default for BuildVariant {
             stepback: None,
             modules: None,
             activate: None,
+            tags: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+/// An entry in the list of build variants of a project.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum VariantEntry {
+    /// A single build variant.
+    Variant(BuildVariant),
+    /// A matrix of build variants.
+    Matrix(MatrixDefinition),
+}
+
+impl VariantEntry {
+    /// Get the build variant of this entry, if it is not a matrix.
+    pub fn build_variant(&self) -> Option<&BuildVariant> {
+        match self {
+            VariantEntry::Variant(build_variant) => Some(build_variant),
+            VariantEntry::Matrix(_) => None,
+        }
+    }
+
+    /// Get a mutable reference to the build variant of this entry, if it is not a matrix.
+    pub fn build_variant_mut(&mut self) -> Option<&mut BuildVariant> {
+        match self {
+            VariantEntry::Variant(build_variant) => Some(build_variant),
+            VariantEntry::Matrix(_) => None,
+        }
+    }
+
+    /// Get the matrix of this entry, if it is one.
+    pub fn matrix(&self) -> Option<&MatrixDefinition> {
+        match self {
+            VariantEntry::Variant(_) => None,
+            VariantEntry::Matrix(matrix) => Some(matrix),
+        }
+    }
+
+    /// Get a mutable reference to the matrix of this entry, if it is one.
+    pub fn matrix_mut(&mut self) -> Option<&mut MatrixDefinition> {
+        match self {
+            VariantEntry::Variant(_) => None,
+            VariantEntry::Matrix(matrix) => Some(matrix),
+        }
+    }
+}
+
+impl From<BuildVariant> for VariantEntry {
+    fn from(build_variant: BuildVariant) -> Self {
+        VariantEntry::Variant(build_variant)
+    }
+}
+
+impl From<MatrixDefinition> for VariantEntry {
+    fn from(matrix: MatrixDefinition) -> Self {
+        VariantEntry::Matrix(matrix)
+    }
+}
+
+impl<'de> Deserialize<'de> for VariantEntry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Decide which kind of entry this is up front so errors keep the path to the problem.
+        let value = serde_yaml::Value::deserialize(deserializer)?;
+        if value.get("matrix_name").is_some() {
+            Ok(VariantEntry::Matrix(from_value(value)?))
+        } else {
+            Ok(VariantEntry::Variant(from_value(value)?))
         }
     }
 }
@@ -0,0 +1,420 @@
+//! Flatten everything a task runs into a single list of commands.
+//!
+//! A task that is not part of a task group runs:
+//!
+//! 1. The project's `pre` commands.
+//! 2. The task's own commands.
+//! 3. The project's `post` commands.
+//! 4. The project's `timeout` commands, but only if the task times out.
+//!
+//! Like in Evergreen, a task that runs as part of a task group runs the task group's blocks
+//! instead of the project's `pre`, `post` and `timeout`: `setup_group`, `setup_task`, the task's
+//! commands, `teardown_task`, `teardown_group` and then `timeout` if it times out. Since
+//! `setup_group` runs before the first task of the group and `teardown_group` after the last,
+//! they are only part of the first and last task respectively.
+//!
+//! Function calls are replaced by the commands of the function with the `vars` of the call
+//! substituted. Any other expansions are left as they are.
+use crate::interpolate::{InterpolationError, Interpolator};
+use crate::models::builtin::BuiltInCommand;
+use crate::models::commands::{EvgCommand, FunctionCall};
+use crate::models::project::EvgProject;
+use crate::models::task::TaskRef;
+use crate::models::task_group::EvgTaskGroup;
+use crate::selector::Selector;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use thiserror::Error;
+
+/// Errors that can occur while rendering a task.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum RenderError {
+    /// The build variant is not defined in the project.
+    #[error("Build variant '{0}' is not defined")]
+    UndefinedVariant(String),
+
+    /// The task does not run on the build variant.
+    #[error("Task '{task}' does not run on build variant '{variant}'")]
+    TaskNotOnVariant {
+        /// Name of the build variant.
+        variant: String,
+        /// Name of the task.
+        task: String,
+    },
+
+    /// A function is called that is not defined.
+    #[error("Function '{0}' is not defined")]
+    UndefinedFunction(String),
+
+    /// A function calls another function, which Evergreen does not allow.
+    #[error("Function '{function}' calls function '{called}'")]
+    NestedFunctionCall {
+        /// Function making the call.
+        function: String,
+        /// Function being called.
+        called: String,
+    },
+
+    /// The vars of a function call could not be substituted into its commands.
+    #[error("Could not apply vars of function '{function}': {source}")]
+    Interpolation {
+        /// Function being called.
+        function: String,
+        /// Why substitution failed.
+        source: InterpolationError,
+    },
+}
+
+/// The part of a task run a command comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Block {
+    /// The project's `pre` commands.
+    Pre,
+    /// The task group's `setup_group` commands.
+    SetupGroup,
+    /// The task group's `setup_task` commands.
+    SetupTask,
+    /// The task's own commands.
+    Task,
+    /// The task group's `teardown_task` commands.
+    TeardownTask,
+    /// The task group's `teardown_group` commands.
+    TeardownGroup,
+    /// The project's `post` commands.
+    Post,
+    /// The `timeout` commands of the project or task group, only run if the task times out.
+    Timeout,
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            Block::Pre => "pre",
+            Block::SetupGroup => "setup_group",
+            Block::SetupTask => "setup_task",
+            Block::Task => "task",
+            Block::TeardownTask => "teardown_task",
+            Block::TeardownGroup => "teardown_group",
+            Block::Post => "post",
+            Block::Timeout => "timeout",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A command a task runs and where it came from.
+#[derive(Debug, Clone)]
+pub struct RenderedCommand {
+    /// The command to run.
+    pub command: BuiltInCommand,
+    /// The block the command is part of.
+    pub block: Block,
+    /// The function the command came from, if it was part of a function.
+    pub function: Option<String>,
+}
+
+/// Get every command the given task runs on the given build variant, in the order they run.
+///
+/// * `project`: Project the task is defined in.
+/// * `variant`: Name of build variant the task runs on.
+/// * `task`: Name of task to render.
+pub fn render_task(
+    project: &EvgProject,
+    variant: &str,
+    task: &str,
+) -> Result<Vec<RenderedCommand>, RenderError> {
+    let build_variant = project
+        .build_variants()
+        .find(|bv| bv.name == variant)
+        .ok_or_else(|| RenderError::UndefinedVariant(variant.to_string()))?;
+    let evg_task = project
+        .tasks_on_variant(build_variant)
+        .into_iter()
+        .find(|t| t.name == task)
+        .ok_or_else(|| RenderError::TaskNotOnVariant {
+            variant: variant.to_string(),
+            task: task.to_string(),
+        })?;
+
+    let task_commands = evg_task.commands.as_deref();
+    let blocks = match task_group_for(project, &build_variant.tasks, task) {
+        Some(task_group) => {
+            let is_first = task_group.tasks.first().is_some_and(|t| t == task);
+            let is_last = task_group.tasks.last().is_some_and(|t| t == task);
+            vec![
+                (
+                    Block::SetupGroup,
+                    task_group.setup_group.as_deref().filter(|_| is_first),
+                ),
+                (Block::SetupTask, task_group.setup_task.as_deref()),
+                (Block::Task, task_commands),
+                (Block::TeardownTask, task_group.teardown_task.as_deref()),
+                (
+                    Block::TeardownGroup,
+                    task_group.teardown_group.as_deref().filter(|_| is_last),
+                ),
+                (Block::Timeout, task_group.timeout.as_deref()),
+            ]
+        }
+        None => vec![
+            (Block::Pre, project.pre.as_deref()),
+            (Block::Task, task_commands),
+            (Block::Post, project.post.as_deref()),
+            (Block::Timeout, project.timeout.as_deref()),
+        ],
+    };
+
+    let mut rendered = vec![];
+    for (block, commands) in blocks {
+        for command in commands.unwrap_or_default() {
+            match command {
+                EvgCommand::BuiltIn(command) => rendered.push(RenderedCommand {
+                    command: command.clone(),
+                    block,
+                    function: None,
+                }),
+                EvgCommand::Function(call) => {
+                    for command in render_function_call(project, call)? {
+                        rendered.push(RenderedCommand {
+                            command,
+                            block,
+                            function: Some(call.func.to_string()),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(rendered)
+}
+
+/// Find the task group the given task runs as part of on a build variant.
+fn task_group_for<'a>(
+    project: &'a EvgProject,
+    task_refs: &[TaskRef],
+    task: &str,
+) -> Option<&'a EvgTaskGroup> {
+    let task_group_map = project.task_group_map();
+    task_refs
+        .iter()
+        .filter_map(|task_ref| {
+            Selector::parse(&task_ref.name)
+                .and_then(|s| s.select_task_units(project))
+                .ok()
+        })
+        .flatten()
+        .filter_map(|name| task_group_map.get(name).copied())
+        .find(|tg| tg.tasks.iter().any(|t| t == task))
+}
+
+/// Get the commands of the called function with the vars of the call substituted.
+fn render_function_call(
+    project: &EvgProject,
+    call: &FunctionCall,
+) -> Result<Vec<BuiltInCommand>, RenderError> {
+    let function = project
+        .functions
+        .get(&call.func)
+        .ok_or_else(|| RenderError::UndefinedFunction(call.func.to_string()))?;
+    let vars: BTreeMap<String, String> = call
+        .vars
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let interpolator = Interpolator::partial(&vars);
+
+    function
+        .commands()
+        .iter()
+        .map(|command| match command {
+            EvgCommand::BuiltIn(command) => interpolator
+                .interpolate(command)
+                .map(|interpolated| interpolated.value)
+                .map_err(|source| RenderError::Interpolation {
+                    function: call.func.to_string(),
+                    source,
+                }),
+            EvgCommand::Function(nested) => Err(RenderError::NestedFunctionCall {
+                function: call.func.to_string(),
+                called: nested.func.to_string(),
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECT: &str = r#"
+pre:
+  - command: shell.exec
+    params:
+      script: echo pre
+post:
+  - func: report
+    vars:
+      target: post
+timeout:
+  - command: shell.exec
+    params:
+      script: echo timeout
+functions:
+  report:
+    command: shell.exec
+    params:
+      script: echo ${target} ${workdir}
+  run_tests:
+    - command: shell.exec
+      params:
+        script: run ${suite|all}
+    - command: attach.results
+      params:
+        file_location: ${suite}.json
+  nested:
+    func: report
+tasks:
+  - name: compile
+    commands:
+      - command: shell.exec
+        params:
+          script: make
+  - name: test
+    commands:
+      - func: run_tests
+        vars:
+          suite: core
+  - name: lint
+    commands:
+      - command: shell.exec
+        params:
+          script: make lint
+  - name: package
+    commands:
+      - command: shell.exec
+        params:
+          script: make package
+  - name: broken
+    commands:
+      - func: nested
+task_groups:
+  - name: test_group
+    tasks: ["test", "lint", "package"]
+    setup_group:
+      - func: report
+        vars:
+          target: setup_group
+    setup_task:
+      - command: shell.exec
+        params:
+          script: echo setup_task
+    teardown_task:
+      - command: shell.exec
+        params:
+          script: echo teardown_task
+    teardown_group:
+      - command: shell.exec
+        params:
+          script: echo teardown_group
+buildvariants:
+  - name: linux
+    tasks:
+      - name: compile
+      - name: test_group
+      - name: broken
+"#;
+
+    fn summarize(rendered: &[RenderedCommand]) -> Vec<(String, Option<String>, String)> {
+        rendered
+            .iter()
+            .map(|r| {
+                let params = serde_yaml::to_value(&r.command).unwrap()["params"].clone();
+                let summary = serde_yaml::to_string(&params).unwrap();
+                (r.block.to_string(), r.function.clone(), summary)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_render_task() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let rendered = render_task(&project, "linux", "compile").unwrap();
+
+        let blocks: Vec<(Block, Option<&str>)> = rendered
+            .iter()
+            .map(|r| (r.block, r.function.as_deref()))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![
+                (Block::Pre, None),
+                (Block::Task, None),
+                (Block::Post, Some("report")),
+                (Block::Timeout, None),
+            ]
+        );
+        assert!(summarize(&rendered)[2].2.contains("echo post ${workdir}"));
+    }
+
+    #[test]
+    fn test_render_task_in_task_group() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let rendered = render_task(&project, "linux", "test").unwrap();
+
+        let summary = summarize(&rendered);
+        let blocks: Vec<&str> = summary.iter().map(|(b, _, _)| b.as_str()).collect();
+        assert_eq!(
+            blocks,
+            vec!["setup_group", "setup_task", "task", "task", "teardown_task",]
+        );
+        assert!(summary[0].2.contains("echo setup_group ${workdir}"));
+        assert_eq!(summary[2].1.as_deref(), Some("run_tests"));
+        assert!(summary[2].2.contains("run core"));
+        assert!(summary[3].2.contains("file_location: core.json"));
+    }
+
+    #[test]
+    fn test_group_blocks_only_run_with_first_and_last_task_of_group() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let blocks = |task: &str| -> Vec<Block> {
+            render_task(&project, "linux", task)
+                .unwrap()
+                .iter()
+                .map(|r| r.block)
+                .collect()
+        };
+
+        assert_eq!(
+            blocks("lint"),
+            vec![Block::SetupTask, Block::Task, Block::TeardownTask]
+        );
+        assert_eq!(
+            blocks("package"),
+            vec![
+                Block::SetupTask,
+                Block::Task,
+                Block::TeardownTask,
+                Block::TeardownGroup
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_task_with_nested_function_call_fails() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let result = render_task(&project, "linux", "broken");
+
+        assert_eq!(
+            result.unwrap_err(),
+            RenderError::NestedFunctionCall {
+                function: "nested".to_string(),
+                called: "report".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Read and write the test results files that tasks attach to Evergreen.
+//!
+//! Evergreen reads test results in a few formats, depending on the command used to attach them:
+//!
+//! * [`evergreen`]: Evergreen's own JSON format, attached with `attach.results`.
+//! * [`xunit`]: XUnit/JUnit XML, attached with `attach.xunit_results`.
+//! * [`gotest`]: Verbose `go test` output, attached with `gotest.parse_files`.
+//!
+//! Results in each format are converted to [`TestResult`], so they can be handled the same way
+//! regardless of where they came from. Results can be written back out in Evergreen's JSON format
+//! with [`write_file`].
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+pub mod evergreen;
+pub mod gotest;
+pub mod xunit;
+
+/// Errors that can occur while reading or writing test results.
+#[derive(Debug, Error)]
+pub enum ResultsError {
+    /// A results file could not be read or written.
+    #[error("Could not access '{}': {source}", .path.display())]
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+        /// Error accessing the file.
+        source: io::Error,
+    },
+
+    /// Results in Evergreen's JSON format could not be parsed or serialized.
+    #[error("Invalid Evergreen test results: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// XUnit results could not be parsed.
+    #[error("Invalid XUnit test results: {0}")]
+    Xml(#[from] roxmltree::Error),
+}
+
+/// How a test turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    /// The test passed.
+    Pass,
+    /// The test failed.
+    Fail,
+    /// The test was skipped.
+    Skip,
+    /// The test failed, but should not fail the task.
+    SilentFail,
+}
+
+impl TestStatus {
+    /// Check if the status counts as a failure.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, TestStatus::Fail | TestStatus::SilentFail)
+    }
+}
+
+impl Display for TestStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match self {
+            TestStatus::Pass => "pass",
+            TestStatus::Fail => "fail",
+            TestStatus::Skip => "skip",
+            TestStatus::SilentFail => "silentfail",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where to find the logs of a test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TestLog {
+    /// URL of the logs in a web page.
+    pub url: Option<String>,
+    /// URL of the raw logs.
+    pub url_raw: Option<String>,
+    /// Local file containing the logs.
+    pub file: Option<PathBuf>,
+    /// Line in the logs the test starts at.
+    pub line_num: Option<u32>,
+}
+
+/// The result of a single test, from any of the supported formats.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestResult {
+    /// Name of the test.
+    pub name: String,
+    /// Name to show for the test instead of `name`.
+    pub display_name: Option<String>,
+    /// ID of the group of tests the test is part of.
+    pub group_id: Option<String>,
+    /// How the test turned out.
+    pub status: TestStatus,
+    /// When the test started, if known.
+    pub start: Option<SystemTime>,
+    /// How long the test took.
+    pub duration: Duration,
+    /// Exit code of the test process, if it ran as its own process.
+    pub exit_code: Option<i32>,
+    /// Where to find the logs of the test.
+    pub log: TestLog,
+    /// Output of the test captured in the results, like failure messages.
+    pub output: Option<String>,
+}
+
+impl TestResult {
+    /// Create a result for the named test with the given status and no other details.
+    pub fn new(name: &str, status: TestStatus) -> Self {
+        TestResult {
+            name: name.to_string(),
+            display_name: None,
+            group_id: None,
+            status,
+            start: None,
+            duration: Duration::default(),
+            exit_code: None,
+            log: TestLog::default(),
+            output: None,
+        }
+    }
+}
+
+/// A format of test results file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultsFormat {
+    /// Evergreen's JSON format.
+    Evergreen,
+    /// XUnit/JUnit XML.
+    XUnit,
+    /// Verbose `go test` output.
+    GoTest,
+}
+
+/// Read the test results in the given file.
+///
+/// Results without a log of their own are pointed at the file they were read from.
+///
+/// * `path`: Path of the file to read.
+/// * `format`: Format of the file.
+pub fn read_file(path: &Path, format: ResultsFormat) -> Result<Vec<TestResult>, ResultsError> {
+    let contents = fs::read_to_string(path).map_err(|source| ResultsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let mut results = match format {
+        ResultsFormat::Evergreen => evergreen::EvgTestResults::from_json_str(&contents)?.into(),
+        ResultsFormat::XUnit => xunit::parse(&contents)?,
+        ResultsFormat::GoTest => gotest::parse(&contents),
+    };
+    for result in &mut results {
+        if result.log.url.is_none() && result.log.url_raw.is_none() && result.log.file.is_none() {
+            result.log.file = Some(path.to_path_buf());
+        }
+    }
+    Ok(results)
+}
+
+/// Write test results to a file in Evergreen's JSON format.
+///
+/// * `path`: Path of the file to write.
+/// * `results`: Results to write.
+pub fn write_file(path: &Path, results: &[TestResult]) -> Result<(), ResultsError> {
+    let json = evergreen::EvgTestResults::from(results).to_json_string()?;
+    fs::write(path, json).map_err(|source| ResultsError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_results_round_trip_through_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let gotest_file = dir.path().join("test.log");
+        fs::write(
+            &gotest_file,
+            "=== RUN   TestA\n--- PASS: TestA (1.50s)\n=== RUN   TestB\n--- FAIL: TestB (0.25s)\n",
+        )
+        .unwrap();
+        let json_file = dir.path().join("results.json");
+
+        let results = read_file(&gotest_file, ResultsFormat::GoTest).unwrap();
+        write_file(&json_file, &results).unwrap();
+        let read_back = read_file(&json_file, ResultsFormat::Evergreen).unwrap();
+
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(read_back[0].name, "TestA");
+        assert_eq!(read_back[0].duration, Duration::from_millis(1500));
+        assert_eq!(read_back[1].status, TestStatus::Fail);
+        assert_eq!(read_back[1].log.file.as_deref(), Some(json_file.as_path()));
+        assert_eq!(read_back[1].log.line_num, Some(3));
+        assert_eq!(read_back[1].output, results[1].output);
+    }
+}
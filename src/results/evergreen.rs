@@ -0,0 +1,231 @@
+//! Evergreen's JSON test results format, as read by `attach.results`.
+//!
+//! ```json
+//! {
+//!   "results": [
+//!     {
+//!       "status": "pass",
+//!       "test_file": "test_1",
+//!       "exit_code": 0,
+//!       "elapsed": 0.322,
+//!       "start": 1398782500.359,
+//!       "end": 1398782500.681
+//!     }
+//!   ]
+//! }
+//! ```
+use super::{TestLog, TestResult, TestStatus};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Status of a test in Evergreen's JSON format.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EvgTestStatus {
+    /// The test passed.
+    Pass,
+    /// The test failed.
+    Fail,
+    /// The test was skipped.
+    Skip,
+    /// The test failed, but should not fail the task.
+    SilentFail,
+}
+
+/// A single test in Evergreen's JSON format.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EvgTestResult {
+    /// How the test turned out.
+    pub status: EvgTestStatus,
+    /// Name of the test.
+    pub test_file: String,
+    /// Name to show for the test instead of `test_file`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_test_name: Option<String>,
+    /// ID of the group of tests the test is part of.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+    /// URL of the logs of the test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// URL of the raw logs of the test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url_raw: Option<String>,
+    /// Contents of the logs of the test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_raw: Option<String>,
+    /// Line in the logs the test starts at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_num: Option<u32>,
+    /// Exit code of the test process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// When the test started, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<f64>,
+    /// When the test ended, in seconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<f64>,
+    /// How long the test took in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elapsed: Option<f64>,
+}
+
+/// A file of test results in Evergreen's JSON format.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct EvgTestResults {
+    /// Results of each test.
+    pub results: Vec<EvgTestResult>,
+}
+
+impl EvgTestResults {
+    /// Parse test results from JSON.
+    pub fn from_json_str(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Serialize the test results to JSON.
+    pub fn to_json_string(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl From<EvgTestStatus> for TestStatus {
+    fn from(status: EvgTestStatus) -> Self {
+        match status {
+            EvgTestStatus::Pass => TestStatus::Pass,
+            EvgTestStatus::Fail => TestStatus::Fail,
+            EvgTestStatus::Skip => TestStatus::Skip,
+            EvgTestStatus::SilentFail => TestStatus::SilentFail,
+        }
+    }
+}
+
+impl From<TestStatus> for EvgTestStatus {
+    fn from(status: TestStatus) -> Self {
+        match status {
+            TestStatus::Pass => EvgTestStatus::Pass,
+            TestStatus::Fail => EvgTestStatus::Fail,
+            TestStatus::Skip => EvgTestStatus::Skip,
+            TestStatus::SilentFail => EvgTestStatus::SilentFail,
+        }
+    }
+}
+
+impl From<&EvgTestResult> for TestResult {
+    /// The duration is taken from `elapsed`, or from `start` and `end` if it is not set.
+    fn from(result: &EvgTestResult) -> Self {
+        let elapsed = match (result.elapsed, result.start, result.end) {
+            (Some(elapsed), _, _) => elapsed,
+            (None, Some(start), Some(end)) => end - start,
+            _ => 0.0,
+        };
+        TestResult {
+            name: result.test_file.to_string(),
+            display_name: result.display_test_name.clone(),
+            group_id: result.group_id.clone(),
+            status: result.status.into(),
+            start: result
+                .start
+                .and_then(|start| Duration::try_from_secs_f64(start).ok())
+                .map(|since_epoch| UNIX_EPOCH + since_epoch),
+            duration: Duration::try_from_secs_f64(elapsed).unwrap_or_default(),
+            exit_code: result.exit_code,
+            log: TestLog {
+                url: result.url.clone(),
+                url_raw: result.url_raw.clone(),
+                file: None,
+                line_num: result.line_num,
+            },
+            output: result.log_raw.clone(),
+        }
+    }
+}
+
+impl From<&TestResult> for EvgTestResult {
+    /// The local log file of the result is not kept, since the format has no place for it.
+    fn from(result: &TestResult) -> Self {
+        let start = result
+            .start
+            .and_then(|start| start.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs_f64());
+        let elapsed = result.duration.as_secs_f64();
+        EvgTestResult {
+            status: result.status.into(),
+            test_file: result.name.to_string(),
+            display_test_name: result.display_name.clone(),
+            group_id: result.group_id.clone(),
+            url: result.log.url.clone(),
+            url_raw: result.log.url_raw.clone(),
+            log_raw: result.output.clone(),
+            line_num: result.log.line_num,
+            exit_code: result.exit_code,
+            start,
+            end: start.map(|start| start + elapsed),
+            elapsed: Some(elapsed),
+        }
+    }
+}
+
+impl From<EvgTestResults> for Vec<TestResult> {
+    fn from(results: EvgTestResults) -> Self {
+        results.results.iter().map(TestResult::from).collect()
+    }
+}
+
+impl From<&[TestResult]> for EvgTestResults {
+    fn from(results: &[TestResult]) -> Self {
+        EvgTestResults {
+            results: results.iter().map(EvgTestResult::from).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_evergreen_results() {
+        let json = r#"{
+            "results": [
+                {"status": "pass", "test_file": "test_1", "exit_code": 0,
+                 "start": 1398782500.359, "end": 1398782500.681},
+                {"status": "silentfail", "test_file": "test_2", "url": "https://logs/2",
+                 "line_num": 12, "elapsed": 2.5, "group_id": "group"}
+            ]
+        }"#;
+
+        let results: Vec<TestResult> = EvgTestResults::from_json_str(json).unwrap().into();
+
+        assert_eq!(results[0].status, TestStatus::Pass);
+        assert_eq!(results[0].duration.as_millis(), 322);
+        assert_eq!(
+            results[0].start,
+            Some(UNIX_EPOCH + Duration::from_secs_f64(1398782500.359))
+        );
+        assert_eq!(results[1].status, TestStatus::SilentFail);
+        assert_eq!(results[1].duration, Duration::from_millis(2500));
+        assert_eq!(results[1].log.url.as_deref(), Some("https://logs/2"));
+        assert_eq!(results[1].log.line_num, Some(12));
+        assert_eq!(results[1].group_id.as_deref(), Some("group"));
+    }
+
+    #[test]
+    fn test_write_evergreen_results() {
+        let mut result = TestResult::new("test_1", TestStatus::Skip);
+        result.duration = Duration::from_secs(3);
+
+        let json = EvgTestResults::from(&[result][..])
+            .to_json_string()
+            .unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "results": [{"status": "skip", "test_file": "test_1", "elapsed": 3.0}]
+            })
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Verbose `go test` output, as read by `gotest.parse_files`.
+//!
+//! ```text
+//! === RUN   TestOne
+//! --- PASS: TestOne (0.01s)
+//! === RUN   TestTwo
+//!     two_test.go:12: unexpected value
+//! --- FAIL: TestTwo (1.20s)
+//! ```
+use super::{TestResult, TestStatus};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Parse the results of the tests in verbose `go test` output.
+///
+/// Each test's output is the lines from its `=== RUN` line to its result line, and its log starts
+/// at the `=== RUN` line. Lines after `=== CONT` or `=== NAME` belong to the named test, while
+/// `=== PAUSE` leaves them with the test that was running. Subtests are reported as tests of their
+/// own. Tests that were started but have no result, for example because the test binary panicked,
+/// are reported as failed.
+///
+/// * `output`: Output of `go test -v`.
+pub fn parse(output: &str) -> Vec<TestResult> {
+    let mut results: Vec<(TestResult, Vec<&str>, bool)> = vec![];
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    let mut current = None;
+
+    for (line_num, line) in output.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("=== RUN") {
+            let name = name.trim();
+            let mut result = TestResult::new(name, TestStatus::Fail);
+            result.log.line_num = Some(line_num as u32 + 1);
+            index_of.insert(name.to_string(), results.len());
+            current = Some(results.len());
+            results.push((result, vec![line], false));
+        } else if let Some(name) = trimmed
+            .strip_prefix("=== CONT")
+            .or_else(|| trimmed.strip_prefix("=== NAME"))
+        {
+            // Go 1.20 and later print `=== NAME` when output switches back to a test.
+            current = index_of.get(name.trim()).copied();
+        } else if let Some((status, name, duration)) = parse_result_line(trimmed) {
+            let index = match index_of.get(name) {
+                Some(index) => *index,
+                None => {
+                    // Results can be reported for tests that were never announced as running.
+                    index_of.insert(name.to_string(), results.len());
+                    let mut result = TestResult::new(name, status);
+                    result.log.line_num = Some(line_num as u32 + 1);
+                    results.push((result, vec![], false));
+                    results.len() - 1
+                }
+            };
+            let (result, lines, finished) = &mut results[index];
+            result.status = status;
+            result.duration = duration;
+            lines.push(line);
+            *finished = true;
+        } else if let Some(index) = current {
+            let (_, lines, finished) = &mut results[index];
+            if !*finished {
+                lines.push(line);
+            }
+        }
+    }
+
+    results
+        .into_iter()
+        .map(|(mut result, lines, _)| {
+            result.output = Some(lines.join("\n"));
+            result
+        })
+        .collect()
+}
+
+/// Parse a line like `--- PASS: TestName (0.01s)`.
+fn parse_result_line(line: &str) -> Option<(TestStatus, &str, Duration)> {
+    let rest = line.strip_prefix("--- ")?;
+    let (status, rest) = rest.split_once(": ")?;
+    let status = match status {
+        "PASS" => TestStatus::Pass,
+        "FAIL" => TestStatus::Fail,
+        "SKIP" => TestStatus::Skip,
+        _ => return None,
+    };
+    let (name, duration) = match rest.rsplit_once(" (") {
+        Some((name, duration)) => {
+            let secs = duration
+                .strip_suffix("s)")
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+            (name, secs.unwrap_or_default())
+        }
+        None => (rest, Duration::default()),
+    };
+    Some((status, name.trim(), duration))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_gotest_output() {
+        let output = "\
+=== RUN   TestPass
+--- PASS: TestPass (0.01s)
+=== RUN   TestFail
+    fail_test.go:12: unexpected value
+--- FAIL: TestFail (1.20s)
+=== RUN   TestParent
+=== RUN   TestParent/child
+=== PAUSE TestParent/child
+=== CONT  TestParent/child
+    --- SKIP: TestParent/child (0.00s)
+--- PASS: TestParent (0.50s)
+=== RUN   TestPanic
+panic: oh no
+FAIL
+FAIL	example.com/pkg	1.712s
+";
+
+        let results = parse(output);
+
+        let summary: Vec<(&str, TestStatus, u128, Option<u32>)> = results
+            .iter()
+            .map(|r| {
+                (
+                    r.name.as_str(),
+                    r.status,
+                    r.duration.as_millis(),
+                    r.log.line_num,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("TestPass", TestStatus::Pass, 10, Some(1)),
+                ("TestFail", TestStatus::Fail, 1200, Some(3)),
+                ("TestParent", TestStatus::Pass, 500, Some(6)),
+                ("TestParent/child", TestStatus::Skip, 0, Some(7)),
+                ("TestPanic", TestStatus::Fail, 0, Some(12)),
+            ]
+        );
+        assert_eq!(
+            results[1].output.as_deref(),
+            Some("=== RUN   TestFail\n    fail_test.go:12: unexpected value\n--- FAIL: TestFail (1.20s)")
+        );
+        assert!(results[4]
+            .output
+            .as_deref()
+            .unwrap()
+            .contains("panic: oh no"));
+    }
+
+    #[test]
+    fn test_output_after_name_lines_belongs_to_the_named_test() {
+        let output = "\
+=== RUN   TestParent
+=== RUN   TestParent/child
+    child_test.go:5: in child
+=== NAME  TestParent
+    parent_test.go:20: in parent
+    --- PASS: TestParent/child (0.00s)
+--- FAIL: TestParent (0.10s)
+";
+
+        let results = parse(output);
+
+        let parent = results[0].output.as_deref().unwrap();
+        let child = results[1].output.as_deref().unwrap();
+        assert!(parent.contains("in parent"));
+        assert!(!child.contains("in parent"));
+        assert!(child.contains("in child"));
+    }
+
+    #[test]
+    fn test_parallel_subtests() {
+        let output = "\
+=== RUN   TestParallel
+=== RUN   TestParallel/a
+=== PAUSE TestParallel/a
+=== RUN   TestParallel/b
+=== PAUSE TestParallel/b
+=== NAME  TestParallel
+    parallel_test.go:20: waiting for subtests
+=== CONT  TestParallel/a
+=== RUN   TestParallel/a/nested
+=== CONT  TestParallel/b
+    parallel_test.go:30: in b
+=== PAUSE TestParallel/a/nested
+printed by b
+=== CONT  TestParallel/a/nested
+    parallel_test.go:40: in nested
+--- FAIL: TestParallel (0.02s)
+    --- PASS: TestParallel/a (0.01s)
+        --- PASS: TestParallel/a/nested (0.00s)
+    --- FAIL: TestParallel/b (0.01s)
+";
+
+        let results = parse(output);
+
+        let summary: Vec<(&str, TestStatus)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.status))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("TestParallel", TestStatus::Fail),
+                ("TestParallel/a", TestStatus::Pass),
+                ("TestParallel/b", TestStatus::Fail),
+                ("TestParallel/a/nested", TestStatus::Pass),
+            ]
+        );
+        let output_of = |index: usize| results[index].output.as_deref().unwrap();
+        assert!(output_of(0).contains("waiting for subtests"));
+        assert!(output_of(2).contains("in b"));
+        assert!(output_of(2).contains("printed by b"));
+        assert!(!output_of(3).contains("printed by b"));
+        assert!(output_of(3).contains("in nested"));
+    }
+}
@@ -0,0 +1,108 @@
+//! XUnit/JUnit XML test results, as read by `attach.xunit_results`.
+//!
+//! Every `testcase` element is read, wherever it is nested in `testsuites` and `testsuite`
+//! elements. Like in Evergreen, tests are named `<classname>.<name>` if they have a class name.
+use super::{TestResult, TestStatus};
+use std::time::Duration;
+
+/// Parse the test cases in XUnit XML.
+///
+/// A test case fails if it has a `failure` or `error` element and is skipped if it has a
+/// `skipped` element. The messages and contents of those elements, along with any `system-out`
+/// and `system-err`, are kept as the output of the test.
+///
+/// * `xml`: XUnit XML to parse.
+pub fn parse(xml: &str) -> Result<Vec<TestResult>, roxmltree::Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let results = document
+        .descendants()
+        .filter(|node| node.has_tag_name("testcase"))
+        .map(|testcase| {
+            let name = testcase.attribute("name").unwrap_or_default();
+            let name = match testcase.attribute("classname") {
+                Some(class_name) if !class_name.is_empty() => format!("{}.{}", class_name, name),
+                _ => name.to_string(),
+            };
+
+            let mut status = TestStatus::Pass;
+            let mut output = vec![];
+            for child in testcase.children().filter(|child| child.is_element()) {
+                match child.tag_name().name() {
+                    "failure" | "error" => status = TestStatus::Fail,
+                    "skipped" if status == TestStatus::Pass => status = TestStatus::Skip,
+                    "system-out" | "system-err" | "skipped" => (),
+                    _ => continue,
+                }
+                output.extend(child.attribute("message").map(str::to_string));
+                output.extend(
+                    child
+                        .text()
+                        .map(str::trim)
+                        .filter(|text| !text.is_empty())
+                        .map(str::to_string),
+                );
+            }
+
+            let mut result = TestResult::new(&name, status);
+            result.duration = testcase
+                .attribute("time")
+                .and_then(|time| time.trim().replace(',', "").parse::<f64>().ok())
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .unwrap_or_default();
+            if !output.is_empty() {
+                result.output = Some(output.join("\n"));
+            }
+            result
+        })
+        .collect();
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_xunit() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="suite" tests="4">
+    <testcase classname="pkg.Tests" name="test_pass" time="1.5"/>
+    <testcase classname="pkg.Tests" name="test_fail" time="0.25">
+      <failure message="assertion failed">expected 1, got 2</failure>
+      <system-out>some output</system-out>
+    </testcase>
+    <testsuite name="nested">
+      <testcase name="test_skip" time="1,000.0"><skipped/></testcase>
+      <testcase name="test_error"><error message="boom"/></testcase>
+    </testsuite>
+  </testsuite>
+</testsuites>"#;
+
+        let results = parse(xml).unwrap();
+
+        let summary: Vec<(&str, TestStatus, u128)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), r.status, r.duration.as_millis()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("pkg.Tests.test_pass", TestStatus::Pass, 1500),
+                ("pkg.Tests.test_fail", TestStatus::Fail, 250),
+                ("test_skip", TestStatus::Skip, 1_000_000),
+                ("test_error", TestStatus::Fail, 0),
+            ]
+        );
+        assert_eq!(
+            results[1].output.as_deref(),
+            Some("assertion failed\nexpected 1, got 2\nsome output")
+        );
+        assert_eq!(results[0].output, None);
+    }
+
+    #[test]
+    fn test_parse_invalid_xunit() {
+        assert!(parse("<testsuite><testcase></testsuite>").is_err());
+    }
+}
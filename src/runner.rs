@@ -0,0 +1,1360 @@
+//! Run the commands of a task on the local machine.
+//!
+//! This is meant for reproducing what a task does without needing an Evergreen host. Tasks are
+//! rendered with [`render_task`](crate::render::render_task), expansions are substituted into
+//! each command right before it runs and the output of commands is streamed to a [`LogSink`].
+//!
+//! Only commands that can be run locally are supported, any other commands are reported as
+//! [`CommandStatus::Unsupported`] and skipped. The blocks of a task run like they do in
+//! Evergreen:
+//!
+//! * A failing command stops the rest of the commands in its block from running.
+//! * A failure in the task's own commands fails the task, as does a failure in `pre` if the
+//!   project sets `pre_error_fails_task`. The task's commands are skipped if `pre` fails the task.
+//! * `post` and teardown blocks always run, but don't affect whether the task fails.
+//! * If the task's own commands time out, the task fails and the `timeout` commands run right
+//!   after them. Otherwise the `timeout` commands are not run.
+//!
+//! A task times out when its own commands run longer than the exec timeout, or when any command
+//! runs longer than the idle timeout without producing output. Both can be changed while the task
+//! runs with `timeout.update`. Processes that time out are killed.
+//!
+//! `expansions.update` changes the expansions seen by the commands that run after it, and
+//! `expansions.write` writes the current expansions to a file. The `archive.*` commands are run
+//! with [`crate::archive`]. The S3 commands (`s3.put`, `s3.get` and `s3Copy.copy`) are run
+//! against an [`ArtifactStore`](crate::artifacts::ArtifactStore) if the runner is given one.
+use crate::artifacts::ArtifactStore;
+use crate::expansions::{EffectiveExpansions, ExpansionsError, RunContext};
+use crate::interpolate::Interpolator;
+use crate::models::builtin::{EvgCommandSpec, ShellExecParams, SubprocessExecParams};
+use crate::models::project::EvgProject;
+use crate::render::{render_task, Block, RenderError, RenderedCommand};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+mod archive;
+mod expansions;
+mod s3;
+mod timeout;
+
+const DEFAULT_SHELL: &str = "sh";
+/// Evergreen's default for how long the task's own commands can run.
+const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+/// Evergreen's default for how long a command can run without producing output.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+/// How often to check if a running process has finished or timed out.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Errors that can stop a task from running.
+#[derive(Debug, Error)]
+pub enum RunError {
+    /// The work directory could not be created.
+    #[error("Could not create work directory '{}': {source}", .path.display())]
+    Workdir {
+        /// Path of the work directory.
+        path: PathBuf,
+        /// Error creating the directory.
+        source: io::Error,
+    },
+
+    /// The expansions of the task could not be calculated.
+    #[error(transparent)]
+    Expansions(#[from] ExpansionsError),
+
+    /// The task could not be rendered.
+    #[error(transparent)]
+    Render(#[from] RenderError),
+
+    /// The exec timeout of the task is not a number of seconds.
+    #[error("Invalid exec_timeout_secs of task '{task}': {message}")]
+    ExecTimeout {
+        /// Name of the task.
+        task: String,
+        /// Why the timeout is invalid.
+        message: String,
+    },
+}
+
+/// Which stream a line of output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    /// Standard output of a command.
+    Stdout,
+    /// Standard error of a command.
+    Stderr,
+    /// Messages from the runner about what it is doing.
+    System,
+}
+
+/// A line of output from running a task.
+#[derive(Debug, Clone)]
+pub struct LogLine<'a> {
+    /// Index of the command that produced the line.
+    pub command: usize,
+    /// Stream the line came from.
+    pub stream: LogStream,
+    /// Contents of the line, without the line ending.
+    pub text: &'a str,
+}
+
+/// Somewhere to send output from running a task.
+///
+/// Lines can come from several threads at once, since output of commands is read while they
+/// run.
+pub trait LogSink: Send + Sync {
+    /// Handle a line of output.
+    fn log(&self, line: &LogLine);
+}
+
+/// Print output from running a task to standard output.
+#[derive(Debug, Clone, Default)]
+pub struct StdoutLogSink;
+
+impl LogSink for StdoutLogSink {
+    fn log(&self, line: &LogLine) {
+        let stream = match line.stream {
+            LogStream::Stdout => "out",
+            LogStream::Stderr => "err",
+            LogStream::System => "sys",
+        };
+        println!("[{}:{}] {}", line.command, stream, line.text);
+    }
+}
+
+/// How running a command turned out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandStatus {
+    /// The command ran successfully.
+    Succeeded,
+    /// The command failed.
+    Failed {
+        /// Exit code of the process, if it exited normally.
+        exit_code: Option<i32>,
+        /// Description of the failure.
+        message: String,
+    },
+    /// The command failed, but is set to `continue_on_err` so it counts as a success.
+    FailedIgnored {
+        /// Exit code of the process, if it exited normally.
+        exit_code: Option<i32>,
+    },
+    /// The command was started in the background and is not waited on.
+    Background,
+    /// The command was not run because of an earlier failure or because it is in a block that
+    /// is not run.
+    Skipped,
+    /// The command can't be run locally.
+    Unsupported,
+}
+
+impl CommandStatus {
+    /// Check if the status counts as a failure.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, CommandStatus::Failed { .. })
+    }
+}
+
+impl Display for CommandStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CommandStatus::Succeeded => write!(f, "succeeded"),
+            CommandStatus::Failed { message, .. } => write!(f, "failed: {}", message),
+            CommandStatus::FailedIgnored { exit_code } => {
+                write!(f, "failed with exit code {:?}, continuing", exit_code)
+            }
+            CommandStatus::Background => write!(f, "running in background"),
+            CommandStatus::Skipped => write!(f, "skipped"),
+            CommandStatus::Unsupported => write!(f, "not supported locally"),
+        }
+    }
+}
+
+/// The result of running a single command.
+#[derive(Debug, Clone)]
+pub struct CommandResult {
+    /// Name of the command, e.g. `shell.exec`.
+    pub name: String,
+    /// The block the command is part of.
+    pub block: Block,
+    /// The function the command came from, if it was part of a function.
+    pub function: Option<String>,
+    /// How running the command turned out.
+    pub status: CommandStatus,
+    /// How long the command took to run.
+    pub duration: Duration,
+}
+
+/// The result of running a task.
+#[derive(Debug, Clone, Default)]
+pub struct TaskReport {
+    /// Results of each command, in the order they were rendered.
+    pub commands: Vec<CommandResult>,
+    /// Whether the task failed.
+    pub failed: bool,
+    /// Whether the task failed because it timed out.
+    pub timed_out: bool,
+}
+
+/// Settings for running the commands of a task.
+#[derive(Debug, Clone)]
+pub struct TaskOptions {
+    /// Whether a failure in `pre` should fail the task.
+    pub pre_error_fails_task: bool,
+    /// How long the task's own commands can run before the task times out.
+    pub exec_timeout: Duration,
+    /// How long a command can run without producing output before it times out.
+    pub idle_timeout: Duration,
+}
+
+impl Default for TaskOptions {
+    fn default() -> Self {
+        TaskOptions {
+            pre_error_fails_task: false,
+            exec_timeout: DEFAULT_EXEC_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// Options for starting a process.
+#[derive(Debug, Default)]
+struct ProcessOptions<'a> {
+    working_dir: Option<&'a str>,
+    env: HashMap<String, String>,
+    add_to_path: &'a [String],
+    background: bool,
+    continue_on_err: bool,
+    silent: bool,
+    ignore_standard_out: bool,
+    ignore_standard_error: bool,
+    redirect_standard_error_to_output: bool,
+}
+
+/// State kept while running the commands of a task.
+struct TaskState {
+    expansions: BTreeMap<String, String>,
+    background: Vec<Child>,
+    timeouts: timeout::Timeouts,
+    block: Block,
+    failed_block: Option<Block>,
+    timed_out: bool,
+}
+
+/// Run tasks on the local machine.
+pub struct LocalRunner {
+    workdir: PathBuf,
+    context: RunContext,
+    sink: Arc<dyn LogSink>,
+    artifacts: Option<Arc<dyn ArtifactStore>>,
+    redacted: BTreeSet<String>,
+}
+
+impl LocalRunner {
+    /// Create a runner that runs tasks in the given work directory, creating it if needed.
+    ///
+    /// * `workdir`: Directory to run tasks in.
+    /// * `sink`: Where to send the output of tasks.
+    pub fn new(workdir: &Path, sink: Arc<dyn LogSink>) -> Result<Self, RunError> {
+        std::fs::create_dir_all(workdir).map_err(|source| RunError::Workdir {
+            path: workdir.to_path_buf(),
+            source,
+        })?;
+        Ok(LocalRunner {
+            workdir: workdir.to_path_buf(),
+            context: RunContext::default(),
+            sink,
+            artifacts: None,
+            redacted: BTreeSet::new(),
+        })
+    }
+
+    /// Use the given details for the built-in expansions of tasks that are run.
+    ///
+    /// The `workdir` expansion is always set to the work directory of the runner.
+    pub fn with_context(mut self, context: RunContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Use the given store for the artifacts of the S3 commands.
+    ///
+    /// Without a store, S3 commands are reported as unsupported.
+    pub fn with_artifact_store(mut self, artifacts: Arc<dyn ArtifactStore>) -> Self {
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    /// Treat the given expansions as secret, like private project variables in Evergreen.
+    ///
+    /// Secret expansions are left out of the files written by `expansions.write` unless the
+    /// command sets `redacted: true`.
+    pub fn with_redacted_expansions<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.redacted.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Run the given task on the given build variant.
+    ///
+    /// * `project`: Project the task is defined in.
+    /// * `variant`: Name of build variant to run the task on.
+    /// * `task`: Name of task to run.
+    pub fn run_task(
+        &self,
+        project: &EvgProject,
+        variant: &str,
+        task: &str,
+    ) -> Result<TaskReport, RunError> {
+        let context = RunContext {
+            workdir: Some(self.workdir.to_string_lossy().to_string()),
+            ..self.context.clone()
+        };
+        let expansions = EffectiveExpansions::for_task(project, variant, task, &context)?;
+        let commands = render_task(project, variant, task)?;
+        let exec_timeout = match project
+            .tasks
+            .iter()
+            .find(|t| t.name == task)
+            .and_then(|t| t.exec_timeout_secs.as_ref())
+        {
+            Some(value) => {
+                timeout::resolve_expanded(value, &expansions.values()).map_err(|message| {
+                    RunError::ExecTimeout {
+                        task: task.to_string(),
+                        message,
+                    }
+                })?
+            }
+            None => DEFAULT_EXEC_TIMEOUT,
+        };
+        let options = TaskOptions {
+            pre_error_fails_task: project.pre_error_fails_task.unwrap_or(false),
+            exec_timeout,
+            ..Default::default()
+        };
+        Ok(self.run_commands(&commands, expansions.values(), &options))
+    }
+
+    /// Run the given rendered commands.
+    ///
+    /// * `commands`: Commands to run.
+    /// * `expansions`: Expansions to substitute into the commands.
+    /// * `options`: Settings for running the commands.
+    pub fn run_commands(
+        &self,
+        commands: &[RenderedCommand],
+        expansions: BTreeMap<String, String>,
+        options: &TaskOptions,
+    ) -> TaskReport {
+        let mut state = TaskState {
+            expansions,
+            background: vec![],
+            timeouts: timeout::Timeouts::new(options.exec_timeout, options.idle_timeout),
+            block: Block::Pre,
+            failed_block: None,
+            timed_out: false,
+        };
+        let mut report = TaskReport::default();
+        let mut results = vec![None; commands.len()];
+
+        for index in execution_order(commands) {
+            let rendered = &commands[index];
+            let skip = match rendered.block {
+                Block::Timeout => !state.timed_out || state.failed_block == Some(Block::Timeout),
+                Block::Task => report.failed || state.failed_block == Some(Block::Task),
+                block => state.failed_block == Some(block),
+            };
+            let start = Instant::now();
+            let status = if skip {
+                CommandStatus::Skipped
+            } else {
+                state.block = rendered.block;
+                if rendered.block == Block::Task {
+                    state.timeouts.start_task();
+                }
+                self.run_command(index, rendered, &mut state)
+            };
+            let name = rendered.command.command.name().to_string();
+            self.system(index, &format!("{} ({}) {}", name, rendered.block, status));
+
+            if status.is_failure() {
+                state.failed_block = Some(rendered.block);
+                match rendered.block {
+                    Block::Task => report.failed = true,
+                    Block::Pre if options.pre_error_fails_task => report.failed = true,
+                    _ => (),
+                }
+            }
+            results[index] = Some(CommandResult {
+                name,
+                block: rendered.block,
+                function: rendered.function.clone(),
+                status,
+                duration: start.elapsed(),
+            });
+        }
+        report.commands = results.into_iter().flatten().collect();
+        report.timed_out = state.timed_out;
+
+        for mut child in state.background {
+            kill_process_group(&mut child);
+        }
+        report
+    }
+
+    fn run_command(
+        &self,
+        index: usize,
+        rendered: &RenderedCommand,
+        state: &mut TaskState,
+    ) -> CommandStatus {
+        let interpolator = Interpolator::new(&state.expansions);
+        let command = match interpolator.interpolate(&rendered.command) {
+            Ok(interpolated) => {
+                for name in &interpolated.undefined {
+                    self.system(index, &format!("Expansion '{}' is not defined", name));
+                }
+                interpolated.value
+            }
+            Err(err) => {
+                return CommandStatus::Failed {
+                    exit_code: None,
+                    message: err.to_string(),
+                }
+            }
+        };
+
+        match &command.command {
+            EvgCommandSpec::ShellExec(params) => self.shell_exec(index, params, state),
+            EvgCommandSpec::SubprocessExec(params) => self.subprocess_exec(index, params, state),
+            EvgCommandSpec::ExpansionsUpdate(params) => {
+                expansions::update(&mut state.expansions, &self.workdir, params.as_ref())
+            }
+            EvgCommandSpec::ExpansionsWrite(params) => {
+                expansions::write(&state.expansions, &self.redacted, &self.workdir, params)
+            }
+            EvgCommandSpec::TimeoutUpdate(params) => state.timeouts.update(params),
+            EvgCommandSpec::ArchiveTargzPack(params) => archive::targz_pack(&self.workdir, params),
+            EvgCommandSpec::ArchiveTargzExtract(params) => {
+                archive::targz_extract(&self.workdir, params)
+            }
+            EvgCommandSpec::ArchiveAutoExtract(params) => {
+                archive::auto_extract(&self.workdir, params)
+            }
+            spec => self.run_artifact_command(spec, state),
+        }
+    }
+
+    fn run_artifact_command(&self, spec: &EvgCommandSpec, state: &TaskState) -> CommandStatus {
+        let store = match &self.artifacts {
+            Some(store) => store.as_ref(),
+            None => return CommandStatus::Unsupported,
+        };
+        let variant = state.expansions.get("build_variant").map(|v| v.as_str());
+        match spec {
+            EvgCommandSpec::S3Put(params) => s3::put(store, &self.workdir, params),
+            EvgCommandSpec::S3Get(params) => s3::get(store, &self.workdir, variant, params),
+            EvgCommandSpec::S3Copy(params) => s3::copy(store, variant, params),
+            _ => CommandStatus::Unsupported,
+        }
+    }
+
+    fn shell_exec(
+        &self,
+        index: usize,
+        params: &ShellExecParams,
+        state: &mut TaskState,
+    ) -> CommandStatus {
+        let options = ProcessOptions {
+            working_dir: params.working_dir.as_deref(),
+            env: command_env(
+                state,
+                params.env.as_ref(),
+                params.add_expansions_to_env,
+                params.include_expansions_in_env.as_deref(),
+            ),
+            background: params.background.unwrap_or(false),
+            continue_on_err: params.continue_on_err.unwrap_or(false),
+            silent: params.silent.unwrap_or(false),
+            ignore_standard_out: params.ignore_standard_out.unwrap_or(false),
+            ignore_standard_error: params.ignore_standard_error.unwrap_or(false),
+            redirect_standard_error_to_output: params
+                .redirect_standard_error_to_output
+                .unwrap_or(false),
+            ..Default::default()
+        };
+        let shell = params.shell.as_deref().unwrap_or(DEFAULT_SHELL);
+        let args = vec!["-c".to_string(), params.script.to_string()];
+        self.run_process(index, shell, &args, &options, state)
+    }
+
+    fn subprocess_exec(
+        &self,
+        index: usize,
+        params: &SubprocessExecParams,
+        state: &mut TaskState,
+    ) -> CommandStatus {
+        let config = &params.execution_config;
+        let options = ProcessOptions {
+            working_dir: params.working_dir.as_deref(),
+            env: command_env(
+                state,
+                params.env.as_ref(),
+                config.add_expansions_to_env,
+                config.include_expansions_in_env.as_deref(),
+            ),
+            add_to_path: config.add_to_path.as_deref().unwrap_or_default(),
+            background: params.background.unwrap_or(false),
+            continue_on_err: config.continue_on_err.unwrap_or(false),
+            silent: config.silent.unwrap_or(false),
+            ignore_standard_out: config.ignore_standard_out.unwrap_or(false),
+            ignore_standard_error: config.ignore_standard_error.unwrap_or(false),
+            redirect_standard_error_to_output: config
+                .redirect_standard_error_to_output
+                .unwrap_or(false),
+        };
+
+        let (program, args) = match (&params.binary, &params.command, &params.shell) {
+            (_, Some(command), Some(shell)) => (
+                shell.to_string(),
+                vec!["-c".to_string(), command.to_string()],
+            ),
+            (_, Some(command), None) => {
+                let mut words = split_command(command);
+                if words.is_empty() {
+                    return failure("'command' is empty");
+                }
+                let program = words.remove(0);
+                (program, words)
+            }
+            (Some(binary), None, _) => {
+                (binary.to_string(), params.args.clone().unwrap_or_default())
+            }
+            (None, None, _) => return failure("one of 'binary' or 'command' must be set"),
+        };
+        self.run_process(index, &program, &args, &options, state)
+    }
+
+    fn run_process(
+        &self,
+        index: usize,
+        program: &str,
+        args: &[String],
+        options: &ProcessOptions,
+        state: &mut TaskState,
+    ) -> CommandStatus {
+        let working_dir = match options.working_dir {
+            Some(dir) => self.workdir.join(dir),
+            None => self.workdir.clone(),
+        };
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(&working_dir)
+            .envs(&options.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if !options.add_to_path.is_empty() {
+            let mut paths: Vec<PathBuf> = options
+                .add_to_path
+                .iter()
+                .map(|p| self.workdir.join(p))
+                .collect();
+            let existing = std::env::var_os("PATH").unwrap_or_default();
+            paths.extend(std::env::split_paths(&existing));
+            if let Ok(path) = std::env::join_paths(paths) {
+                command.env("PATH", path);
+            }
+        }
+
+        // Put the process in a group of its own so the processes it starts are stopped with it.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let merged_output = if options.redirect_standard_error_to_output {
+            let pipe = os_pipe::pipe().and_then(|(reader, writer)| {
+                let stderr = writer.try_clone()?;
+                Ok((reader, writer, stderr))
+            });
+            match pipe {
+                Ok((reader, stdout, stderr)) => {
+                    command.stdout(stdout).stderr(stderr);
+                    Some(reader)
+                }
+                Err(err) => return failure(&format!("could not create pipe: {}", err)),
+            }
+        } else {
+            None
+        };
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(err) => return failure(&format!("could not start '{}': {}", program, err)),
+        };
+        // Drop the command so it no longer holds the write end of a merged output pipe.
+        drop(command);
+        let last_output = Arc::new(Mutex::new(Instant::now()));
+        let readers = match merged_output {
+            Some(output) => vec![self.stream_output(
+                index,
+                Some(output),
+                LogStream::Stdout,
+                options.silent || options.ignore_standard_out,
+                &last_output,
+            )],
+            None => vec![
+                self.stream_output(
+                    index,
+                    child.stdout.take(),
+                    LogStream::Stdout,
+                    options.silent || options.ignore_standard_out,
+                    &last_output,
+                ),
+                self.stream_output(
+                    index,
+                    child.stderr.take(),
+                    LogStream::Stderr,
+                    options.silent || options.ignore_standard_error,
+                    &last_output,
+                ),
+            ],
+        };
+
+        if options.background {
+            // Output of background processes is streamed until they close it, which may be
+            // after the task is finished if they started processes of their own.
+            state.background.push(child);
+            return CommandStatus::Background;
+        }
+
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) => (),
+                Err(err) => break Err(err),
+            }
+            let last = *last_output.lock().unwrap();
+            if let Some(message) = state.timeouts.exceeded(state.block, last) {
+                // The readers are not joined, processes that left the group of the killed one
+                // may still hold its output open.
+                kill_process_group(&mut child);
+                if state.block == Block::Task {
+                    state.timed_out = true;
+                }
+                return failure(&format!("'{}' timed out: {}", program, message));
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        };
+        for reader in readers {
+            let _ = reader.join();
+        }
+        match status {
+            Ok(status) if status.success() => CommandStatus::Succeeded,
+            Ok(status) if options.continue_on_err => CommandStatus::FailedIgnored {
+                exit_code: status.code(),
+            },
+            Ok(status) => CommandStatus::Failed {
+                exit_code: status.code(),
+                message: format!("'{}' exited with {}", program, status),
+            },
+            Err(err) => failure(&format!("could not wait for '{}': {}", program, err)),
+        }
+    }
+
+    /// Send the lines read from the given output of a process to the log sink.
+    ///
+    /// `last_output` is set to the time each line is read, even if the line is discarded.
+    fn stream_output<R: Read + Send + 'static>(
+        &self,
+        index: usize,
+        output: Option<R>,
+        stream: LogStream,
+        discard: bool,
+        last_output: &Arc<Mutex<Instant>>,
+    ) -> JoinHandle<()> {
+        let sink = Arc::clone(&self.sink);
+        let last_output = Arc::clone(last_output);
+        std::thread::spawn(move || {
+            let output = match output {
+                Some(output) => output,
+                None => return,
+            };
+            let mut reader = BufReader::new(output);
+            let mut buffer = vec![];
+            while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                *last_output.lock().unwrap() = Instant::now();
+                if !discard {
+                    let line = String::from_utf8_lossy(&buffer);
+                    sink.log(&LogLine {
+                        command: index,
+                        stream,
+                        text: line.trim_end_matches(&['\r', '\n'][..]),
+                    });
+                }
+                buffer.clear();
+            }
+        })
+    }
+
+    fn system(&self, index: usize, text: &str) {
+        self.sink.log(&LogLine {
+            command: index,
+            stream: LogStream::System,
+            text,
+        });
+    }
+}
+
+/// Get the order to run commands in.
+///
+/// Commands run in the order they were rendered, except that `timeout` commands run right after
+/// the task's own commands, before `post` or the teardown blocks.
+fn execution_order(commands: &[RenderedCommand]) -> Vec<usize> {
+    let split = commands
+        .iter()
+        .rposition(|c| {
+            matches!(
+                c.block,
+                Block::Pre | Block::SetupGroup | Block::SetupTask | Block::Task
+            )
+        })
+        .map_or(0, |i| i + 1);
+    let (before, after): (Vec<usize>, Vec<usize>) = (0..commands.len())
+        .filter(|i| commands[*i].block != Block::Timeout)
+        .partition(|i| *i < split);
+    let timeout = (0..commands.len()).filter(|i| commands[*i].block == Block::Timeout);
+    before.into_iter().chain(timeout).chain(after).collect()
+}
+
+/// Kill a process started by `run_process` along with the processes it started, and wait for it.
+///
+/// The process may have already exited, which is fine.
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // The process is the leader of its own group, see `run_process`.
+        // SAFETY: `kill` has no memory safety requirements.
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn failure(message: &str) -> CommandStatus {
+    CommandStatus::Failed {
+        exit_code: None,
+        message: message.to_string(),
+    }
+}
+
+/// Build the environment variables to set for a command.
+fn command_env(
+    state: &TaskState,
+    env: Option<&HashMap<String, String>>,
+    add_expansions_to_env: Option<bool>,
+    include_expansions_in_env: Option<&[String]>,
+) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    if add_expansions_to_env.unwrap_or(false) {
+        vars.extend(state.expansions.clone());
+    }
+    for name in include_expansions_in_env.unwrap_or_default() {
+        if let Some(value) = state.expansions.get(name) {
+            vars.insert(name.to_string(), value.to_string());
+        }
+    }
+    vars.extend(env.cloned().unwrap_or_default());
+    vars
+}
+
+/// Split a command line into words, respecting single and double quotes.
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::artifacts::LocalArtifactStore;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct CollectingSink {
+        lines: Mutex<Vec<(usize, LogStream, String)>>,
+    }
+
+    impl LogSink for CollectingSink {
+        fn log(&self, line: &LogLine) {
+            self.lines
+                .lock()
+                .unwrap()
+                .push((line.command, line.stream, line.text.to_string()));
+        }
+    }
+
+    impl CollectingSink {
+        fn output(&self, command: usize) -> Vec<(LogStream, String)> {
+            self.lines
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(c, s, _)| *c == command && *s != LogStream::System)
+                .map(|(_, s, t)| (*s, t.to_string()))
+                .collect()
+        }
+    }
+
+    const PROJECT: &str = r#"
+pre:
+  - command: shell.exec
+    params:
+      script: mkdir -p src && echo pre
+post:
+  - command: shell.exec
+    params:
+      script: echo post
+timeout:
+  - command: shell.exec
+    params:
+      script: echo timeout
+functions:
+  run:
+    command: shell.exec
+    params:
+      working_dir: src
+      script: echo "${message} from $(basename $(pwd)) $GREETING $build_variant" && echo oops >&2
+      env:
+        GREETING: hello
+      include_expansions_in_env: ["build_variant"]
+      redirect_standard_error_to_output: true
+tasks:
+  - name: test
+    commands:
+      - func: run
+        vars:
+          message: hi
+      - command: subprocess.exec
+        params:
+          command: sh -c 'exit 3'
+          continue_on_err: true
+      - command: subprocess.exec
+        params:
+          binary: sh
+          args: ["-c", "echo $PATH"]
+          add_to_path: ["bin"]
+      - command: shell.exec
+        params:
+          script: exit 1
+      - command: shell.exec
+        params:
+          script: echo never
+      - command: attach.results
+        params:
+          file_location: results.json
+buildvariants:
+  - name: linux
+    tasks:
+      - name: test
+"#;
+
+    #[test]
+    fn test_run_task() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(CollectingSink::default());
+        let runner = LocalRunner::new(workdir.path(), sink.clone()).unwrap();
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let report = runner.run_task(&project, "linux", "test").unwrap();
+
+        let statuses: Vec<(&str, Block, &CommandStatus)> = report
+            .commands
+            .iter()
+            .map(|c| (c.name.as_str(), c.block, &c.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("shell.exec", Block::Pre, &CommandStatus::Succeeded),
+                ("shell.exec", Block::Task, &CommandStatus::Succeeded),
+                (
+                    "subprocess.exec",
+                    Block::Task,
+                    &CommandStatus::FailedIgnored { exit_code: Some(3) }
+                ),
+                ("subprocess.exec", Block::Task, &CommandStatus::Succeeded),
+                (
+                    "shell.exec",
+                    Block::Task,
+                    &CommandStatus::Failed {
+                        exit_code: Some(1),
+                        message: "'sh' exited with exit status: 1".to_string()
+                    }
+                ),
+                ("shell.exec", Block::Task, &CommandStatus::Skipped),
+                ("attach.results", Block::Task, &CommandStatus::Skipped),
+                ("shell.exec", Block::Post, &CommandStatus::Succeeded),
+                ("shell.exec", Block::Timeout, &CommandStatus::Skipped),
+            ]
+        );
+        assert!(report.failed);
+        assert_eq!(
+            sink.output(1),
+            vec![
+                (LogStream::Stdout, "hi from src hello linux".to_string()),
+                (LogStream::Stdout, "oops".to_string()),
+            ]
+        );
+        let path = &sink.output(3)[0].1;
+        let bin = workdir.path().join("bin");
+        assert!(path.starts_with(&format!("{}:", bin.display())));
+    }
+
+    #[test]
+    fn test_background_and_unsupported_commands() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(CollectingSink::default());
+        let runner = LocalRunner::new(&workdir.path().join("nested"), sink).unwrap();
+        let project = EvgProject::from_yaml_str(
+            r#"
+tasks:
+  - name: test
+    commands:
+      - command: shell.exec
+        params:
+          script: |
+            sleep 30 &
+            echo $! > sleep.pid
+            wait
+            touch finished
+          background: true
+      - command: attach.results
+        params:
+          file_location: results.json
+      - command: shell.exec
+        params:
+          script: until [ -s sleep.pid ]; do sleep 0.1; done
+buildvariants:
+  - name: linux
+    tasks:
+      - name: test
+"#,
+        )
+        .unwrap();
+
+        let report = runner.run_task(&project, "linux", "test").unwrap();
+
+        // The task doesn't wait for background commands to finish.
+        assert!(!workdir.path().join("nested/finished").exists());
+        assert!(!report.failed);
+        assert_eq!(report.commands[0].status, CommandStatus::Background);
+        assert_eq!(report.commands[1].status, CommandStatus::Unsupported);
+
+        // Processes started by background commands are stopped with them.
+        #[cfg(unix)]
+        {
+            let pid: libc::pid_t = std::fs::read_to_string(workdir.path().join("nested/sleep.pid"))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap();
+            let stopped = (0..50).any(|_| {
+                std::thread::sleep(Duration::from_millis(100));
+                // SAFETY: `kill` has no memory safety requirements.
+                unsafe { libc::kill(pid, 0) != 0 }
+            });
+            assert!(stopped);
+        }
+    }
+
+    #[test]
+    fn test_artifacts_are_passed_between_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(LocalArtifactStore::new(&dir.path().join("store")));
+        let sink = Arc::new(CollectingSink::default());
+        let project = EvgProject::from_yaml_str(
+            r#"
+tasks:
+  - name: produce
+    commands:
+      - command: shell.exec
+        params:
+          script: |
+            mkdir -p out/nested && echo one > out/a.log && echo two > out/b.log && echo no > out/c.txt
+            echo three > out/nested/d.log
+            tar czf dist.tgz out
+      - command: s3.put
+        params:
+          local_files_include_filter: ["*.log"]
+          local_files_include_filter_prefix: out
+          remote_file: logs/
+          bucket: artifacts
+          aws_key: key
+          aws_secret: secret
+          permissions: private
+          content_type: text/plain
+      - command: s3.put
+        params:
+          local_file: dist.tgz
+          remote_file: dist.tgz
+          bucket: artifacts
+          aws_key: key
+          aws_secret: secret
+          permissions: private
+          content_type: application/gzip
+      - command: s3.put
+        params:
+          local_file: missing.txt
+          remote_file: missing.txt
+          bucket: artifacts
+          aws_key: key
+          aws_secret: secret
+          permissions: private
+          content_type: text/plain
+          optional: true
+      - command: s3Copy.copy
+        params:
+          aws_key: key
+          aws_secret: secret
+          s3_copy_files:
+            - source: {bucket: artifacts, path: dist.tgz}
+              destination: {bucket: release, path: "${build_variant}/dist.tgz"}
+            - source: {bucket: artifacts, path: missing.txt}
+              destination: {bucket: release, path: missing.txt}
+              optional: true
+  - name: consume
+    commands:
+      - command: s3.get
+        params:
+          local_file: a.log
+          remote_file: logs/a.log
+          bucket: artifacts
+          aws_key: key
+          aws_secret: secret
+      - command: s3.get
+        params:
+          extract_to: extracted
+          remote_file: linux/dist.tgz
+          bucket: release
+          aws_key: key
+          aws_secret: secret
+      - command: s3.get
+        params:
+          local_file: other.log
+          remote_file: logs/b.log
+          bucket: artifacts
+          aws_key: key
+          aws_secret: secret
+          build_variants: ["windows"]
+buildvariants:
+  - name: linux
+    tasks:
+      - name: produce
+      - name: consume
+"#,
+        )
+        .unwrap();
+
+        let producer = LocalRunner::new(&dir.path().join("produce"), sink.clone())
+            .unwrap()
+            .with_artifact_store(store.clone());
+        let produced = producer.run_task(&project, "linux", "produce").unwrap();
+        let consumer = LocalRunner::new(&dir.path().join("consume"), sink)
+            .unwrap()
+            .with_artifact_store(store);
+        let consumed = consumer.run_task(&project, "linux", "consume").unwrap();
+
+        assert!(!produced.failed, "{:?}", produced);
+        assert!(!consumed.failed, "{:?}", consumed);
+        let consume_dir = dir.path().join("consume");
+        assert_eq!(
+            std::fs::read_to_string(consume_dir.join("a.log")).unwrap(),
+            "one\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(consume_dir.join("extracted/out/b.log")).unwrap(),
+            "two\n"
+        );
+        assert_eq!(consumed.commands[2].status, CommandStatus::Skipped);
+        assert!(!dir.path().join("store/artifacts/logs/c.txt").exists());
+        assert!(dir.path().join("store/artifacts/logs/d.log").exists());
+    }
+
+    #[test]
+    fn test_expansions_are_updated_and_written() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(CollectingSink::default());
+        let runner = LocalRunner::new(workdir.path(), sink.clone())
+            .unwrap()
+            .with_redacted_expansions(["secret"]);
+        let project = EvgProject::from_yaml_str(
+            r#"
+tasks:
+  - name: test
+    commands:
+      - command: expansions.update
+        params:
+          updates:
+            - key: greeting
+              value: hello ${build_variant}
+      - command: shell.exec
+        params:
+          script: |
+            printf 'name: world\ncount: 3\n' > extra.yml
+      - command: expansions.update
+        params:
+          file: extra.yml
+      - command: expansions.update
+        params:
+          file: missing.yml
+          ignore_missing_file: true
+      - command: shell.exec
+        params:
+          script: echo "${greeting} ${name} ${count}"
+      - command: expansions.write
+        params:
+          file: expansions.yml
+      - command: expansions.write
+        params:
+          file: all.yml
+          redacted: true
+      - command: expansions.update
+        params:
+          file: missing.yml
+buildvariants:
+  - name: linux
+    expansions:
+      secret: hunter2
+    tasks:
+      - name: test
+"#,
+        )
+        .unwrap();
+
+        let report = runner.run_task(&project, "linux", "test").unwrap();
+
+        assert_eq!(
+            sink.output(4),
+            vec![(LogStream::Stdout, "hello linux world 3".to_string())]
+        );
+        let written = std::fs::read_to_string(workdir.path().join("expansions.yml")).unwrap();
+        assert!(written.contains("greeting: hello linux"));
+        assert!(written.contains("count: \"3\""));
+        assert!(!written.contains("hunter2"));
+        let all = std::fs::read_to_string(workdir.path().join("all.yml")).unwrap();
+        assert!(all.contains("secret: hunter2"));
+        assert!(report.failed);
+        assert!(report.commands[7].status.is_failure());
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(CollectingSink::default());
+        let runner = LocalRunner::new(workdir.path(), sink.clone()).unwrap();
+        let project = EvgProject::from_yaml_str(
+            r#"
+post:
+  - command: shell.exec
+    params:
+      script: echo post
+timeout:
+  - command: shell.exec
+    params:
+      script: echo timeout
+tasks:
+  - name: idle
+    commands:
+      - command: timeout.update
+        params:
+          timeout_secs: ${idle_timeout}
+      - command: shell.exec
+        params:
+          script: echo started && sleep 5
+      - command: shell.exec
+        params:
+          script: echo never
+  - name: exec
+    exec_timeout_secs: 1
+    commands:
+      - command: shell.exec
+        params:
+          script: while true; do echo running; sleep 0.1; done
+  - name: exec_expansion
+    exec_timeout_secs: ${idle_timeout}
+    commands:
+      - command: shell.exec
+        params:
+          script: while true; do echo running; sleep 0.1; done
+  - name: invalid
+    commands:
+      - command: timeout.update
+        params:
+          exec_timeout_secs: soon
+  - name: undefined
+    exec_timeout_secs: ${undefined_timeout}
+buildvariants:
+  - name: linux
+    expansions:
+      idle_timeout: "1"
+    tasks:
+      - name: idle
+      - name: exec
+      - name: exec_expansion
+      - name: invalid
+      - name: undefined
+"#,
+        )
+        .unwrap();
+
+        let idle = runner.run_task(&project, "linux", "idle").unwrap();
+        let exec = runner.run_task(&project, "linux", "exec").unwrap();
+        let exec_expansion = runner
+            .run_task(&project, "linux", "exec_expansion")
+            .unwrap();
+        let invalid = runner.run_task(&project, "linux", "invalid").unwrap();
+        let undefined = runner.run_task(&project, "linux", "undefined");
+
+        let statuses: Vec<String> = idle.commands.iter().map(|c| c.status.to_string()).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "succeeded",
+                "failed: 'sh' timed out: no output for 1 seconds",
+                "skipped",
+                "succeeded",
+                "succeeded",
+            ]
+        );
+        assert!(idle.failed && idle.timed_out);
+        assert!(exec.failed && exec.timed_out);
+        assert!(exec.commands[0]
+            .status
+            .to_string()
+            .ends_with("exceeded exec timeout of 1 seconds"));
+        assert!(exec_expansion.failed && exec_expansion.timed_out);
+        assert!(exec_expansion.commands[0]
+            .status
+            .to_string()
+            .ends_with("exceeded exec timeout of 1 seconds"));
+        assert!(invalid.failed && !invalid.timed_out);
+        assert!(invalid.commands[0].status.is_failure());
+        assert!(
+            matches!(undefined, Err(RunError::ExecTimeout { task, .. }) if task == "undefined")
+        );
+
+        // The timeout commands run before post.
+        let lines = sink.lines.lock().unwrap();
+        let stdout: Vec<&str> = lines
+            .iter()
+            .filter(|(_, s, _)| *s == LogStream::Stdout)
+            .map(|(_, _, t)| t.as_str())
+            .take(3)
+            .collect();
+        assert_eq!(stdout, vec!["started", "timeout", "post"]);
+    }
+
+    #[test]
+    fn test_archive_commands() {
+        let workdir = tempfile::tempdir().unwrap();
+        let sink = Arc::new(CollectingSink::default());
+        let runner = LocalRunner::new(workdir.path(), sink).unwrap();
+        let project = EvgProject::from_yaml_str(
+            r#"
+tasks:
+  - name: test
+    commands:
+      - command: shell.exec
+        params:
+          script: mkdir -p build/lib && echo a > build/a.o && echo b > build/lib/b.o && echo c > build/c.txt
+      - command: archive.targz_pack
+        params:
+          target: dist/build.tgz
+          source_dir: build
+          include: ["./**"]
+          exclude_files: ["*.txt"]
+      - command: archive.targz_extract
+        params:
+          path: dist/build.tgz
+          destination: extracted
+          exclude_files: ["lib/*"]
+      - command: archive.auto_extract
+        params:
+          path: dist/build.tgz
+          destination: auto
+      - command: archive.auto_extract
+        params:
+          path: missing.zip
+          destination: auto
+buildvariants:
+  - name: linux
+    tasks:
+      - name: test
+"#,
+        )
+        .unwrap();
+
+        let report = runner.run_task(&project, "linux", "test").unwrap();
+
+        let statuses: Vec<bool> = report
+            .commands
+            .iter()
+            .map(|c| c.status.is_failure())
+            .collect();
+        assert_eq!(statuses, vec![false, false, false, false, true]);
+        assert!(workdir.path().join("extracted/a.o").is_file());
+        assert!(!workdir.path().join("extracted/lib/b.o").exists());
+        assert!(workdir.path().join("auto/lib/b.o").is_file());
+        assert!(!workdir.path().join("auto/c.txt").exists());
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(
+            split_command(r#"python -c "print('hi there')" '' x"#),
+            vec!["python", "-c", "print('hi there')", "", "x"]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! Emulate the `archive.*` commands with [`crate::archive`].
+use super::{failure, CommandStatus};
+use crate::archive::{self, ArchiveError};
+use crate::models::builtin::{
+    ArchiveAutoExtractParams, ArchiveTargzExtractParams, ArchiveTargzPackParams,
+};
+use std::path::Path;
+
+/// Create a gzipped tarball of the matching files under `source_dir`.
+pub(super) fn targz_pack(workdir: &Path, params: &ArchiveTargzPackParams) -> CommandStatus {
+    let result = archive::pack_targz(
+        &workdir.join(&params.source_dir),
+        &workdir.join(&params.target),
+        &params.include,
+        params.exclude_files.as_deref().unwrap_or_default(),
+    );
+    status(result.map(|_| ()))
+}
+
+/// Extract a gzipped tarball to `destination`.
+pub(super) fn targz_extract(workdir: &Path, params: &ArchiveTargzExtractParams) -> CommandStatus {
+    status(archive::extract_targz(
+        &workdir.join(&params.path),
+        &workdir.join(&params.destination),
+        params.exclude_files.as_deref().unwrap_or_default(),
+    ))
+}
+
+/// Extract an archive of any supported format to `destination`.
+pub(super) fn auto_extract(workdir: &Path, params: &ArchiveAutoExtractParams) -> CommandStatus {
+    let result = archive::extract(
+        &workdir.join(&params.path),
+        &workdir.join(&params.destination),
+        params.exclude_files.as_deref().unwrap_or_default(),
+    );
+    status(result.map(|_| ()))
+}
+
+fn status(result: Result<(), ArchiveError>) -> CommandStatus {
+    match result {
+        Ok(()) => CommandStatus::Succeeded,
+        Err(err) => failure(&err.to_string()),
+    }
+}
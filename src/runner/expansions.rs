@@ -0,0 +1,101 @@
+//! Emulate the commands that read and write the expansions of a task.
+use super::{failure, CommandStatus};
+use crate::models::builtin::{ExpansionsUpdateParams, ExpansionsWriteParams};
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Update expansions with the inline `updates` and then the contents of `file`.
+///
+/// Values of the inline updates have already had expansions substituted into them, values from
+/// the file are used as they are.
+pub(super) fn update(
+    expansions: &mut BTreeMap<String, String>,
+    workdir: &Path,
+    params: Option<&ExpansionsUpdateParams>,
+) -> CommandStatus {
+    let params = match params {
+        Some(params) => params,
+        None => return CommandStatus::Succeeded,
+    };
+    for update in params.updates.iter().flatten() {
+        expansions.insert(update.key.to_string(), update.value.to_string());
+    }
+
+    if let Some(file) = &params.file {
+        let path = workdir.join(file);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err)
+                if err.kind() == io::ErrorKind::NotFound
+                    && params.ignore_missing_file.unwrap_or(false) =>
+            {
+                return CommandStatus::Succeeded
+            }
+            Err(err) => return failure(&format!("could not read '{}': {}", path.display(), err)),
+        };
+        match parse_expansions_file(&contents) {
+            Ok(updates) => expansions.extend(updates),
+            Err(message) => return failure(&format!("invalid '{}': {}", path.display(), message)),
+        }
+    }
+    CommandStatus::Succeeded
+}
+
+/// Write expansions to `file` as YAML.
+///
+/// Expansions in `redacted` are only written if the command sets `redacted: true`.
+pub(super) fn write(
+    expansions: &BTreeMap<String, String>,
+    redacted: &BTreeSet<String>,
+    workdir: &Path,
+    params: &ExpansionsWriteParams,
+) -> CommandStatus {
+    let include_redacted = params.redacted.unwrap_or(false);
+    let written: BTreeMap<&String, &String> = expansions
+        .iter()
+        .filter(|(name, _)| include_redacted || !redacted.contains(*name))
+        .collect();
+    let path = workdir.join(&params.file);
+    let result = serde_yaml::to_string(&written)
+        .map_err(|err| err.to_string())
+        .and_then(|yaml| fs::write(&path, yaml).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => CommandStatus::Succeeded,
+        Err(message) => failure(&format!(
+            "could not write '{}': {}",
+            path.display(),
+            message
+        )),
+    }
+}
+
+/// Read a YAML mapping of expansion names to values.
+///
+/// Numbers and booleans are converted to strings, an empty file has no expansions.
+fn parse_expansions_file(contents: &str) -> Result<BTreeMap<String, String>, String> {
+    if contents.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let mapping = match serde_yaml::from_str(contents).map_err(|err| err.to_string())? {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => return Ok(BTreeMap::new()),
+        _ => return Err("expected a mapping of expansions".to_string()),
+    };
+    mapping
+        .into_iter()
+        .map(|(key, value)| Ok((scalar_to_string(&key)?, scalar_to_string(&value)?)))
+        .collect()
+}
+
+fn scalar_to_string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        Value::Null => Ok(String::new()),
+        _ => Err("expansion names and values must be scalars".to_string()),
+    }
+}
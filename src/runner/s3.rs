@@ -0,0 +1,170 @@
+//! Emulate the S3 commands with an [`ArtifactStore`].
+use super::{failure, CommandStatus};
+use crate::archive;
+use crate::artifacts::ArtifactStore;
+use crate::models::builtin::{S3CopyParams, S3GetParams, S3PutParams};
+use crate::models::params::S3Location;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Check if a command limited to the given build variants runs on the current one.
+fn runs_on_variant(build_variants: Option<&[String]>, variant: Option<&str>) -> bool {
+    match (build_variants, variant) {
+        (Some(build_variants), Some(variant)) => build_variants.iter().any(|bv| bv == variant),
+        _ => true,
+    }
+}
+
+/// Upload local files to the store.
+pub(super) fn put(
+    store: &dyn ArtifactStore,
+    workdir: &Path,
+    params: &S3PutParams,
+) -> CommandStatus {
+    let optional = params.optional.unwrap_or(false);
+    let files: Vec<(PathBuf, String)> =
+        match (&params.local_file, &params.local_files_include_filter) {
+            (Some(local_file), None) => {
+                vec![(workdir.join(local_file), params.remote_file.to_string())]
+            }
+            (None, Some(filters)) => {
+                let base = workdir.join(
+                    params
+                        .local_files_include_filter_prefix
+                        .as_deref()
+                        .unwrap_or(""),
+                );
+                match matching_files(&base, filters) {
+                    Ok(files) => files
+                        .into_iter()
+                        .map(|file| {
+                            let name = file
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                                .to_string();
+                            (file, format!("{}{}", params.remote_file, name))
+                        })
+                        .collect(),
+                    Err(message) => return failure(&message),
+                }
+            }
+            _ => {
+                return failure(
+                    "exactly one of 'local_file' or 'local_files_include_filter' must be set",
+                )
+            }
+        };
+
+    if files.is_empty() && !optional {
+        return failure("no files matched 'local_files_include_filter'");
+    }
+    for (file, remote_file) in files {
+        if !file.is_file() {
+            if optional {
+                continue;
+            }
+            return failure(&format!("'{}' does not exist", file.display()));
+        }
+        let location = S3Location {
+            bucket: params.bucket.to_string(),
+            path: remote_file,
+            extra: BTreeMap::new(),
+        };
+        if let Err(err) = store.put(&location, &file) {
+            return failure(&format!("could not put '{}': {}", file.display(), err));
+        }
+    }
+    CommandStatus::Succeeded
+}
+
+/// Download a file from the store, extracting it if `extract_to` is set.
+pub(super) fn get(
+    store: &dyn ArtifactStore,
+    workdir: &Path,
+    variant: Option<&str>,
+    params: &S3GetParams,
+) -> CommandStatus {
+    if !runs_on_variant(params.build_variants.as_deref(), variant) {
+        return CommandStatus::Skipped;
+    }
+    let location = S3Location {
+        bucket: params.bucket.to_string(),
+        path: params.remote_file.to_string(),
+        extra: BTreeMap::new(),
+    };
+
+    match (&params.local_file, &params.extract_to) {
+        (Some(local_file), None) => match store.get(&location, &workdir.join(local_file)) {
+            Ok(()) => CommandStatus::Succeeded,
+            Err(err) => failure(&format!("could not get '{}': {}", params.remote_file, err)),
+        },
+        (None, Some(extract_to)) => {
+            let destination = workdir.join(extract_to);
+            match get_and_extract(store, &location, &destination) {
+                Ok(()) => CommandStatus::Succeeded,
+                Err(err) => failure(&format!(
+                    "could not extract '{}' to '{}': {}",
+                    params.remote_file,
+                    destination.display(),
+                    err
+                )),
+            }
+        }
+        _ => failure("exactly one of 'local_file' or 'extract_to' must be set"),
+    }
+}
+
+/// Copy files between locations in the store.
+pub(super) fn copy(
+    store: &dyn ArtifactStore,
+    variant: Option<&str>,
+    params: &S3CopyParams,
+) -> CommandStatus {
+    for file in &params.s3_copy_files {
+        if !runs_on_variant(file.build_variants.as_deref(), variant) {
+            continue;
+        }
+        match store.copy(&file.source, &file.destination) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound && file.optional == Some(true) => (),
+            Err(err) => {
+                return failure(&format!(
+                    "could not copy '{}/{}': {}",
+                    file.source.bucket, file.source.path, err
+                ))
+            }
+        }
+    }
+    CommandStatus::Succeeded
+}
+
+/// Get the files under the given directory that match any of the given glob patterns.
+///
+/// Patterns are matched the same way as the include globs of `archive.targz_pack`.
+fn matching_files(base: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, String> {
+    if !base.is_dir() {
+        return Ok(vec![]);
+    }
+    archive::find_files(base, patterns, &[])
+        .map(|files| files.into_iter().map(|(path, _)| path).collect())
+        .map_err(|err| err.to_string())
+}
+
+/// Download a gzipped tarball from the store and extract it to the given directory.
+fn get_and_extract(
+    store: &dyn ArtifactStore,
+    location: &S3Location,
+    destination: &Path,
+) -> Result<(), String> {
+    fs::create_dir_all(destination).map_err(|err| err.to_string())?;
+    let download = destination.join(".s3-get.tgz");
+    store
+        .get(location, &download)
+        .map_err(|err| err.to_string())?;
+    let result = archive::extract_targz(&download, destination, &[]);
+    fs::remove_file(&download).map_err(|err| err.to_string())?;
+    result.map_err(|err| err.to_string())
+}
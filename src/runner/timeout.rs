@@ -0,0 +1,90 @@
+//! Emulate `timeout.update` and enforce the timeouts of a task.
+use super::{failure, CommandStatus};
+use crate::interpolate::Interpolator;
+use crate::models::builtin::{TimeoutUpdateParams, TimeoutValue};
+use crate::render::Block;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// The timeouts of a running task.
+#[derive(Debug)]
+pub(super) struct Timeouts {
+    exec: Duration,
+    idle: Duration,
+    task_started: Option<Instant>,
+}
+
+impl Timeouts {
+    pub(super) fn new(exec: Duration, idle: Duration) -> Self {
+        Timeouts {
+            exec,
+            idle,
+            task_started: None,
+        }
+    }
+
+    /// Note that the task's own commands have started, which the exec timeout is measured from.
+    pub(super) fn start_task(&mut self) {
+        self.task_started.get_or_insert_with(Instant::now);
+    }
+
+    /// Change the timeouts as given by `timeout.update`.
+    pub(super) fn update(&mut self, params: &TimeoutUpdateParams) -> CommandStatus {
+        let exec = params.exec_timeout_secs.as_ref().map(resolve).transpose();
+        let idle = params.timeout_secs.as_ref().map(resolve).transpose();
+        match (exec, idle) {
+            (Ok(exec), Ok(idle)) => {
+                self.exec = exec.unwrap_or(self.exec);
+                self.idle = idle.unwrap_or(self.idle);
+                CommandStatus::Succeeded
+            }
+            (Err(message), _) | (_, Err(message)) => failure(&message),
+        }
+    }
+
+    /// Check if a command in the given block has run into a timeout, describing it if so.
+    ///
+    /// The exec timeout only applies to the task's own commands, the idle timeout applies to
+    /// every command.
+    ///
+    /// * `block`: Block of the running command.
+    /// * `last_output`: When the command last produced output, or when it started if it hasn't.
+    pub(super) fn exceeded(&self, block: Block, last_output: Instant) -> Option<String> {
+        if block == Block::Task && self.task_started.is_some_and(|s| s.elapsed() > self.exec) {
+            return Some(format!(
+                "exceeded exec timeout of {} seconds",
+                self.exec.as_secs()
+            ));
+        }
+        if last_output.elapsed() > self.idle {
+            return Some(format!("no output for {} seconds", self.idle.as_secs()));
+        }
+        None
+    }
+}
+
+/// Get the duration a timeout value stands for, substituting the given expansions into it.
+pub(super) fn resolve_expanded(
+    value: &TimeoutValue,
+    expansions: &BTreeMap<String, String>,
+) -> Result<Duration, String> {
+    let value = Interpolator::new(expansions)
+        .interpolate(value)
+        .and_then(|interpolated| interpolated.into_result())
+        .map_err(|err| err.to_string())?;
+    resolve(&value)
+}
+
+/// Get the duration a timeout value stands for.
+///
+/// Expansions in the value have already been substituted, so it has to be a number of seconds.
+fn resolve(value: &TimeoutValue) -> Result<Duration, String> {
+    match value {
+        TimeoutValue::Int(secs) => Ok(Duration::from_secs(*secs)),
+        TimeoutValue::Expansion(value) => value
+            .trim()
+            .parse()
+            .map(Duration::from_secs)
+            .map_err(|_| format!("invalid timeout '{}', expected a number of seconds", value)),
+    }
+}
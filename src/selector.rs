@@ -0,0 +1,351 @@
+//! Parse and evaluate the selectors Evergreen allows in place of task names.
+//!
+//! A selector is a whitespace separated list of criteria that must all match:
+//!
+//! * `name` matches the task or task group with that name.
+//! * `.tag` matches tasks and task groups with that tag.
+//! * `*` matches everything.
+//!
+//! Any criterion other than `*` can be negated with a leading `!`, so `.tests !.slow` matches
+//! everything tagged `tests` that is not also tagged `slow`.
+use crate::models::project::EvgProject;
+use crate::models::task::EvgTask;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+const WILDCARD: &str = "*";
+
+/// Errors that can occur while parsing or evaluating a selector.
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum SelectorError {
+    /// The selector does not contain any criteria.
+    #[error("Selector is empty")]
+    Empty,
+
+    /// A criterion in the selector could not be parsed.
+    #[error("Invalid criterion '{criterion}' in selector '{selector}': {reason}")]
+    InvalidCriterion {
+        /// The selector being parsed.
+        selector: String,
+        /// The criterion that could not be parsed.
+        criterion: String,
+        /// Why the criterion is invalid.
+        reason: String,
+    },
+
+    /// The selector did not match anything.
+    #[error("Selector '{selector}' does not match anything")]
+    NoMatches {
+        /// The selector that matched nothing.
+        selector: String,
+    },
+}
+
+/// What a single criterion of a selector matches against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CriterionKind {
+    /// Match everything.
+    All,
+    /// Match by name.
+    Name(String),
+    /// Match by tag.
+    Tag(String),
+}
+
+/// A single criterion of a selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Criterion {
+    /// Match things that do not meet the criterion.
+    pub negated: bool,
+    /// What the criterion matches.
+    pub kind: CriterionKind,
+}
+
+impl Criterion {
+    fn matches(&self, name: &str, tags: &[String]) -> bool {
+        let is_match = match &self.kind {
+            CriterionKind::All => true,
+            CriterionKind::Name(n) => n == name,
+            CriterionKind::Tag(tag) => tags.iter().any(|t| t == tag),
+        };
+        is_match != self.negated
+    }
+}
+
+impl Display for Criterion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        match &self.kind {
+            CriterionKind::All => write!(f, "{}", WILDCARD),
+            CriterionKind::Name(name) => write!(f, "{}", name),
+            CriterionKind::Tag(tag) => write!(f, ".{}", tag),
+        }
+    }
+}
+
+/// A parsed selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    /// Criteria that must all match.
+    pub criteria: Vec<Criterion>,
+}
+
+impl Selector {
+    /// Parse the given selector.
+    ///
+    /// * `selector`: Selector to parse.
+    pub fn parse(selector: &str) -> Result<Self, SelectorError> {
+        let criteria = selector
+            .split_whitespace()
+            .map(|criterion| parse_criterion(selector, criterion))
+            .collect::<Result<Vec<Criterion>, SelectorError>>()?;
+        if criteria.is_empty() {
+            return Err(SelectorError::Empty);
+        }
+        Ok(Selector { criteria })
+    }
+
+    /// Get the name this selector refers to if it is just a plain name.
+    pub fn name(&self) -> Option<&str> {
+        match self.criteria.as_slice() {
+            [Criterion {
+                negated: false,
+                kind: CriterionKind::Name(name),
+            }] => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Check if something with the given name and tags is matched by this selector.
+    ///
+    /// * `name`: Name of task or task group.
+    /// * `tags`: Tags of task or task group.
+    pub fn matches(&self, name: &str, tags: &[String]) -> bool {
+        self.criteria.iter().all(|c| c.matches(name, tags))
+    }
+
+    /// Check if the given task is matched by this selector.
+    pub fn matches_task(&self, task: &EvgTask) -> bool {
+        self.matches(&task.name, task.tags.as_deref().unwrap_or_default())
+    }
+
+    /// Get the tasks in the given project that this selector matches.
+    ///
+    /// Fails if no tasks are matched.
+    ///
+    /// * `project`: Project to select tasks from.
+    pub fn select_tasks<'a>(
+        &self,
+        project: &'a EvgProject,
+    ) -> Result<Vec<&'a EvgTask>, SelectorError> {
+        let tasks: Vec<&EvgTask> = project
+            .tasks
+            .iter()
+            .filter(|t| self.matches_task(t))
+            .collect();
+        if tasks.is_empty() {
+            return Err(self.no_matches());
+        }
+        Ok(tasks)
+    }
+
+    /// Get the names of the tasks and task groups in the given project this selector matches.
+    ///
+    /// This is how a selector in the task list of a build variant is evaluated. Fails if
+    /// nothing is matched.
+    ///
+    /// * `project`: Project to select tasks and task groups from.
+    pub fn select_task_units<'a>(
+        &self,
+        project: &'a EvgProject,
+    ) -> Result<Vec<&'a str>, SelectorError> {
+        let tasks = project
+            .tasks
+            .iter()
+            .filter(|t| self.matches_task(t))
+            .map(|t| t.name.as_str());
+        let task_groups = project
+            .task_groups
+            .iter()
+            .flatten()
+            .filter(|tg| self.matches(&tg.name, tg.tags.as_deref().unwrap_or_default()))
+            .map(|tg| tg.name.as_str());
+        let names: Vec<&str> = tasks.chain(task_groups).collect();
+        if names.is_empty() {
+            return Err(self.no_matches());
+        }
+        Ok(names)
+    }
+
+    fn no_matches(&self) -> SelectorError {
+        SelectorError::NoMatches {
+            selector: self.to_string(),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Selector::parse(s)
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let criteria: Vec<String> = self.criteria.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", criteria.join(" "))
+    }
+}
+
+fn parse_criterion(selector: &str, criterion: &str) -> Result<Criterion, SelectorError> {
+    let invalid = |reason: &str| SelectorError::InvalidCriterion {
+        selector: selector.to_string(),
+        criterion: criterion.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let (negated, rest) = match criterion.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, criterion),
+    };
+    let kind = match rest.strip_prefix('.') {
+        Some(tag) => CriterionKind::Tag(tag.to_string()),
+        None if rest == WILDCARD => CriterionKind::All,
+        None => CriterionKind::Name(rest.to_string()),
+    };
+
+    match &kind {
+        CriterionKind::All if negated => Err(invalid("the wildcard cannot be negated")),
+        CriterionKind::Name(name) | CriterionKind::Tag(name) if name.is_empty() => {
+            Err(invalid("missing name"))
+        }
+        CriterionKind::Name(name) | CriterionKind::Tag(name) if name.starts_with(['!', '.']) => {
+            Err(invalid("unexpected '!' or '.'"))
+        }
+        _ => Ok(Criterion { negated, kind }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROJECT: &str = r#"
+tasks:
+  - name: compile
+    tags: ["build"]
+  - name: unit_tests
+    tags: ["tests"]
+  - name: integration_tests
+    tags: ["tests", "slow"]
+task_groups:
+  - name: smoke_group
+    tags: ["tests"]
+    tasks: ["unit_tests"]
+"#;
+
+    #[test]
+    fn test_parse_selectors() {
+        let selector = Selector::parse(" .tests  !.slow !compile * ").unwrap();
+
+        assert_eq!(
+            selector.criteria,
+            vec![
+                Criterion {
+                    negated: false,
+                    kind: CriterionKind::Tag("tests".to_string())
+                },
+                Criterion {
+                    negated: true,
+                    kind: CriterionKind::Tag("slow".to_string())
+                },
+                Criterion {
+                    negated: true,
+                    kind: CriterionKind::Name("compile".to_string())
+                },
+                Criterion {
+                    negated: false,
+                    kind: CriterionKind::All
+                },
+            ]
+        );
+        assert_eq!(selector.to_string(), ".tests !.slow !compile *");
+        assert_eq!(selector.name(), None);
+        assert_eq!(Selector::parse("compile").unwrap().name(), Some("compile"));
+    }
+
+    #[test]
+    fn test_malformed_selectors_fail_to_parse() {
+        assert_eq!(Selector::parse("  "), Err(SelectorError::Empty));
+        for (selector, criterion) in &[
+            (".tests .", "."),
+            ("!", "!"),
+            ("!*", "!*"),
+            ("!!.tests", "!!.tests"),
+            ("..tests", "..tests"),
+        ] {
+            match Selector::parse(selector) {
+                Err(SelectorError::InvalidCriterion { criterion: c, .. }) => {
+                    assert_eq!(&c, criterion)
+                }
+                result => panic!("Expected '{}' to be invalid, got {:?}", selector, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_tasks() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+        let select = |selector: &str| -> Vec<String> {
+            Selector::parse(selector)
+                .unwrap()
+                .select_tasks(&project)
+                .unwrap()
+                .iter()
+                .map(|t| t.name.to_string())
+                .collect()
+        };
+
+        assert_eq!(select(".tests"), vec!["unit_tests", "integration_tests"]);
+        assert_eq!(select(".tests !.slow"), vec!["unit_tests"]);
+        assert_eq!(select("!.tests"), vec!["compile"]);
+        assert_eq!(
+            select("* !compile"),
+            vec!["unit_tests", "integration_tests"]
+        );
+        assert_eq!(select("compile"), vec!["compile"]);
+    }
+
+    #[test]
+    fn test_select_task_units_includes_task_groups() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let names = Selector::parse(".tests !.slow")
+            .unwrap()
+            .select_task_units(&project)
+            .unwrap();
+
+        assert_eq!(names, vec!["unit_tests", "smoke_group"]);
+    }
+
+    #[test]
+    fn test_selectors_that_match_nothing_fail() {
+        let project = EvgProject::from_yaml_str(PROJECT).unwrap();
+
+        let result = Selector::parse(".build !compile")
+            .unwrap()
+            .select_tasks(&project);
+
+        assert_eq!(
+            result.unwrap_err(),
+            SelectorError::NoMatches {
+                selector: ".build !compile".to_string()
+            }
+        );
+    }
+}
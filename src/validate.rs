@@ -0,0 +1,597 @@
+//! Check that an Evergreen project is internally consistent.
+//!
+//! Validation looks for references between parts of the project that point at something that
+//! does not exist, like a build variant referencing an undefined task or a task calling an
+//! undefined function. It also checks that tasks don't set fields that can't be used together,
+//! like `batchtime` and `cron`.
+use crate::matrix::expand_matrix;
+use crate::models::commands::EvgCommand;
+use crate::models::project::EvgProject;
+use crate::selector::{Selector, SelectorError};
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+/// A problem found while validating a project.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Logical path to the node with the problem, e.g. `buildvariants[2].tasks[0]`.
+    pub path: String,
+    /// What is wrong with the node.
+    pub kind: DiagnosticKind,
+}
+
+/// The kinds of problems validation can detect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// A build variant references a task, task group or tag that is not defined.
+    UndefinedTaskRef(String),
+    /// A task name is a selector that cannot be parsed.
+    InvalidSelector(String),
+    /// A command calls a function that is not defined.
+    UndefinedFunction(String),
+    /// A task depends on a task that is not defined.
+    UndefinedDependency(String),
+    /// A task depends on a task in a build variant that is not defined.
+    UndefinedDependencyVariant(String),
+    /// A display task includes a task that is not part of its build variant.
+    UndefinedExecutionTask(String),
+    /// A task group includes a task that is not defined.
+    UndefinedTaskGroupTask(String),
+    /// A build variant uses a module that is not declared by the project.
+    UndeclaredModule(String),
+    /// A task sets two fields that can't be used together.
+    ConflictingFields(String, String),
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use DiagnosticKind::*;
+        match &self.kind {
+            UndefinedTaskRef(name) => write!(
+                f,
+                "{}: '{}' is not a task, task group or tag",
+                self.path, name
+            ),
+            InvalidSelector(message) => write!(f, "{}: {}", self.path, message),
+            UndefinedFunction(name) => {
+                write!(f, "{}: function '{}' is not defined", self.path, name)
+            }
+            UndefinedDependency(name) => {
+                write!(f, "{}: dependency '{}' is not a task", self.path, name)
+            }
+            UndefinedDependencyVariant(name) => write!(
+                f,
+                "{}: dependency build variant '{}' is not defined",
+                self.path, name
+            ),
+            UndefinedExecutionTask(name) => write!(
+                f,
+                "{}: execution task '{}' is not part of the build variant",
+                self.path, name
+            ),
+            UndefinedTaskGroupTask(name) => {
+                write!(f, "{}: task '{}' is not defined", self.path, name)
+            }
+            UndeclaredModule(name) => {
+                write!(f, "{}: module '{}' is not declared", self.path, name)
+            }
+            ConflictingFields(first, second) => write!(
+                f,
+                "{}: '{}' and '{}' can't both be set",
+                self.path, first, second
+            ),
+        }
+    }
+}
+
+/// Check the given project for references to things that do not exist and for conflicting
+/// task settings.
+///
+/// * `project`: Project to validate.
+pub fn validate(project: &EvgProject) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    check_build_variants(project, &mut diagnostics);
+    check_function_calls(project, &mut diagnostics);
+    check_dependencies(project, &mut diagnostics);
+    check_task_groups(project, &mut diagnostics);
+    check_tasks(project, &mut diagnostics);
+    diagnostics
+}
+
+fn diagnostic(path: String, kind: DiagnosticKind) -> Diagnostic {
+    Diagnostic { path, kind }
+}
+
+fn check_build_variants(project: &EvgProject, diagnostics: &mut Vec<Diagnostic>) {
+    let task_group_map = project.task_group_map();
+    let modules: HashSet<&str> = project
+        .modules
+        .iter()
+        .flatten()
+        .map(|m| m.name.as_str())
+        .collect();
+
+    for (bv_index, entry) in project.buildvariants.iter().enumerate() {
+        let bv = match entry.build_variant() {
+            Some(bv) => bv,
+            None => continue,
+        };
+        let mut variant_tasks: HashSet<&str> = HashSet::new();
+        for (index, task_ref) in bv.tasks.iter().enumerate() {
+            let name = task_ref.name.as_str();
+            let path = format!("buildvariants[{}].tasks[{}]", bv_index, index);
+            match Selector::parse(name).and_then(|s| s.select_task_units(project)) {
+                Ok(names) => {
+                    for name in names {
+                        match task_group_map.get(name) {
+                            Some(task_group) => {
+                                variant_tasks.extend(task_group.tasks.iter().map(|t| t.as_str()))
+                            }
+                            None => {
+                                variant_tasks.insert(name);
+                            }
+                        }
+                    }
+                }
+                Err(SelectorError::NoMatches { .. }) => diagnostics.push(diagnostic(
+                    path,
+                    DiagnosticKind::UndefinedTaskRef(name.to_string()),
+                )),
+                Err(err) => diagnostics.push(diagnostic(
+                    path,
+                    DiagnosticKind::InvalidSelector(err.to_string()),
+                )),
+            }
+        }
+
+        for (dt_index, display_task) in bv.display_tasks.iter().flatten().enumerate() {
+            for (index, task) in display_task.execution_tasks.iter().enumerate() {
+                if !variant_tasks.contains(task.as_str()) {
+                    diagnostics.push(diagnostic(
+                        format!(
+                            "buildvariants[{}].display_tasks[{}].execution_tasks[{}]",
+                            bv_index, dt_index, index
+                        ),
+                        DiagnosticKind::UndefinedExecutionTask(task.to_string()),
+                    ));
+                }
+            }
+        }
+
+        for (index, module) in bv.modules.iter().flatten().enumerate() {
+            if !modules.contains(module.as_str()) {
+                diagnostics.push(diagnostic(
+                    format!("buildvariants[{}].modules[{}]", bv_index, index),
+                    DiagnosticKind::UndeclaredModule(module.to_string()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_command_list(
+    project: &EvgProject,
+    path: &str,
+    commands: &[EvgCommand],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (index, command) in commands.iter().enumerate() {
+        if let EvgCommand::Function(function_call) = command {
+            if !project.functions.contains_key(&function_call.func) {
+                diagnostics.push(diagnostic(
+                    format!("{}[{}]", path, index),
+                    DiagnosticKind::UndefinedFunction(function_call.func.to_string()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_function_calls(project: &EvgProject, diagnostics: &mut Vec<Diagnostic>) {
+    let blocks = [
+        ("pre", &project.pre),
+        ("post", &project.post),
+        ("timeout", &project.timeout),
+    ];
+    for (name, commands) in blocks.iter() {
+        if let Some(commands) = commands {
+            check_command_list(project, name, commands, diagnostics);
+        }
+    }
+
+    let mut function_names: Vec<&String> = project.functions.keys().collect();
+    function_names.sort();
+    for name in function_names {
+        let path = format!("functions.{}", name);
+        let commands = project.functions[name].commands();
+        check_command_list(project, &path, commands, diagnostics);
+    }
+
+    for (index, task) in project.tasks.iter().enumerate() {
+        if let Some(commands) = &task.commands {
+            let path = format!("tasks[{}].commands", index);
+            check_command_list(project, &path, commands, diagnostics);
+        }
+    }
+
+    for (tg_index, task_group) in project.task_groups.iter().flatten().enumerate() {
+        let blocks = [
+            ("setup_group", &task_group.setup_group),
+            ("teardown_group", &task_group.teardown_group),
+            ("setup_task", &task_group.setup_task),
+            ("teardown_task", &task_group.teardown_task),
+            ("timeout", &task_group.timeout),
+        ];
+        for (name, commands) in blocks.iter() {
+            if let Some(commands) = commands {
+                let path = format!("task_groups[{}].{}", tg_index, name);
+                check_command_list(project, &path, commands, diagnostics);
+            }
+        }
+    }
+}
+
+fn check_dependencies(project: &EvgProject, diagnostics: &mut Vec<Diagnostic>) {
+    // Dependencies can name build variants generated by matrices. Matrices that can't be
+    // expanded are reported when they are expanded, not here.
+    let axes = project.axes.as_deref().unwrap_or_default();
+    let mut build_variant_names: HashSet<String> = project
+        .build_variants()
+        .map(|bv| bv.name.to_string())
+        .collect();
+    for matrix in project.matrices() {
+        if let Ok(build_variants) = expand_matrix(matrix, axes) {
+            build_variant_names.extend(build_variants.into_iter().map(|bv| bv.name));
+        }
+    }
+
+    for (task_index, task) in project.tasks.iter().enumerate() {
+        for (index, dependency) in task.depends_on.iter().flatten().enumerate() {
+            let path = format!("tasks[{}].depends_on[{}]", task_index, index);
+            let name = dependency.name.as_str();
+            match Selector::parse(name).and_then(|s| s.select_tasks(project)) {
+                Ok(_) => (),
+                Err(SelectorError::NoMatches { .. }) => diagnostics.push(diagnostic(
+                    path.clone(),
+                    DiagnosticKind::UndefinedDependency(name.to_string()),
+                )),
+                Err(err) => diagnostics.push(diagnostic(
+                    path.clone(),
+                    DiagnosticKind::InvalidSelector(err.to_string()),
+                )),
+            }
+
+            if let Some(variant) = &dependency.variant {
+                if variant != "*" && !build_variant_names.contains(variant) {
+                    diagnostics.push(diagnostic(
+                        path,
+                        DiagnosticKind::UndefinedDependencyVariant(variant.to_string()),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_task_groups(project: &EvgProject, diagnostics: &mut Vec<Diagnostic>) {
+    let task_map = project.task_def_map();
+
+    for (tg_index, task_group) in project.task_groups.iter().flatten().enumerate() {
+        for (index, task) in task_group.tasks.iter().enumerate() {
+            if !task_map.contains_key(task) {
+                diagnostics.push(diagnostic(
+                    format!("task_groups[{}].tasks[{}]", tg_index, index),
+                    DiagnosticKind::UndefinedTaskGroupTask(task.to_string()),
+                ));
+            }
+        }
+    }
+}
+
+fn check_tasks(project: &EvgProject, diagnostics: &mut Vec<Diagnostic>) {
+    for (index, task) in project.tasks.iter().enumerate() {
+        let mut conflicts = vec![];
+        if task.batchtime.is_some() && task.cron.is_some() {
+            conflicts.push(("batchtime", "cron"));
+        }
+        if task.allowed_requesters.is_some() {
+            if task.patchable.is_some() {
+                conflicts.push(("allowed_requesters", "patchable"));
+            }
+            if task.patch_only.is_some() {
+                conflicts.push(("allowed_requesters", "patch_only"));
+            }
+        }
+        if task.patchable == Some(false) && task.patch_only == Some(true) {
+            conflicts.push(("patchable: false", "patch_only: true"));
+        }
+
+        for (first, second) in conflicts {
+            diagnostics.push(diagnostic(
+                format!("tasks[{}]", index),
+                DiagnosticKind::ConflictingFields(first.to_string(), second.to_string()),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(project: &EvgProject) -> Vec<(String, DiagnosticKind)> {
+        validate(project)
+            .into_iter()
+            .map(|d| (d.path, d.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_consistent_project_has_no_diagnostics() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+modules:
+  - name: enterprise
+    repo: git@github.com:10gen/enterprise.git
+    branch: master
+    prefix: src/modules
+functions:
+  setup:
+    command: shell.exec
+    params:
+      script: echo hello
+tasks:
+  - name: compile
+    tags: ["build"]
+    commands:
+      - func: setup
+  - name: test
+    depends_on:
+      - name: compile
+        variant: bv_0
+    commands:
+      - func: setup
+task_groups:
+  - name: test_group
+    tasks: ["test"]
+buildvariants:
+  - name: bv_0
+    modules: ["enterprise"]
+    tasks:
+      - name: .build
+      - name: test_group
+    display_tasks:
+      - name: everything
+        execution_tasks: ["compile", "test"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(validate(&project), vec![]);
+    }
+
+    #[test]
+    fn test_dangling_references_are_reported() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions:
+  setup:
+    - func: missing_from_function
+pre:
+  - func: missing_from_pre
+tasks:
+  - name: compile
+    depends_on:
+      - name: missing_task
+      - name: compile
+        variant: missing_variant
+    commands:
+      - func: setup
+      - func: missing_from_task
+task_groups:
+  - name: group
+    tasks: ["compile", "missing_group_task"]
+    setup_group:
+      - func: missing_from_group
+buildvariants:
+  - name: bv_0
+    modules: ["missing_module"]
+    tasks:
+      - name: compile
+      - name: missing_ref
+      - name: .missing_tag
+    display_tasks:
+      - name: display
+        execution_tasks: ["compile", "group"]
+"#,
+        )
+        .unwrap();
+
+        use DiagnosticKind::*;
+        assert_eq!(
+            kinds(&project),
+            vec![
+                (
+                    "buildvariants[0].tasks[1]".to_string(),
+                    UndefinedTaskRef("missing_ref".to_string())
+                ),
+                (
+                    "buildvariants[0].tasks[2]".to_string(),
+                    UndefinedTaskRef(".missing_tag".to_string())
+                ),
+                (
+                    "buildvariants[0].display_tasks[0].execution_tasks[1]".to_string(),
+                    UndefinedExecutionTask("group".to_string())
+                ),
+                (
+                    "buildvariants[0].modules[0]".to_string(),
+                    UndeclaredModule("missing_module".to_string())
+                ),
+                (
+                    "pre[0]".to_string(),
+                    UndefinedFunction("missing_from_pre".to_string())
+                ),
+                (
+                    "functions.setup[0]".to_string(),
+                    UndefinedFunction("missing_from_function".to_string())
+                ),
+                (
+                    "tasks[0].commands[1]".to_string(),
+                    UndefinedFunction("missing_from_task".to_string())
+                ),
+                (
+                    "task_groups[0].setup_group[0]".to_string(),
+                    UndefinedFunction("missing_from_group".to_string())
+                ),
+                (
+                    "tasks[0].depends_on[0]".to_string(),
+                    UndefinedDependency("missing_task".to_string())
+                ),
+                (
+                    "tasks[0].depends_on[1]".to_string(),
+                    UndefinedDependencyVariant("missing_variant".to_string())
+                ),
+                (
+                    "task_groups[0].tasks[1]".to_string(),
+                    UndefinedTaskGroupTask("missing_group_task".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selectors_are_checked() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+tasks:
+  - name: compile
+    tags: ["build"]
+  - name: test
+    tags: ["tests"]
+    depends_on:
+      - name: .build !compile
+      - name: .build !.
+buildvariants:
+  - name: linux
+    tasks:
+      - name: .build !.tests
+      - name: .tests !test
+"#,
+        )
+        .unwrap();
+
+        use DiagnosticKind::*;
+        assert_eq!(
+            kinds(&project),
+            vec![
+                (
+                    "buildvariants[0].tasks[1]".to_string(),
+                    UndefinedTaskRef(".tests !test".to_string())
+                ),
+                (
+                    "tasks[1].depends_on[0]".to_string(),
+                    UndefinedDependency(".build !compile".to_string())
+                ),
+                (
+                    "tasks[1].depends_on[1]".to_string(),
+                    InvalidSelector(
+                        "Invalid criterion '!.' in selector '.build !.': missing name".to_string()
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependencies_can_name_matrix_variants() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions: {}
+axes:
+  - id: os
+    values:
+      - id: linux
+tasks:
+  - name: compile
+  - name: test
+    depends_on:
+      - name: compile
+        variant: build__os~linux
+      - name: compile
+        variant: build__os~macos
+buildvariants:
+  - matrix_name: build
+    matrix_spec:
+      os: "*"
+    tasks:
+      - name: compile
+  - name: tests
+    tasks:
+      - name: test
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            kinds(&project),
+            vec![(
+                "tasks[1].depends_on[1]".to_string(),
+                DiagnosticKind::UndefinedDependencyVariant("build__os~macos".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_task_fields_are_reported() {
+        let project = EvgProject::from_yaml_str(
+            r#"
+functions: {}
+tasks:
+  - name: nightly
+    batchtime: 1440
+    cron: "0 0 * * *"
+  - name: patches
+    patch_only: true
+    patchable: false
+  - name: requesters
+    allowed_requesters: ["patch", "github_pr"]
+    patchable: true
+  - name: fine
+    batchtime: 60
+    patch_only: true
+buildvariants: []
+"#,
+        )
+        .unwrap();
+
+        use DiagnosticKind::*;
+        let conflict = |a: &str, b: &str| ConflictingFields(a.to_string(), b.to_string());
+        assert_eq!(
+            kinds(&project),
+            vec![
+                ("tasks[0]".to_string(), conflict("batchtime", "cron")),
+                (
+                    "tasks[1]".to_string(),
+                    conflict("patchable: false", "patch_only: true")
+                ),
+                (
+                    "tasks[2]".to_string(),
+                    conflict("allowed_requesters", "patchable")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_are_displayed_with_their_path() {
+        let diagnostic = Diagnostic {
+            path: "buildvariants[0].modules[0]".to_string(),
+            kind: DiagnosticKind::UndeclaredModule("enterprise".to_string()),
+        };
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "buildvariants[0].modules[0]: module 'enterprise' is not declared"
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Helpers for working with the raw YAML representation of a project configuration.
+use crate::error::SourceLocation;
+use serde::de::{DeserializeOwned, Error};
+use std::collections::HashMap;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::Yaml;
+
+/// Convert a parsed YAML document into a value that can be deserialized with serde.
+///
+/// This lets us deserialize documents that need preprocessing with `yaml_rust` (like resolving
+/// merge keys) without having to emit them to a string and parse them again.
+pub(crate) fn into_value(yaml: Yaml) -> serde_yaml::Value {
+    match yaml {
+        Yaml::Real(_) => match yaml.as_f64() {
+            Some(f) => serde_yaml::Value::Number(f.into()),
+            None => serde_yaml::Value::Null,
+        },
+        Yaml::Integer(i) => serde_yaml::Value::Number(i.into()),
+        Yaml::String(s) => serde_yaml::Value::String(s),
+        Yaml::Boolean(b) => serde_yaml::Value::Bool(b),
+        Yaml::Array(array) => {
+            serde_yaml::Value::Sequence(array.into_iter().map(into_value).collect())
+        }
+        Yaml::Hash(hash) => {
+            let mut mapping = serde_yaml::Mapping::with_capacity(hash.len());
+            for (key, value) in hash {
+                mapping.insert(into_value(key), into_value(value));
+            }
+            serde_yaml::Value::Mapping(mapping)
+        }
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => serde_yaml::Value::Null,
+    }
+}
+
+/// Deserialize a buffered YAML value, keeping the path to any error in the error message.
+///
+/// This is used when a value needs to be inspected before deciding how to deserialize it,
+/// which would otherwise lose the location of errors nested inside of it. The path is added
+/// to the end of the message (see [`split_nested_path`]) so that callers further up can join
+/// it to their own path.
+pub(crate) fn from_value<T, E>(value: serde_yaml::Value) -> Result<T, E>
+where
+    T: DeserializeOwned,
+    E: Error,
+{
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let path = err.path().to_string();
+        let inner = err.into_inner().to_string();
+        if path == "." {
+            return E::custom(inner);
+        }
+        let (message, nested) = split_nested_path(&inner);
+        let path = match nested {
+            Some(nested) => join_paths(&path, nested),
+            None => path,
+        };
+        E::custom(format!("{}{}{})", message, NESTED_PATH_PREFIX, path))
+    })
+}
+
+const NESTED_PATH_PREFIX: &str = " (at ";
+
+/// Split an error message created by [`from_value`] into the message and the path to the node
+/// it describes, relative to the buffered value.
+pub(crate) fn split_nested_path(message: &str) -> (&str, Option<&str>) {
+    match message.rfind(NESTED_PATH_PREFIX) {
+        Some(idx) if message.ends_with(')') => (
+            &message[..idx],
+            Some(&message[idx + NESTED_PATH_PREFIX.len()..message.len() - 1]),
+        ),
+        _ => (message, None),
+    }
+}
+
+/// Join a path relative to the node at `parent` onto `parent`.
+pub(crate) fn join_paths(parent: &str, child: &str) -> String {
+    if parent == "." {
+        child.to_string()
+    } else if child.starts_with('[') {
+        format!("{}{}", parent, child)
+    } else {
+        format!("{}.{}", parent, child)
+    }
+}
+
+/// Find where the node at the given path starts in the YAML input.
+///
+/// The path uses the same format as `serde_path_to_error`, e.g. `tasks[3].commands`. If
+/// the node cannot be found (for example, because it was added by a merge key), the location
+/// of the closest ancestor that can be found is used instead.
+pub(crate) fn locate(yaml_contents: &str, path: &str) -> Option<SourceLocation> {
+    let mut segments = vec![];
+    let mut candidates = vec![render_path(&segments)];
+    for segment in parse_path(path) {
+        segments.push(segment);
+        candidates.push(render_path(&segments));
+    }
+
+    let mut locator = Locator {
+        stack: vec![],
+        segments: vec![],
+        pending: None,
+        found: candidates.iter().map(|c| (c.clone(), None)).collect(),
+    };
+    let mut parser = Parser::new(yaml_contents.chars());
+    parser.load(&mut locator, false).ok()?;
+
+    candidates
+        .iter()
+        .rev()
+        .find_map(|c| locator.found.get(c).copied().flatten())
+        .map(|marker| SourceLocation {
+            line: marker.line(),
+            column: marker.col() + 1,
+        })
+}
+
+#[derive(Debug, Clone)]
+enum PathSegment {
+    Index(usize),
+    Key(String),
+}
+
+/// Split a rendered path back into its segments.
+fn parse_path(path: &str) -> Vec<PathSegment> {
+    let mut segments = vec![];
+    if path == "." {
+        return segments;
+    }
+    for part in path.split('.') {
+        let (key, indices) = match part.find('[') {
+            Some(idx) => part.split_at(idx),
+            None => (part, ""),
+        };
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.to_string()));
+        }
+        for index in indices.split(['[', ']']) {
+            if let Ok(index) = index.parse() {
+                segments.push(PathSegment::Index(index));
+            }
+        }
+    }
+    segments
+}
+
+fn render_path(segments: &[PathSegment]) -> String {
+    if segments.is_empty() {
+        return ".".to_string();
+    }
+
+    let mut rendered = String::new();
+    for segment in segments {
+        match segment {
+            PathSegment::Index(index) => rendered.push_str(&format!("[{}]", index)),
+            PathSegment::Key(key) => {
+                if !rendered.is_empty() {
+                    rendered.push('.');
+                }
+                rendered.push_str(key);
+            }
+        }
+    }
+    rendered
+}
+
+/// Current position inside a YAML collection.
+enum Frame {
+    /// Inside a sequence, at the given index.
+    Sequence(usize),
+    /// Inside a mapping, waiting for a key (`None`) or the value of the given key.
+    Mapping(Option<String>),
+    /// Inside a collection being used as a mapping key.
+    Key,
+}
+
+/// Event receiver that records where the nodes at a set of paths start.
+struct Locator {
+    stack: Vec<Frame>,
+    segments: Vec<PathSegment>,
+    /// Path of a block mapping whose location should be taken from its first key.
+    pending: Option<String>,
+    found: HashMap<String, Option<Marker>>,
+}
+
+impl Locator {
+    /// Handle the start of a node, returning true if the node is a mapping key.
+    fn start_node(&mut self, mark: Marker) -> bool {
+        self.start_node_at(mark).0
+    }
+
+    /// Like `start_node`, but also return the path of the node if it is being searched for.
+    fn start_node_at(&mut self, mark: Marker) -> (bool, Option<String>) {
+        let segment = match self.stack.last() {
+            None => None,
+            Some(Frame::Sequence(index)) => Some(PathSegment::Index(*index)),
+            Some(Frame::Mapping(Some(key))) => Some(PathSegment::Key(key.clone())),
+            Some(Frame::Mapping(None)) | Some(Frame::Key) => return (true, None),
+        };
+        if let Some(segment) = segment {
+            self.segments.push(segment);
+        }
+
+        let path = render_path(&self.segments);
+        match self.found.get_mut(&path) {
+            Some(slot) if slot.is_none() => {
+                *slot = Some(mark);
+                (false, Some(path))
+            }
+            _ => (false, None),
+        }
+    }
+
+    /// Handle the end of a node that is not a mapping key.
+    fn end_node(&mut self) {
+        match self.stack.last_mut() {
+            None => (),
+            Some(Frame::Sequence(index)) => {
+                *index += 1;
+                self.segments.pop();
+            }
+            Some(Frame::Mapping(key)) => {
+                *key = None;
+                self.segments.pop();
+            }
+            Some(Frame::Key) => (),
+        }
+    }
+
+    /// Handle the end of a node being used as a mapping key.
+    fn end_key(&mut self, key: String) {
+        if let Some(Frame::Mapping(current)) = self.stack.last_mut() {
+            *current = Some(key);
+        }
+    }
+
+    fn start_collection(&mut self, frame: Frame, mark: Marker) {
+        let (is_key, path) = self.start_node_at(mark);
+        if is_key {
+            self.stack.push(Frame::Key);
+        } else {
+            // The parser reports block mappings as starting after their first key, so use
+            // the location of the first key instead.
+            if let Frame::Mapping(_) = frame {
+                self.pending = path;
+            }
+            self.stack.push(frame);
+        }
+    }
+
+    fn end_collection(&mut self) {
+        if let Some(Frame::Key) = self.stack.pop() {
+            if !matches!(self.stack.last(), Some(Frame::Key)) {
+                self.end_key("?".to_string());
+            }
+        } else {
+            self.end_node();
+        }
+    }
+}
+
+impl MarkedEventReceiver for Locator {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        if let Some(path) = self.pending.take() {
+            if ev != Event::MappingEnd {
+                self.found.insert(path, Some(mark));
+            }
+        }
+
+        match ev {
+            Event::Scalar(value, ..) => {
+                if self.start_node(mark) {
+                    if !matches!(self.stack.last(), Some(Frame::Key)) {
+                        self.end_key(value);
+                    }
+                } else {
+                    self.end_node();
+                }
+            }
+            Event::Alias(_) => {
+                if self.start_node(mark) {
+                    if !matches!(self.stack.last(), Some(Frame::Key)) {
+                        self.end_key("?".to_string());
+                    }
+                } else {
+                    self.end_node();
+                }
+            }
+            Event::SequenceStart(_) => self.start_collection(Frame::Sequence(0), mark),
+            Event::MappingStart(_) => self.start_collection(Frame::Mapping(None), mark),
+            Event::SequenceEnd | Event::MappingEnd => self.end_collection(),
+            _ => (),
+        }
+    }
+}
+
+/// Deserializers for string fields that also accept other scalars.
+///
+/// YAML authors often leave values like `8`, `5.0` or `true` unquoted where Evergreen expects a
+/// string. Those are read as numbers and booleans, so fields that hold names, expansions or
+/// other text use these to convert them back to strings. Use them with
+/// `#[serde(default, deserialize_with = "...")]` on optional fields.
+pub(crate) mod lenient {
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+    use std::fmt;
+
+    /// A string written as any YAML scalar.
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    struct ScalarString(String);
+
+    impl<'de> Deserialize<'de> for ScalarString {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct ScalarVisitor;
+
+            impl Visitor<'_> for ScalarVisitor {
+                type Value = ScalarString;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("a string")
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                    Ok(ScalarString(value.to_string()))
+                }
+
+                fn visit_string<E: de::Error>(self, value: String) -> Result<Self::Value, E> {
+                    Ok(ScalarString(value))
+                }
+
+                fn visit_bool<E: de::Error>(self, value: bool) -> Result<Self::Value, E> {
+                    Ok(ScalarString(value.to_string()))
+                }
+
+                fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                    Ok(ScalarString(value.to_string()))
+                }
+
+                fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                    Ok(ScalarString(value.to_string()))
+                }
+
+                fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                    // Debug formatting keeps the decimal point of whole numbers, e.g. `5.0`.
+                    Ok(ScalarString(format!("{:?}", value)))
+                }
+            }
+
+            deserializer.deserialize_any(ScalarVisitor)
+        }
+    }
+
+    pub(crate) fn string<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        ScalarString::deserialize(deserializer).map(|s| s.0)
+    }
+
+    pub(crate) fn option_string<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<ScalarString>::deserialize(deserializer).map(|s| s.map(|s| s.0))
+    }
+
+    pub(crate) fn strings<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<ScalarString>::deserialize(deserializer)
+            .map(|strings| strings.into_iter().map(|s| s.0).collect())
+    }
+
+    pub(crate) fn option_strings<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<ScalarString>>::deserialize(deserializer)
+            .map(|strings| strings.map(|s| s.into_iter().map(|s| s.0).collect()))
+    }
+
+    pub(crate) fn option_string_map<'de, D>(
+        deserializer: D,
+    ) -> Result<Option<BTreeMap<String, String>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<BTreeMap<ScalarString, ScalarString>>::deserialize(deserializer)
+            .map(|map| map.map(|map| map.into_iter().map(|(k, v)| (k.0, v.0)).collect()))
+    }
+}